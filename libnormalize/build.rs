@@ -0,0 +1,53 @@
+/// Build script: serializes the built-in presets ahead of time.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory the preset TOML files are read from, relative to the crate root.
+const PRESET_DIR: &str = "src/presets";
+
+/// Parse each preset in [`PRESET_DIR`] and write it to `$OUT_DIR/presets/<name>.json` as compact
+/// JSON, which `src/presets.rs` embeds with `include_bytes!`. Deserializing that is much cheaper
+/// than parsing the TOML at runtime, and a preset with a syntax error fails the build instead of
+/// the first program that uses it.
+fn main() {
+    let out: PathBuf = Path::new(&env::var("OUT_DIR").expect("OUT_DIR is set by cargo"))
+        .join("presets");
+    fs::create_dir_all(&out).expect("can't create the preset output directory");
+    println!("cargo:rerun-if-changed={PRESET_DIR}");
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(PRESET_DIR)
+        .expect("can't read the preset directory")
+        .map(|entry| entry.expect("can't read the preset directory").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source: String = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("can't read {}: {e}", path.display()));
+        let value: toml::Value = toml::from_str(&source)
+            .unwrap_or_else(|e| panic!("{} is not valid TOML: {e}", path.display()));
+        let data: Vec<u8> = serde_json::to_vec(&value)
+            .unwrap_or_else(|e| panic!("can't serialize {}: {e}", path.display()));
+        let name: &Path = Path::new(path.file_stem().expect("preset files have a name"));
+        fs::write(out.join(name).with_extension("json"), data)
+            .unwrap_or_else(|e| panic!("can't write the serialized {}: {e}", path.display()));
+    }
+}
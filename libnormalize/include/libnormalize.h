@@ -0,0 +1,195 @@
+/*
+ * C interface to libnormalize.
+ *     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+ *
+ *     This program is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     This program is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License
+ *     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#ifndef LIBNORMALIZE_H
+#define LIBNORMALIZE_H
+
+/* Generated from src/ffi.rs by cbindgen; don't edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * A class of suspicious characters that check and scan modes report.
+ */
+typedef enum NormalizeClass {
+  /**
+   * Bidirectional control characters, which can reorder how text is displayed without
+   * changing how it is read by a compiler (the "Trojan Source" attack).
+   */
+  NORMALIZE_CLASS_BIDI_CONTROLS,
+  /**
+   * Characters that don't render at all, like zero-width spaces and joiners.
+   */
+  NORMALIZE_CLASS_INVISIBLE,
+  /**
+   * Non-ASCII characters that the chain translates into something else, i.e. lookalikes of the
+   * characters they are normalized to.
+   */
+  NORMALIZE_CLASS_CONFUSABLES,
+  /**
+   * Letters from a different script than the rest of the word they are in, like a Cyrillic
+   * "а" in an otherwise Latin word. Han, Hiragana, Katakana, Hangul and Bopomofo count as one
+   * script, since they are written together.
+   */
+  NORMALIZE_CLASS_MIXED_SCRIPTS,
+  /**
+   * Characters on the chain's deny list, which normalizing refuses to translate. Bidi controls
+   * and invisible characters on the list are still reported in their own classes.
+   */
+  NORMALIZE_CLASS_DENIED,
+} NormalizeClass;
+
+/**
+ * How seriously a class of findings is taken. Severities are ordered, so the worst finding in a
+ * report is the maximum of their severities.
+ */
+typedef enum NormalizeSeverity {
+  /**
+   * The class isn't reported at all.
+   */
+  NORMALIZE_SEVERITY_OFF,
+  /**
+   * Reported, but never affects the exit code.
+   */
+  NORMALIZE_SEVERITY_INFO,
+  /**
+   * Reported as a warning.
+   */
+  NORMALIZE_SEVERITY_WARN,
+  /**
+   * Reported as an error, and makes check and scan modes fail.
+   */
+  NORMALIZE_SEVERITY_ERROR,
+} NormalizeSeverity;
+
+/**
+ * A Chain is an ordered list of rules that is applied to each character of the input. The rules
+ * are tried in order, and the first one that handles a character decides its replacement; if no
+ * rule handles it, the character is passed through unchanged if it is ASCII, and handled by the
+ * chain's [`Fallback`] policy otherwise.
+ *
+ * This is the same evaluation order as `translate()` uses for a slice of translators, but a chain
+ * can also contain one-to-many rules ([`Expander`]s), so it translates into a `String` rather
+ * than returning a single `char`.
+ *
+ * A chain also carries an allow list of characters that are always passed through untouched,
+ * and a deny list of characters that make the input invalid (see [`Chain::try_translate_str`]).
+ * Both are lists of inclusive ranges.
+ */
+typedef struct NormalizeChain NormalizeChain;
+
+/**
+ * A suspicious character found by [`normalize_detect`].
+ */
+typedef struct NormalizeFinding {
+  /**
+   * The class of suspicious character it is.
+   */
+  NormalizeClass kind;
+  NormalizeSeverity severity;
+  /**
+   * The character's codepoint.
+   */
+  uint32_t codepoint;
+  /**
+   * The byte offset of the character in the text.
+   */
+  size_t offset;
+} NormalizeFinding;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The message for the last error on the calling thread, or NULL if there hasn't been one. The
+ * message belongs to the library, and stays valid until the next error on the same thread.
+ */
+const char *normalize_last_error(void);
+
+/**
+ * Build a chain from built-in presets: a comma-separated list of preset names like
+ * "math,cyrillic", or "all" for every one. NULL or an empty list selects the default preset.
+ * Returns NULL if a preset is unknown.
+ *
+ * # Safety
+ * `names` must be NULL or point to a NUL-terminated string.
+ */
+NormalizeChain *normalize_chain_from_presets(const char *names);
+
+/**
+ * Build a chain from a config, written in `format`: "toml", "json" or "yaml" (NULL means TOML).
+ * Includes are resolved relative to the current working directory. Returns NULL if the config is
+ * invalid.
+ *
+ * # Safety
+ * `config` must point to a NUL-terminated string, and `format` must be NULL or point to one.
+ */
+NormalizeChain *normalize_chain_from_config(const char *config, const char *format);
+
+/**
+ * Free a chain. Does nothing if `chain` is NULL.
+ *
+ * # Safety
+ * `chain` must be NULL or a chain from this library that hasn't been freed, and that no other
+ * thread is still using.
+ */
+void normalize_chain_free(NormalizeChain *chain);
+
+/**
+ * Normalize some text. Returns a new string, to be freed with [`normalize_string_free`], or NULL
+ * if an argument is NULL, the chain rejects a character in the text (one on its deny list, or
+ * one that no rule handles when its fallback is "error"), or the translation has a NUL character
+ * in it.
+ *
+ * # Safety
+ * `chain` must be a chain from this library that hasn't been freed, and `text` must point to a
+ * NUL-terminated string.
+ */
+char *normalize_str(const NormalizeChain *chain, const char *text);
+
+/**
+ * Free a string returned by the library. Does nothing if `s` is NULL.
+ *
+ * # Safety
+ * `s` must be NULL or a string returned by [`normalize_str`] that hasn't been freed.
+ */
+void normalize_string_free(char *s);
+
+/**
+ * Find the suspicious characters in some text, with the severities the chain gives their
+ * classes, in order (see [`detect::detect`]). Up to `capacity` of them are written to `findings`,
+ * and the number found is returned, so calling it with a `capacity` of 0 counts them. Returns -1
+ * if an argument is NULL.
+ *
+ * # Safety
+ * `chain` must be a chain from this library that hasn't been freed, `text` must point to a
+ * NUL-terminated string, and `findings` must have room for `capacity` findings (it may be NULL
+ * if `capacity` is 0).
+ */
+ptrdiff_t normalize_detect(const NormalizeChain *chain,
+                           const char *text,
+                           NormalizeFinding *findings,
+                           size_t capacity);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LIBNORMALIZE_H */
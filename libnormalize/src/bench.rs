@@ -0,0 +1,180 @@
+/// Measuring how fast a chain translates text, overall and translator by translator.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Reverse;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use crate::chain::{Chain, Rule};
+use crate::lint;
+
+/// How long translating some text took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throughput {
+    /// The size of the text, in bytes.
+    pub bytes: usize,
+    /// The number of characters in the text.
+    pub chars: usize,
+    /// The fastest time the whole text was translated in.
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Megabytes (millions of bytes) translated per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bytes as f64 / 1e6 / self.seconds()
+    }
+
+    /// Characters translated per second.
+    pub fn chars_per_sec(&self) -> f64 {
+        self.chars as f64 / self.seconds()
+    }
+
+    /// The elapsed time in seconds, never zero (so rates stay finite for tiny inputs).
+    fn seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// Translate every text with [`Chain::translate_str`], `iterations` times over (at least once),
+/// and return the fastest round. Taking the fastest round leaves out most of the noise from
+/// other processes and cold caches.
+pub fn throughput<S: AsRef<str>>(chain: &Chain, texts: &[S], iterations: usize) -> Throughput {
+    let bytes: usize = texts.iter().map(|text| text.as_ref().len()).sum();
+    let chars: usize = texts.iter().map(|text| text.as_ref().chars().count()).sum();
+    let mut fastest: Duration = Duration::MAX;
+    for _ in 0..iterations.max(1) {
+        let start: Instant = Instant::now();
+        for text in texts {
+            black_box(chain.translate_str(black_box(text.as_ref())));
+        }
+        fastest = fastest.min(start.elapsed());
+    }
+    Throughput { bytes, chars, elapsed: fastest }
+}
+
+/// The work one group of rules did (usually one translator; see [`Chain::begin_group`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// The group's name. Rules before the first group are "unnamed", and characters that no rule
+    /// handles are counted under "fallback".
+    pub name: String,
+    /// How many characters got as far as the group.
+    pub reached: usize,
+    /// How many of those the group handled.
+    pub handled: usize,
+    /// How long the group took to try every character that reached it.
+    pub elapsed: Duration,
+}
+
+/// Break down the time spent in the chain's rules, group by group. Each group is timed on just
+/// the characters that reach it when the texts are translated: those (after `pre_normalize`)
+/// that aren't protected, aren't in the allow list and weren't handled by an earlier group. The
+/// last entry is the fallback, which takes no time to speak of.
+pub fn breakdown<S: AsRef<str>>(chain: &Chain, texts: &[S]) -> Vec<Group> {
+    let mut remaining: Vec<char> = reaching(chain, texts);
+    let mut groups: Vec<Group> = Vec::new();
+    let mut out: String = String::new();
+    for (_, name, rules) in groups_of(chain) {
+        let reached: usize = remaining.len();
+        let start: Instant = Instant::now();
+        for &c in &remaining {
+            out.clear();
+            black_box(rules.iter().any(|rule| rule.apply_into(c, &mut out)));
+        }
+        let elapsed: Duration = start.elapsed();
+        remaining.retain(|&c| {
+            out.clear();
+            !rules.iter().any(|rule| rule.apply_into(c, &mut out))
+        });
+        groups.push(Group { name, reached, handled: reached - remaining.len(), elapsed });
+    }
+    groups.push(Group { name: "fallback".to_string(), reached: remaining.len(),
+                        handled: remaining.len(), elapsed: Duration::ZERO });
+    groups
+}
+
+/// Reorder the groups of a chain (usually its config sections) so that the ones that handle the
+/// most characters of some texts come first, for chains whose rules are tried one after another.
+/// Returns every group with rules in its new place, along with how many characters it handled.
+///
+/// What the chain does is left unchanged: a group is never moved ahead of a group that shadows it
+/// (see [`crate::lint::overlaps`]), since that would change which mapping wins. Groups that
+/// handle equally many characters keep their order. Finding the overlaps runs every rule on every
+/// Unicode scalar value, so this is slow.
+pub fn hot_order<S: AsRef<str>>(chain: &Chain, texts: &[S]) -> Vec<(String, usize)> {
+    let shadowing: Vec<(usize, usize)> = lint::shadowing(chain);
+    let mut left: Vec<(usize, String, usize)> = hits(chain, texts);
+    let mut order: Vec<(String, usize)> = Vec::new();
+    while !left.is_empty() {
+        let free = |group: usize| !shadowing.iter()
+            .any(|&(winner, loser)| loser == group && left.iter().any(|&(g, _, _)| g == winner));
+        // the first group left is never shadowed by another one that's left, since groups are
+        // only shadowed by earlier ones
+        let next: usize = (0..left.len())
+            .filter(|&i| free(left[i].0))
+            .max_by_key(|&i| (left[i].2, Reverse(i)))
+            .unwrap_or(0);
+        let (_, name, hits): (usize, String, usize) = left.remove(next);
+        order.push((name, hits));
+    }
+    order
+}
+
+/// Count how many characters of some texts each group of rules handles, as the group's index
+/// (see [`groups_of`]), its name and its count, in chain order.
+fn hits<S: AsRef<str>>(chain: &Chain, texts: &[S]) -> Vec<(usize, String, usize)> {
+    let groups: Vec<(usize, String, &[Rule])> = groups_of(chain);
+    let mut counts: Vec<usize> = vec![0; groups.len()];
+    let mut out: String = String::new();
+    for c in reaching(chain, texts) {
+        let handled: Option<usize> = groups.iter().position(|(_, _, rules)| {
+            rules.iter().any(|rule| {
+                out.clear();
+                rule.apply_into(c, &mut out)
+            })
+        });
+        if let Some(i) = handled { counts[i] += 1; }
+    }
+    groups.into_iter().zip(counts).map(|((group, name, _), hits)| (group, name, hits)).collect()
+}
+
+/// The characters of some texts that reach the chain's rules: those (after `pre_normalize`)
+/// that aren't protected and aren't in the allow list.
+fn reaching<S: AsRef<str>>(chain: &Chain, texts: &[S]) -> Vec<char> {
+    let mut reaching: Vec<char> = Vec::new();
+    for text in texts {
+        for (_, protected, segment) in chain.segments(text.as_ref()) {
+            if protected { continue; }
+            reaching.extend(chain.pre_normalize.apply(segment).chars()
+                .filter(|&c| !chain.is_allowed(c)));
+        }
+    }
+    reaching
+}
+
+/// Split the chain's rules into their groups, in order, along with the groups' indexes and names.
+/// Rules before the first group are group 0, and the group starting at `chain.names[i]` is group
+/// `i + 1`. Groups without any rules are left out.
+fn groups_of(chain: &Chain) -> Vec<(usize, String, &[Rule])> {
+    let mut groups: Vec<(usize, String, &[Rule])> = Vec::new();
+    let first: usize = chain.names.first().map_or(chain.rules.len(), |&(start, _)| start);
+    if first > 0 { groups.push((0, "unnamed".to_string(), &chain.rules[..first])); }
+    for (i, (start, name)) in chain.names.iter().enumerate() {
+        let end: usize = chain.names.get(i + 1).map_or(chain.rules.len(), |&(end, _)| end);
+        if *start < end { groups.push((i + 1, name.clone(), &chain.rules[*start..end])); }
+    }
+    groups
+}
@@ -0,0 +1,455 @@
+/// On-disk cache of compiled translator chains.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use regex::Regex;
+use crate::chain::{Chain, ExitPolicy, Fallback, NormalForm, TestVector};
+use crate::detect::{Class, Severities};
+use crate::translators::{map_translation, Translator};
+
+/// Identifies a cache file, and the version of its layout. Bump the version whenever the layout
+/// (or the meaning of a compiled table) changes, so stale caches are ignored rather than misread.
+const MAGIC: &[u8; 4] = b"UNCC";
+const VERSION: u32 = 11;
+
+/// Identifies a precompiled table (see [`store_table`]), and the version of its layout.
+const TABLE_MAGIC: &[u8; 4] = b"UNCT";
+const TABLE_VERSION: u32 = 1;
+
+/// The directory compiled chains are cached in: `utf-normalize` inside the platform's cache
+/// directory (`$XDG_CACHE_HOME` or `~/.cache` on Linux, `~/Library/Caches` on macOS, and
+/// `%LOCALAPPDATA%` on Windows).
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("utf-normalize"))
+}
+
+/// The cache file for a chain, given everything that identifies how it was configured (such as
+/// the paths of the config files it was built from, and any environment overrides). The contents
+/// of the config files are not part of the key; they are checked when the cache is loaded.
+pub fn cache_path(dir: &Path, key: &[String]) -> PathBuf {
+    let mut hasher: DefaultHasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    key.hash(&mut hasher);
+    dir.join(format!("{:016x}.bin", hasher.finish()))
+}
+
+/// Describe the files in a directory for a cache key (see [`cache_path`]), as each file's path,
+/// modification time and length, in path order. Presets loaded from a data directory depend on
+/// which files are in it, not just on the contents of the ones that were loaded, so adding,
+/// removing or changing any file gives a different key. A directory that can't be read is
+/// described as such.
+pub fn dir_listing(dir: &Path) -> Vec<String> {
+    let entries: fs::ReadDir = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return vec![format!("{}: {}", dir.display(), e)],
+    };
+    let mut listing: Vec<String> = entries
+        .filter_map(|entry| Dependency::of(&entry.ok()?.path()).ok())
+        .map(|dep| format!("{}:{}:{}", dep.path, dep.mtime, dep.len))
+        .collect();
+    listing.sort();
+    listing
+}
+
+/// A file that a cached chain was built from, along with enough metadata to tell if it changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Dependency {
+    path: String,
+    mtime: u64,
+    len: u64,
+}
+
+impl Dependency {
+    pub(crate) fn of(path: &Path) -> io::Result<Dependency> {
+        let meta: fs::Metadata = fs::metadata(path)?;
+        let mtime: u64 = meta.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(Dependency { path: path.to_string_lossy().into_owned(), mtime, len: meta.len() })
+    }
+
+    pub(crate) fn is_fresh(&self) -> bool {
+        Dependency::of(Path::new(&self.path)).map(|now| now == *self).unwrap_or(false)
+    }
+}
+
+/// Compile a chain (see [`Chain::compile`]) and write it to a cache file, recording the config
+/// files it was built from so that the cache is invalidated when any of them change. Chains with
+/// sequence rules can't be compiled to a table, so nothing is written for them.
+pub fn store(path: &Path, chain: &Chain, files: &[PathBuf]) -> io::Result<()> {
+    if chain.has_sequences() { return Ok(()); }
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(MAGIC);
+    put_u32(&mut out, VERSION);
+
+    put_u32(&mut out, files.len() as u32);
+    for file in files {
+        let dep: Dependency = Dependency::of(file)?;
+        put_str(&mut out, &dep.path);
+        put_u64(&mut out, dep.mtime);
+        put_u64(&mut out, dep.len);
+    }
+
+    put_u32(&mut out, chain.passes_ascii() as u32);
+    put_ranges(&mut out, &chain.allow);
+    put_ranges(&mut out, &chain.deny);
+    put_str(&mut out, &chain.fallback.to_string());
+    put_str(&mut out, &chain.replacement);
+    put_str(&mut out, &chain.pre_normalize.to_string());
+    put_str(&mut out, &chain.post_normalize.to_string());
+    put_str(&mut out, &chain.exit_code.to_string());
+    put_u32(&mut out, chain.protect.len() as u32);
+    for pattern in &chain.protect {
+        put_str(&mut out, pattern.as_str());
+    }
+    put_u32(&mut out, chain.only.len() as u32);
+    for pattern in &chain.only {
+        put_str(&mut out, pattern.as_str());
+    }
+    for class in Class::ALL {
+        put_str(&mut out, &chain.severity.get(class).to_string());
+    }
+    put_u32(&mut out, chain.tests.len() as u32);
+    for vector in &chain.tests {
+        put_str(&mut out, &vector.input);
+        put_str(&mut out, &vector.expect);
+    }
+
+    // each entry records the group its rule belongs to, as an index into the list of names (or
+    // u32::MAX for rules without one)
+    put_u32(&mut out, chain.names.len() as u32);
+    for (_, name) in &chain.names {
+        put_str(&mut out, name);
+    }
+    let table: Vec<(char, String, usize)> = chain.compile_rules();
+    put_u32(&mut out, table.len() as u32);
+    for (c, replacement, rule) in &table {
+        put_u32(&mut out, *c as u32);
+        put_str(&mut out, replacement);
+        let group: Option<usize> = chain.names.partition_point(|&(start, _)| start <= *rule)
+            .checked_sub(1);
+        put_u32(&mut out, group.map_or(u32::MAX, |group| group as u32));
+    }
+
+    if let Some(dir) = path.parent() { fs::create_dir_all(dir)?; }
+    // write to a temporary file first, so a concurrent reader never sees half a cache
+    let tmp: PathBuf = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, out)?;
+    fs::rename(&tmp, path)
+}
+
+/// Load a chain from a cache file. Returns `None` if there is no cache, if it is corrupt or from
+/// a different version, or if any of the config files it was built from have changed since.
+pub fn load(path: &Path) -> Option<Chain> {
+    let data: Vec<u8> = fs::read(path).ok()?;
+    let mut reader: Reader = Reader { data: &data, pos: 0 };
+
+    if reader.take(4)? != MAGIC || reader.u32()? != VERSION { return None; }
+
+    for _ in 0..reader.u32()? {
+        let dep: Dependency = Dependency { path: reader.str()?, mtime: reader.u64()?,
+                                           len: reader.u64()? };
+        if !dep.is_fresh() { return None; }
+    }
+
+    let ascii_filter: bool = reader.u32()? != 0;
+    let allow: Vec<(char, char)> = reader.ranges()?;
+    let deny: Vec<(char, char)> = reader.ranges()?;
+    let fallback: Fallback = reader.str()?.parse().ok()?;
+    let replacement: String = reader.str()?;
+    let pre_normalize: NormalForm = reader.str()?.parse().ok()?;
+    let post_normalize: NormalForm = reader.str()?.parse().ok()?;
+    let exit_code: ExitPolicy = reader.str()?.parse().ok()?;
+    let mut protect: Vec<Regex> = Vec::new();
+    for _ in 0..reader.u32()? {
+        protect.push(Regex::new(&reader.str()?).ok()?);
+    }
+    let mut only: Vec<Regex> = Vec::new();
+    for _ in 0..reader.u32()? {
+        only.push(Regex::new(&reader.str()?).ok()?);
+    }
+    let mut severity: Severities = Severities::default();
+    for class in Class::ALL {
+        severity.set(class, reader.str()?.parse().ok()?);
+    }
+    let mut tests: Vec<TestVector> = Vec::new();
+    for _ in 0..reader.u32()? {
+        tests.push(TestVector { input: reader.str()?, expect: reader.str()? });
+    }
+
+    let mut names: Vec<String> = Vec::new();
+    for _ in 0..reader.u32()? {
+        names.push(reader.str()?);
+    }
+    // one table for the rules without a group, then one per group
+    let mut tables: Vec<Vec<(char, String)>> = vec![Vec::new(); names.len() + 1];
+    for _ in 0..reader.u32()? {
+        let entry: (char, String) = (reader.char()?, reader.str()?);
+        let group: usize = match reader.u32()? {
+            u32::MAX => 0,
+            group => group as usize + 1,
+        };
+        tables.get_mut(group)?.push(entry);
+    }
+
+    // the ASCII filter goes back in front, so that runs of ASCII still skip the rules (ASCII
+    // characters it passes through unchanged aren't in the table)
+    let mut tables = tables.into_iter();
+    let mut chain: Chain = Chain::new();
+    if ascii_filter { chain.push(Translator::AsciiFilter); }
+    chain.push_expander(map_translation(tables.next()?));
+    for (name, table) in names.iter().zip(tables) {
+        chain.begin_group(name);
+        chain.push_expander(map_translation(table));
+    }
+    chain.index();
+    chain.allow = allow;
+    chain.deny = deny;
+    chain.fallback = fallback;
+    chain.replacement = replacement;
+    chain.pre_normalize = pre_normalize;
+    chain.post_normalize = post_normalize;
+    chain.exit_code = exit_code;
+    chain.protect = protect;
+    chain.only = only;
+    chain.severity = severity;
+    chain.tests = tests;
+    Some(chain)
+}
+
+/// The file a table parsed from `source` (such as a data directory's `confusables.txt`) is
+/// precompiled into.
+pub fn table_path(dir: &Path, source: &Path) -> PathBuf {
+    let mut hasher: DefaultHasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    source.hash(&mut hasher);
+    dir.join(format!("{:016x}.table", hasher.finish()))
+}
+
+/// Write a table parsed from `source` to a file, so that later runs can load it instead of
+/// parsing `source` again. The table is invalidated when `source` changes.
+pub fn store_table(path: &Path, table: &[(char, String)], source: &Path) -> io::Result<()> {
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(TABLE_MAGIC);
+    put_u32(&mut out, TABLE_VERSION);
+
+    let dep: Dependency = Dependency::of(source)?;
+    put_str(&mut out, &dep.path);
+    put_u64(&mut out, dep.mtime);
+    put_u64(&mut out, dep.len);
+
+    put_u32(&mut out, table.len() as u32);
+    for (c, replacement) in table {
+        put_u32(&mut out, *c as u32);
+        put_str(&mut out, replacement);
+    }
+
+    if let Some(dir) = path.parent() { fs::create_dir_all(dir)?; }
+    let tmp: PathBuf = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, out)?;
+    fs::rename(&tmp, path)
+}
+
+/// Load a table written by [`store_table`]. Returns `None` if there is no table, if it is corrupt
+/// or from a different version, or if the file it was parsed from has changed since.
+pub fn load_table(path: &Path) -> Option<Vec<(char, String)>> {
+    let data: Vec<u8> = fs::read(path).ok()?;
+    let mut reader: Reader = Reader { data: &data, pos: 0 };
+
+    if reader.take(4)? != TABLE_MAGIC || reader.u32()? != TABLE_VERSION { return None; }
+    let dep: Dependency = Dependency { path: reader.str()?, mtime: reader.u64()?,
+                                       len: reader.u64()? };
+    if !dep.is_fresh() { return None; }
+
+    let mut table: Vec<(char, String)> = Vec::new();
+    for _ in 0..reader.u32()? {
+        table.push((reader.char()?, reader.str()?));
+    }
+    Some(table)
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_ranges(out: &mut Vec<u8>, ranges: &[(char, char)]) {
+    put_u32(out, ranges.len() as u32);
+    for &(s, e) in ranges {
+        put_u32(out, s as u32);
+        put_u32(out, e as u32);
+    }
+}
+
+/// A cursor over the bytes of a cache file. Every read returns `None` if the file is truncated
+/// or malformed.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes: &'a [u8] = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn char(&mut self) -> Option<char> {
+        char::from_u32(self.u32()?)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len: usize = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn ranges(&mut self) -> Option<Vec<(char, char)>> {
+        let mut ranges: Vec<(char, char)> = Vec::new();
+        for _ in 0..self.u32()? {
+            ranges.push((self.char()?, self.char()?));
+        }
+        Some(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    const CONFIG: &str = "[global]\nuse_ascii_filter = true\n\
+        [cyrillic]\ntype = \"lookup\"\nmap = { \"а\" = \"a\", \"р\" = \"p\" }\n\
+        [[test]]\ninput = \"раy\"\nexpect = \"pay\"\n";
+
+    /// Create a fresh directory for a test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir()
+            .join(format!("utf-normalize-cache-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn chains_round_trip_through_the_cache() {
+        let dir: PathBuf = temp_dir("hit");
+        let source: PathBuf = dir.join("config.toml");
+        fs::write(&source, CONFIG).unwrap();
+        let chain: Chain = config::parse(source.to_string_lossy().into_owned()).unwrap();
+        let path: PathBuf = cache_path(&dir, &["config".to_string()]);
+
+        assert!(load(&path).is_none());
+        store(&path, &chain, std::slice::from_ref(&source)).unwrap();
+        let cached: Chain = load(&path).unwrap();
+        assert_eq!(cached.translate_str("раy рal"), "pay pal");
+        assert!(cached.passes_ascii());
+        assert_eq!(cached.tests, chain.tests);
+        assert_eq!(cached.self_test(), Ok(()));
+        let names = |chain: &Chain| -> Vec<String> {
+            chain.names.iter().map(|(_, name)| name.clone()).collect()
+        };
+        assert_eq!(names(&cached), names(&chain));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn caches_are_invalidated_when_a_config_changes() {
+        let dir: PathBuf = temp_dir("config");
+        let source: PathBuf = dir.join("config.toml");
+        fs::write(&source, CONFIG).unwrap();
+        let chain: Chain = config::parse_str(CONFIG, Format::Toml).unwrap();
+        let path: PathBuf = cache_path(&dir, &["config".to_string()]);
+
+        store(&path, &chain, std::slice::from_ref(&source)).unwrap();
+        assert!(load(&path).is_some());
+        fs::write(&source, format!("{}# edited\n", CONFIG)).unwrap();
+        assert!(load(&path).is_none());
+
+        fs::remove_file(&source).unwrap();
+        store(&path, &chain, &[]).unwrap();
+        assert!(load(&path).is_some());
+        let mut data: Vec<u8> = fs::read(&path).unwrap();
+        data[4..8].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        fs::write(&path, data).unwrap();
+        assert!(load(&path).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keys_change_with_the_data_directory() {
+        let dir: PathBuf = temp_dir("listing");
+        let data: PathBuf = dir.join("data");
+        fs::create_dir_all(&data).unwrap();
+        let key = || cache_path(&dir, &dir_listing(&data));
+
+        let empty: PathBuf = key();
+        assert_eq!(key(), empty);
+        fs::write(data.join("cyrillic.toml"), "[cyrillic]\ntype = \"lookup\"\n").unwrap();
+        let added: PathBuf = key();
+        assert_ne!(added, empty);
+        fs::write(data.join("cyrillic.toml"), "[cyrillic]\ntype = \"lookup\"\nmap = {}\n")
+            .unwrap();
+        let changed: PathBuf = key();
+        assert_ne!(changed, added);
+        fs::remove_file(data.join("cyrillic.toml")).unwrap();
+        assert_eq!(key(), empty);
+
+        fs::remove_dir_all(&data).unwrap();
+        assert_ne!(key(), empty);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tables_round_trip_until_their_source_changes() {
+        let dir: PathBuf = std::env::temp_dir()
+            .join(format!("utf-normalize-table-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source: PathBuf = dir.join("confusables.txt");
+        fs::write(&source, "0430 ; 0061 ; MA\n").unwrap();
+        let path: PathBuf = table_path(&dir, &source);
+        let table: Vec<(char, String)> = vec![('а', "a".to_string()), ('ﬁ', "fi".to_string())];
+
+        assert_eq!(load_table(&path), None);
+        store_table(&path, &table, &source).unwrap();
+        assert_eq!(load_table(&path), Some(table));
+
+        fs::write(&source, "0430 ; 0061 ; MA\n0435 ; 0065 ; MA\n").unwrap();
+        assert_eq!(load_table(&path), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1236 @@
+/// Ordered translator chains, mixing one-to-one and one-to-many rules.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use regex::Regex;
+use tracing::debug;
+use unicode_normalization::UnicodeNormalization;
+use crate::charset::CharSet;
+use crate::detect::{Severities, Severity};
+use crate::translators::{Translator, Expander, map_translation};
+use crate::utf8;
+
+/// A single step in a [`Chain`].
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Maps one character to exactly one character.
+    Char(Translator),
+    /// Maps one character to a string of any length.
+    Str(Expander),
+}
+
+impl Rule {
+    /// Run the rule on a character, appending its replacement to `out` if it handles it. Returns
+    /// whether it did.
+    pub fn apply_into(&self, source: char, out: &mut String) -> bool {
+        self.apply_to(source, out)
+    }
+
+    /// Like [`Rule::apply_into`], for any [`Output`].
+    fn apply_to<O: Output>(&self, source: char, out: &mut O) -> bool {
+        let ord: u32 = source as u32;
+        match self {
+            Rule::Char(t) => t.apply(ord).map(|res| out.push_ord(res, source)).is_some(),
+            // FST tables hand their values over a piece at a time, without putting them together
+            Rule::Str(Expander::Fst { table } | Expander::Sequence { table }) => {
+                table.get_with(source.encode_utf8(&mut [0; 4]).as_bytes(), |s| out.push_str(s))
+            }
+            Rule::Str(e) => e.apply(ord).map(|res| out.push_str(&res)).is_some(),
+        }
+    }
+
+    /// Add every codepoint the rule could handle to a set. Returns `false` (having added only
+    /// some of them, or none) if the rule is a function, whose codepoints can't be listed.
+    fn sources_into(&self, set: &mut CharSet) -> bool {
+        match self {
+            Rule::Char(Translator::Range { source, size, .. }) => {
+                if *size > 0 { set.insert_range(*source, source.saturating_add(*size - 1)); }
+            }
+            Rule::Char(Translator::MultiRange { source, size, slice, iters, .. }) => {
+                let len: u32 = (*size).min(*slice);
+                let starts = (0..*iters)
+                    .map_while(|i| i.checked_mul(*slice).and_then(|o| source.checked_add(o)));
+                for start in starts.filter(|_| len > 0) {
+                    if start > char::MAX as u32 { break; }
+                    set.insert_range(start, start.saturating_add(len - 1));
+                }
+            }
+            Rule::Char(Translator::Lookup { table }) => {
+                table.iter().for_each(|(ord, _)| set.insert(ord));
+            }
+            Rule::Char(Translator::AsciiFilter) => set.insert_range(0, 0x7F),
+            Rule::Str(Expander::Map { table }) => table.iter().for_each(|(ord, _)| set.insert(ord)),
+            // a sequence is only matched where its first character is
+            Rule::Str(Expander::Fst { table } | Expander::Sequence { table }) => {
+                for (key, _) in table.entries() {
+                    let key: Option<char> = std::str::from_utf8(&key).ok()
+                        .and_then(|key| key.chars().next());
+                    if let Some(c) = key { set.insert(c as u32); }
+                }
+            }
+            Rule::Str(Expander::Delete { ranges }) => {
+                ranges.iter().for_each(|&(start, end)| set.insert_range(start, end));
+            }
+            Rule::Char(Translator::Custom(_)) | Rule::Str(Expander::Custom(_)) => return false,
+        }
+        true
+    }
+}
+
+/// Somewhere translated text is written. A `String` checks every codepoint a translator produces
+/// as it is pushed, keeping the original character in place of anything that isn't a valid one;
+/// a byte buffer just encodes it, and is checked to be valid UTF-8 once, when it's full (see
+/// [`Chain::translate_str`]).
+trait Output {
+    /// How many bytes have been written.
+    fn len(&self) -> usize;
+    /// The bytes written since `start`.
+    fn since(&self, start: usize) -> &[u8];
+    fn push(&mut self, c: char);
+    fn push_str(&mut self, s: &str);
+    /// Append a codepoint produced by a translator for `source`, which might not be a valid
+    /// character.
+    fn push_ord(&mut self, ord: u32, source: char);
+}
+
+impl Output for String {
+    fn len(&self) -> usize { String::len(self) }
+    fn since(&self, start: usize) -> &[u8] { &self.as_bytes()[start..] }
+    fn push(&mut self, c: char) { String::push(self, c) }
+    fn push_str(&mut self, s: &str) { String::push_str(self, s) }
+    fn push_ord(&mut self, ord: u32, source: char) {
+        String::push(self, char::from_u32(ord).unwrap_or(source))
+    }
+}
+
+impl Output for Vec<u8> {
+    fn len(&self) -> usize { Vec::len(self) }
+    fn since(&self, start: usize) -> &[u8] { &self[start..] }
+    fn push(&mut self, c: char) { encode_ord(c as u32, self) }
+    fn push_str(&mut self, s: &str) { self.extend_from_slice(s.as_bytes()) }
+    fn push_ord(&mut self, ord: u32, _source: char) { encode_ord(ord, self) }
+}
+
+/// What replaced a character (see [`Chain::changes`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    /// The rule with this index in [`Chain::rules`].
+    Rule(usize),
+    /// Pre-normalization, without any rule changing the result.
+    Normalization,
+    /// The chain's [`Fallback`] policy.
+    Fallback,
+}
+
+/// A character that translation replaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The byte offset of the character in the input.
+    pub offset: usize,
+    /// The original character.
+    pub character: char,
+    /// What it is replaced with.
+    pub replacement: String,
+    /// What replaced it.
+    pub handler: Handler,
+}
+
+/// What a chain does with a non-ASCII character that no rule handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fallback {
+    /// Pass the character through unchanged.
+    #[default]
+    Keep,
+    /// Remove the character.
+    Drop,
+    /// Replace the character with the chain's `replacement` string.
+    Replace,
+    /// Replace the character with an escape of its codepoint, like `\u{1F600}`.
+    Escape,
+    /// Treat the character as an error (see [`Chain::try_translate_str`]). Where errors can't be
+    /// reported, the character is kept.
+    Error,
+}
+
+impl FromStr for Fallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(Fallback::Keep),
+            "drop" => Ok(Fallback::Drop),
+            "replace" => Ok(Fallback::Replace),
+            "escape" => Ok(Fallback::Escape),
+            "error" => Ok(Fallback::Error),
+            other => Err(format!("unknown fallback \"{}\" (expected keep, drop, replace, escape \
+                                  or error)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fallback::Keep => write!(f, "keep"),
+            Fallback::Drop => write!(f, "drop"),
+            Fallback::Replace => write!(f, "replace"),
+            Fallback::Escape => write!(f, "escape"),
+            Fallback::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What decides whether a run fails, apart from errors (like unreadable files), which always make
+/// it fail. This is read by the command line tool, so that it can fit different CI policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitPolicy {
+    /// Check modes fail if anything would change, detect and scan fail on findings with severity
+    /// "error", and writing modes only fail on errors.
+    #[default]
+    Default,
+    /// Never fail because of what was changed or found.
+    Never,
+    /// Fail if anything was (or would be) changed, even when writing, or if anything was found.
+    Changes,
+    /// Fail on findings with at least this severity. Normalizing (which finds nothing) only fails
+    /// on errors.
+    Severity(Severity),
+}
+
+impl ExitPolicy {
+    /// Whether a run fails under this policy, given whether anything changed, the worst severity
+    /// found and whether it would fail by default.
+    pub fn fails(self, changed: bool, worst: Severity, default: bool) -> bool {
+        match self {
+            ExitPolicy::Default => default,
+            ExitPolicy::Never => false,
+            ExitPolicy::Changes => changed || worst > Severity::Off,
+            ExitPolicy::Severity(min) => worst >= min,
+        }
+    }
+}
+
+impl FromStr for ExitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(ExitPolicy::Default),
+            "never" => Ok(ExitPolicy::Never),
+            "changes" => Ok(ExitPolicy::Changes),
+            other => match other.parse() {
+                Ok(Severity::Off) | Err(_) => Err(format!("unknown exit code policy \"{}\" \
+                                                           (expected default, never, changes, \
+                                                           info, warn or error)", other)),
+                Ok(severity) => Ok(ExitPolicy::Severity(severity)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ExitPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitPolicy::Default => write!(f, "default"),
+            ExitPolicy::Never => write!(f, "never"),
+            ExitPolicy::Changes => write!(f, "changes"),
+            ExitPolicy::Severity(severity) => write!(f, "{}", severity),
+        }
+    }
+}
+
+/// A Unicode normalization form, applied to text before or after it goes through a [`Chain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalForm {
+    /// Leave the text as it is.
+    #[default]
+    None,
+    /// Canonical composition.
+    Nfc,
+    /// Canonical decomposition.
+    Nfd,
+    /// Compatibility composition.
+    Nfkc,
+    /// Compatibility decomposition. Applied before the chain, this splits characters like `ﬁ` and
+    /// `é` into simpler pieces, so translators only have to handle the base characters.
+    Nfkd,
+}
+
+impl NormalForm {
+    /// Normalize a string to this form. Borrows the string when the form is
+    /// [`NormalForm::None`].
+    pub fn apply<'a>(&self, source: &'a str) -> Cow<'a, str> {
+        match self {
+            NormalForm::None => Cow::Borrowed(source),
+            NormalForm::Nfc => Cow::Owned(source.nfc().collect()),
+            NormalForm::Nfd => Cow::Owned(source.nfd().collect()),
+            NormalForm::Nfkc => Cow::Owned(source.nfkc().collect()),
+            NormalForm::Nfkd => Cow::Owned(source.nfkd().collect()),
+        }
+    }
+}
+
+impl FromStr for NormalForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(NormalForm::None),
+            "nfc" => Ok(NormalForm::Nfc),
+            "nfd" => Ok(NormalForm::Nfd),
+            "nfkc" => Ok(NormalForm::Nfkc),
+            "nfkd" => Ok(NormalForm::Nfkd),
+            other => Err(format!("unknown normalization form \"{}\" (expected NFC, NFD, NFKC, \
+                                  NFKD or none)", other)),
+        }
+    }
+}
+
+impl fmt::Display for NormalForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NormalForm::None => write!(f, "none"),
+            NormalForm::Nfc => write!(f, "NFC"),
+            NormalForm::Nfd => write!(f, "NFD"),
+            NormalForm::Nfkc => write!(f, "NFKC"),
+            NormalForm::Nfkd => write!(f, "NFKD"),
+        }
+    }
+}
+
+/// A Chain is an ordered list of rules that is applied to each character of the input. The rules
+/// are tried in order, and the first one that handles a character decides its replacement; if no
+/// rule handles it, the character is passed through unchanged if it is ASCII, and handled by the
+/// chain's [`Fallback`] policy otherwise.
+///
+/// This is the same evaluation order as `translate()` uses for a slice of translators, but a chain
+/// can also contain one-to-many rules ([`Expander`]s), so it translates into a `String` rather
+/// than returning a single `char`.
+///
+/// A chain also carries an allow list of characters that are always passed through untouched,
+/// and a deny list of characters that make the input invalid (see [`Chain::try_translate_str`]).
+/// Both are lists of inclusive ranges.
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub rules: Vec<Rule>,
+    pub allow: Vec<(char, char)>,
+    pub deny: Vec<(char, char)>,
+    pub tests: Vec<TestVector>,
+    pub fallback: Fallback,
+    /// The replacement for unhandled characters when `fallback` is [`Fallback::Replace`].
+    pub replacement: String,
+    /// Patterns for protected regions. Anything one of these matches is passed through untouched,
+    /// without being translated or checked.
+    pub protect: Vec<Regex>,
+    /// Patterns that scope translation. If there are any, only the regions they match are
+    /// translated and checked; everything else is protected.
+    pub only: Vec<Regex>,
+    /// The severity each class of suspicious characters is reported with (see
+    /// [`crate::detect::detect`]).
+    pub severity: Severities,
+    /// The normalization applied to each unprotected region before it is translated.
+    pub pre_normalize: NormalForm,
+    /// The normalization applied to each unprotected region after it is translated.
+    pub post_normalize: NormalForm,
+    /// What decides the exit code of the command line tool (see [`ExitPolicy`]).
+    pub exit_code: ExitPolicy,
+    /// The names of the groups of rules in the chain (usually the config sections they were built
+    /// from), as the index of each group's first rule and its name, in order. Rules before the
+    /// first group have no name.
+    pub names: Vec<(usize, String)>,
+    /// An index of the chain's range translators, if it has been built (see [`Chain::index`]).
+    /// Pushing a rule drops it, since it no longer covers every rule.
+    ranges: Option<RangeIndex>,
+    /// Every codepoint any rule could handle, if it has been built (see [`Chain::index`]).
+    /// Characters outside it skip the rules entirely. Pushing a rule drops it.
+    sources: Option<CharSet>,
+    /// The indices of the sequence rules (see [`crate::translators::sequence_translation`]), in
+    /// order. These are tried on the text at each character before the rules are run on it.
+    sequences: Vec<usize>,
+}
+
+impl Default for Chain {
+    fn default() -> Chain {
+        Chain::new()
+    }
+}
+
+/// A test vector that ships with a chain: translating `input` should produce exactly `expect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    pub input: String,
+    pub expect: String,
+}
+
+/// A test vector that didn't produce the expected output, along with what it produced instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestFailure {
+    pub vector: TestVector,
+    pub actual: Result<String, Denied>,
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Ok(actual) => write!(f, "{:?}: expected {:?}, got {:?}", self.vector.input,
+                                 self.vector.expect, actual),
+            Err(denied) => write!(f, "{:?}: expected {:?}, got {}", self.vector.input,
+                                  self.vector.expect, denied),
+        }
+    }
+}
+
+/// The error returned when the input contains a character from a chain's deny list, or an
+/// unhandled character when the chain's fallback is [`Fallback::Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denied {
+    /// The denied character.
+    pub character: char,
+    /// The byte offset of the character in the input.
+    pub offset: usize,
+    /// Whether the character was denied for not being handled, rather than for being on the deny
+    /// list.
+    pub unmapped: bool,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what: &str = if self.unmapped { "unmapped" } else { "denied" };
+        write!(f, "{} character U+{:04X} at byte {}", what, self.character as u32, self.offset)
+    }
+}
+
+impl std::error::Error for Denied {}
+
+impl Chain {
+    /// Create an empty chain, which passes every character through unchanged.
+    pub fn new() -> Chain {
+        Chain {
+            rules: Vec::new(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            tests: Vec::new(),
+            fallback: Fallback::Keep,
+            replacement: "?".to_string(),
+            protect: Vec::new(),
+            only: Vec::new(),
+            severity: Severities::default(),
+            pre_normalize: NormalForm::None,
+            post_normalize: NormalForm::None,
+            exit_code: ExitPolicy::Default,
+            names: Vec::new(),
+            ranges: None,
+            sources: None,
+            sequences: Vec::new(),
+        }
+    }
+
+    /// Start a named group of rules: every rule pushed from now on (until the next group starts)
+    /// is reported as coming from `name`.
+    pub fn begin_group(&mut self, name: &str) {
+        self.names.push((self.rules.len(), name.to_string()));
+    }
+
+    /// The name of the group a rule belongs to, if it has one.
+    pub fn rule_name(&self, rule: usize) -> Option<&str> {
+        let group: usize = self.names.partition_point(|&(start, _)| start <= rule);
+        group.checked_sub(1).map(|group| self.names[group].1.as_str())
+    }
+
+    /// Describe what made a change, for reports: the name of the rule's group (or "rule N" for
+    /// rules without one), "normalization" or "fallback".
+    pub fn describe(&self, handler: Handler) -> String {
+        match handler {
+            Handler::Rule(rule) => self.rule_name(rule).map(str::to_string)
+                .unwrap_or_else(|| format!("rule {}", rule)),
+            Handler::Normalization => "normalization".to_string(),
+            Handler::Fallback => "fallback".to_string(),
+        }
+    }
+
+    /// Append a one-to-one translator to the end of the chain.
+    pub fn push(&mut self, translator: Translator) {
+        self.rules.push(Rule::Char(translator));
+        self.ranges = None;
+        self.sources = None;
+    }
+
+    /// Append a one-to-many translator to the end of the chain.
+    pub fn push_expander(&mut self, expander: Expander) {
+        if matches!(expander, Expander::Sequence { .. }) { self.sequences.push(self.rules.len()); }
+        self.rules.push(Rule::Str(expander));
+        self.ranges = None;
+        self.sources = None;
+    }
+
+    /// Build the indexes that speed up looking characters up in the chain:
+    ///
+    /// - a set of every codepoint any rule could handle, so that characters no rule handles (most
+    ///   of them, usually) are passed over without trying any rules. This is skipped if the chain
+    ///   has a custom rule, since there's no way to tell what it handles.
+    /// - an index of the range and multirange translators (see [`RangeIndex`]), if there are
+    ///   enough of them to make it worthwhile, so that the one covering a character is found by a
+    ///   binary search rather than by trying each in turn.
+    ///
+    /// This doesn't change what the chain does, only how fast it does it. Call it once the chain
+    /// is complete; pushing another rule drops the indexes, and changing [`Chain::rules`]
+    /// directly leaves them out of date.
+    pub fn index(&mut self) {
+        let mut sources: CharSet = CharSet::new();
+        let listed: bool = self.rules.iter().all(|rule| rule.sources_into(&mut sources));
+        self.sources = if listed { Some(sources) } else { None };
+        let ranges: RangeIndex = RangeIndex::new(&self.rules);
+        self.ranges = if ranges.len() >= RANGE_INDEX_MIN { Some(ranges) } else { None };
+        self.sequences = self.rules.iter().enumerate()
+            .filter(|(_, rule)| matches!(rule, Rule::Str(Expander::Sequence { .. })))
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    /// Whether the chain has any sequence rules, which match more than one character at a time
+    /// (see [`crate::translators::sequence_translation`]).
+    pub fn has_sequences(&self) -> bool {
+        !self.sequences.is_empty()
+    }
+
+    /// The number of rules in the chain.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether the chain has no rules at all.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a character is on the allow list.
+    pub fn is_allowed(&self, c: char) -> bool {
+        self.allow.iter().any(|&(s, e)| c >= s && c <= e)
+    }
+
+    /// Whether every ASCII character is known to come out of the chain unchanged, so runs of
+    /// ASCII can be copied over without running each character through the rules. That's the
+    /// case when the chain starts with the ASCII filter, which is the first rule whenever
+    /// `use_ascii_filter` is set.
+    pub(crate) fn passes_ascii(&self) -> bool {
+        matches!(self.rules.first(), Some(Rule::Char(Translator::AsciiFilter)))
+    }
+
+    /// Whether a character is on the deny list.
+    pub fn is_denied(&self, c: char) -> bool {
+        self.deny.iter().any(|&(s, e)| c >= s && c <= e)
+    }
+
+    /// Split a string into the regions matched by the protect patterns and the regions between
+    /// them. Each region is returned with its byte offset in `source` and whether it is
+    /// protected. Overlapping matches are merged into one protected region. If there are any
+    /// `only` patterns, everything outside their matches is protected as well.
+    pub fn segments<'a>(&self, source: &'a str) -> Vec<(usize, bool, &'a str)> {
+        if self.protect.is_empty() && self.only.is_empty() { return vec![(0, false, source)]; }
+
+        let mut spans: Vec<(usize, usize)> = matches(&self.protect, source);
+        if !self.only.is_empty() {
+            let mut pos: usize = 0;
+            for (start, end) in matches(&self.only, source) {
+                if start > pos { spans.push((pos, start)); }
+                pos = pos.max(end);
+            }
+            if pos < source.len() { spans.push((pos, source.len())); }
+            spans.sort_unstable();
+        }
+
+        let mut segments: Vec<(usize, bool, &'a str)> = Vec::new();
+        let mut pos: usize = 0;
+        let mut spans = spans.into_iter().peekable();
+        while let Some((start, mut end)) = spans.next() {
+            while let Some(&(next_start, next_end)) = spans.peek() {
+                if next_start > end { break; }
+                end = end.max(next_end);
+                spans.next();
+            }
+            if start > pos { segments.push((pos, false, &source[pos..start])); }
+            segments.push((start, true, &source[start..end]));
+            pos = end;
+        }
+        if pos < source.len() { segments.push((pos, false, &source[pos..])); }
+        segments
+    }
+
+    /// Find the first denied character in a string, if there is one. Protected regions are not
+    /// checked.
+    pub fn find_denied(&self, source: &str) -> Option<Denied> {
+        if self.deny.is_empty() { return None; }
+        self.segments(source).into_iter()
+            .filter(|&(_, protected, _)| !protected)
+            .flat_map(|(base, _, text)| text.char_indices().map(move |(i, c)| (base + i, c)))
+            .find(|&(_, c)| self.is_denied(c))
+            .map(|(offset, character)| Denied { character, offset, unmapped: false })
+    }
+
+    /// Translate a single character, appending its replacement to `out`. The deny list is not
+    /// checked here, and [`Fallback::Error`] keeps unhandled characters; use
+    /// [`Chain::try_translate_str`] to have those reported as errors.
+    pub fn translate_into(&self, source: char, out: &mut String) {
+        if !self.lookup_into(source, out) { self.fallback_into(source, out); }
+    }
+
+    /// Run the allow list and the rules on a character, appending its replacement to `out` if
+    /// any of them handle it. Returns whether the character was handled; if it wasn't, nothing is
+    /// appended.
+    pub fn lookup_into(&self, source: char, out: &mut String) -> bool {
+        if self.is_allowed(source) {
+            out.push(source);
+            return true;
+        }
+        self.rule_into(source, out).is_some()
+    }
+
+    /// Match the sequence rules against the start of `text`, whose first character is `first`,
+    /// appending the replacement of the longest source of more than one character that the first
+    /// matching rule has to `out`. A sequence doesn't match if an earlier rule handles `first`
+    /// on its own. Returns the index of the rule and the length of the match, in bytes.
+    fn sequence_into<O: Output>(&self, text: &str, first: char, out: &mut O)
+        -> Option<(usize, usize)> {
+        for &rule in &self.sequences {
+            let Some(Rule::Str(Expander::Sequence { table })) = self.rules.get(rule) else {
+                continue;
+            };
+            let Some((len, replacement)) = table.longest_match(text.as_bytes()) else { continue };
+            // single characters are left to the rules, in order
+            if len <= first.len_utf8() { continue; }
+            let mut scratch: String = String::new();
+            if self.rule_into(first, &mut scratch).is_some_and(|handler| handler < rule) {
+                return None;
+            }
+            out.push_str(&replacement);
+            return Some((rule, len));
+        }
+        None
+    }
+
+    /// Run just the rules on a character (not the allow list), appending its replacement to `out`
+    /// if any of them handle it. Returns the index of the rule that handled it.
+    fn rule_into<O: Output>(&self, source: char, out: &mut O) -> Option<usize> {
+        if self.sources.as_ref().is_some_and(|sources| !sources.contains(source as u32)) {
+            return None;
+        }
+        let Some(index) = &self.ranges else {
+            return self.rules.iter().position(|rule| rule.apply_to(source, out));
+        };
+        // the indexed rule only wins if no earlier rule outside the index handles the character
+        let found: Option<(usize, u32)> = index.find(source as u32);
+        let limit: usize = found.map_or(self.rules.len(), |(rule, _)| rule);
+        for &rule in index.others.iter().take_while(|&&rule| rule < limit) {
+            if self.rules[rule].apply_to(source, out) { return Some(rule); }
+        }
+        let (rule, ord) = found?;
+        out.push_ord(ord, source);
+        Some(rule)
+    }
+
+    /// Apply the fallback policy to a character that no rule handled. ASCII characters are always
+    /// kept.
+    pub fn fallback_into(&self, source: char, out: &mut String) {
+        self.fallback_to(source, out)
+    }
+
+    /// Like [`Chain::fallback_into`], for any [`Output`].
+    fn fallback_to<O: Output>(&self, source: char, out: &mut O) {
+        if source.is_ascii() {
+            out.push(source);
+            return;
+        }
+        match self.fallback {
+            Fallback::Keep | Fallback::Error => out.push(source),
+            Fallback::Drop => {}
+            Fallback::Replace => out.push_str(&self.replacement),
+            Fallback::Escape => out.push_str(&format!("\\u{{{:X}}}", source as u32)),
+        }
+    }
+
+    /// Whether translating a string is certain to give it back unchanged, worked out without
+    /// translating it: every character is one that no rule handles and the fallback keeps, one on
+    /// the allow list, or ASCII passed through by the ASCII filter, and (if the chain normalizes)
+    /// the string is all ASCII. This holds for [`Chain::try_translate_str`] as well: a chain with
+    /// a deny list never leaves a string unchanged, and an error fallback doesn't count as keeping
+    /// characters. This is much cheaper than translating, so text that is already
+    /// clean can be passed straight through. It only knows which characters the rules handle once
+    /// the chain is indexed (see [`Chain::index`]); before that, or if the chain has a custom rule,
+    /// only ASCII passed by the ASCII filter and allowed characters count as unchanged. A `false`
+    /// doesn't mean the string would change, just that it might.
+    pub fn leaves_unchanged(&self, text: &str) -> bool {
+        if !self.deny.is_empty() { return false; }
+        let ascii: bool = self.passes_ascii();
+        let normalizes: bool = self.pre_normalize != NormalForm::None
+            || self.post_normalize != NormalForm::None;
+        let keeps: bool = self.fallback == Fallback::Keep;
+        let kept = |c: char| -> bool {
+            if ascii && c.is_ascii() { return true; }
+            // normalization never changes ASCII, but it can change anything else
+            if normalizes && !c.is_ascii() { return false; }
+            if self.is_allowed(c) { return true; }
+            self.sources.as_ref()
+                .is_some_and(|sources| !sources.contains(c as u32) && (c.is_ascii() || keeps))
+        };
+        let bytes: &[u8] = text.as_bytes();
+        let mut pos: usize = 0;
+        while pos < text.len() {
+            if ascii { pos += ascii_prefix(&bytes[pos..]); }
+            let Some(c) = text[pos..].chars().next() else { break; };
+            if !kept(c) { return false; }
+            pos += c.len_utf8();
+        }
+        true
+    }
+
+    /// Translate a whole string. Protected regions are copied over unchanged.
+    pub fn translate_str(&self, source: &str) -> String {
+        let mut out: String = String::with_capacity(source.len());
+        self.translate_str_into(source, &mut out);
+        out
+    }
+
+    /// Translate a whole string like [`Chain::translate_str`], appending the result to `out`
+    /// rather than returning a new string. Clearing and reusing one buffer saves an allocation per
+    /// call when translating many small strings.
+    pub fn translate_str_into(&self, source: &str, out: &mut String) {
+        if self.translate_string(source, false, out).is_err() {
+            unreachable!("translation only fails in strict mode");
+        }
+    }
+
+    /// Translate some bytes, appending the translation to `out` as UTF-8. Invalid UTF-8 in
+    /// `source` is decoded as U+FFFD first (see [`utf8::from_utf8_lossy`]). Like
+    /// [`Chain::translate_str_into`], this reuses the caller's buffer.
+    pub fn translate_bytes_into(&self, source: &[u8], out: &mut Vec<u8>) {
+        let text: Cow<str> = utf8::from_utf8_lossy(source);
+        if self.translate_segments(&text, false, out).is_err() {
+            unreachable!("translation only fails in strict mode");
+        }
+    }
+
+    /// Translate a whole string, failing if it contains any denied characters, or (if the
+    /// fallback is [`Fallback::Error`]) any non-ASCII characters that no rule handles. When
+    /// `pre_normalize` is set, the offset of an unhandled character is its offset in the
+    /// normalized text.
+    pub fn try_translate_str(&self, source: &str) -> Result<String, Denied> {
+        let mut out: String = String::with_capacity(source.len());
+        self.try_translate_str_into(source, &mut out)?;
+        Ok(out)
+    }
+
+    /// Like [`Chain::try_translate_str`], appending the translation to `out`. If translation
+    /// fails, `out` is left as it was.
+    pub fn try_translate_str_into(&self, source: &str, out: &mut String) -> Result<(), Denied> {
+        if let Some(denied) = self.find_denied(source) { return Err(denied); }
+        self.translate_string(source, self.fallback == Fallback::Error, out)
+    }
+
+    /// Whether the chain can reject input: it has a deny list, or its fallback is
+    /// [`Fallback::Error`]. Text translated with such a chain should go through
+    /// [`Chain::try_translate_str`], since the other ways of translating ignore both.
+    pub fn is_strict(&self) -> bool {
+        !self.deny.is_empty() || self.fallback == Fallback::Error
+    }
+
+    /// Find every character that translating a string would change, in order. Each character is
+    /// pre-normalized on its own rather than along with its neighbours, so that every change has
+    /// an offset in `source`, and `post_normalize` is not applied; the replacements can differ
+    /// from [`Chain::translate_str`] where normalization combines characters. Sequence rules only
+    /// match single characters here, and the deny list is not checked.
+    pub fn changes(&self, source: &str) -> Vec<Change> {
+        let mut changes: Vec<Change> = Vec::new();
+        let mut buf: [u8; 4] = [0; 4];
+        let ascii: bool = self.passes_ascii();
+        for (base, protected, text) in self.segments(source) {
+            if protected { continue; }
+            for (offset, character) in text.char_indices() {
+                if ascii && character.is_ascii() { continue; }
+                let mut replacement: String = String::new();
+                let mut handler: Option<Handler> = None;
+                for c in self.pre_normalize.apply(character.encode_utf8(&mut buf)).chars() {
+                    let start: usize = replacement.len();
+                    let by: Handler = if self.is_allowed(c) {
+                        replacement.push(c);
+                        Handler::Normalization
+                    } else if let Some(rule) = self.rule_into(c, &mut replacement) {
+                        Handler::Rule(rule)
+                    } else {
+                        self.fallback_into(c, &mut replacement);
+                        Handler::Fallback
+                    };
+                    let mut piece = replacement[start..].chars();
+                    let kept: bool = piece.next() == Some(c) && piece.next().is_none();
+                    if !kept { handler.get_or_insert(by); }
+                }
+                let mut chars = replacement.chars();
+                if chars.next() == Some(character) && chars.next().is_none() { continue; }
+                changes.push(Change {
+                    offset: base + offset,
+                    character,
+                    replacement,
+                    handler: handler.unwrap_or(Handler::Normalization),
+                });
+            }
+        }
+        changes
+    }
+
+    /// Run [`Chain::translate_segments`] on the end of a string. If translation fails, `out` is
+    /// left as it was.
+    fn translate_string(&self, source: &str, strict: bool, out: &mut String) -> Result<(), Denied> {
+        let mut bytes: Vec<u8> = std::mem::take(out).into_bytes();
+        let result: Result<(), Denied> = self.translate_segments(source, strict, &mut bytes);
+        // SAFETY: `bytes` started out as a `String`, and translate_segments only ever appends
+        // valid UTF-8 to it (or nothing at all, if it fails).
+        *out = unsafe { String::from_utf8_unchecked(bytes) };
+        result
+    }
+
+    /// Translate every unprotected region of a string, normalizing it before and after as the
+    /// chain is configured to, and append the result to `out`. In strict mode, unhandled non-ASCII
+    /// characters are an error rather than being passed to the fallback policy, and `out` is left
+    /// as it was.
+    ///
+    /// Each region is translated straight into `out` and checked to be valid UTF-8 once, rather
+    /// than every replacement being checked as it is made.
+    fn translate_segments(&self, source: &str, strict: bool, out: &mut Vec<u8>)
+        -> Result<(), Denied> {
+        let first: usize = out.len();
+        self.translate_regions(source, strict, out).inspect_err(|_| out.truncate(first))
+    }
+
+    /// The body of [`Chain::translate_segments`], which can leave part of a region in `out` if it
+    /// fails.
+    fn translate_regions(&self, source: &str, strict: bool, out: &mut Vec<u8>)
+        -> Result<(), Denied> {
+        for (base, protected, text) in self.segments(source) {
+            if protected {
+                out.extend_from_slice(text.as_bytes());
+                continue;
+            }
+            let text: Cow<str> = self.pre_normalize.apply(text);
+            let start: usize = out.len();
+            self.translate_run(&text, base, strict, out)?;
+            let replaced: Option<String> = match utf8::from_utf8(&out[start..]) {
+                Ok(_) if self.post_normalize == NormalForm::None => None,
+                Ok(translated) => Some(self.post_normalize.apply(translated).into_owned()),
+                // a translator produced a codepoint that isn't a valid character; go over the
+                // region again, checking each one
+                Err(_) => {
+                    let mut checked: String = String::new();
+                    self.translate_run(&text, base, strict, &mut checked)?;
+                    Some(self.post_normalize.apply(&checked).into_owned())
+                }
+            };
+            if let Some(replaced) = replaced {
+                out.truncate(start);
+                out.extend_from_slice(replaced.as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Translate one (pre-normalized) unprotected region, which starts at byte `base` of the
+    /// input, into `out`. In strict mode, unhandled non-ASCII characters are an error.
+    fn translate_run<O: Output>(&self, text: &str, base: usize, strict: bool, out: &mut O)
+        -> Result<(), Denied> {
+        let ascii: bool = self.passes_ascii();
+        let bytes: &[u8] = text.as_bytes();
+        let mut pos: usize = 0;
+        while pos < text.len() {
+            // copy a run of ASCII over wholesale, then translate the non-ASCII run after it
+            let end: usize = if ascii {
+                let run: usize = ascii_prefix(&bytes[pos..]);
+                out.push_str(&text[pos..pos + run]);
+                pos += run;
+                bytes[pos..].iter().position(u8::is_ascii).map_or(text.len(), |len| pos + len)
+            } else {
+                text.len()
+            };
+            // a sequence can run on past the end of the non-ASCII run
+            while pos < end {
+                let Some(character) = text[pos..].chars().next() else { break };
+                let before: usize = out.len();
+                if self.is_allowed(character) {
+                    out.push(character);
+                    pos += character.len_utf8();
+                    continue;
+                }
+                if let Some((rule, len)) = self.sequence_into(&text[pos..], character, out) {
+                    debug!("[chain] {:?} -> {:?} ({})", &text[pos..pos + len],
+                           String::from_utf8_lossy(out.since(before)),
+                           self.describe(Handler::Rule(rule)));
+                    pos += len;
+                    continue;
+                }
+                if let Some(rule) = self.rule_into(character, out) {
+                    self.log_replacement(character, out.since(before), Handler::Rule(rule));
+                    pos += character.len_utf8();
+                    continue;
+                }
+                if strict && !character.is_ascii() {
+                    return Err(Denied { character, offset: base + pos, unmapped: true });
+                }
+                self.fallback_to(character, out);
+                if !character.is_ascii() {
+                    self.log_replacement(character, out.since(before), Handler::Fallback);
+                }
+                pos += character.len_utf8();
+            }
+        }
+        Ok(())
+    }
+
+    /// Log (at debug level) that a character was replaced, what replaced it and what did the
+    /// replacing. Characters that come out unchanged aren't logged.
+    fn log_replacement(&self, source: char, replacement: &[u8], handler: Handler) {
+        if replacement == source.encode_utf8(&mut [0; 4]).as_bytes() { return; }
+        debug!("[chain] U+{:04X} {:?} -> {:?} ({})", source as u32, source,
+               String::from_utf8_lossy(replacement), self.describe(handler));
+    }
+
+    /// Flatten the chain's rules into a table of every character they handle, paired with its
+    /// replacement, in codepoint order. ASCII characters that are mapped to themselves are left
+    /// out, since they behave the same whether they are handled or not. The allow list, deny list
+    /// and fallback are not part of the table.
+    ///
+    /// This runs the chain on every Unicode scalar value, so it is slow (roughly a million
+    /// lookups); it is meant to be done once and cached. Rebuilding a chain from the table with
+    /// [`Chain::from_table`] (and copying over the lists and fallback) gives the same output for
+    /// every character. Sequences of more than one character aren't part of the table, so a chain
+    /// with sequence rules (see [`Chain::has_sequences`]) can't be rebuilt this way.
+    pub fn compile(&self) -> Vec<(char, String)> {
+        self.compile_rules().into_iter().map(|(c, replacement, _)| (c, replacement)).collect()
+    }
+
+    /// Like [`Chain::compile`], but also records the index of the rule that handles each
+    /// character, so that a chain rebuilt from the table can still tell which group each
+    /// replacement came from.
+    pub(crate) fn compile_rules(&self) -> Vec<(char, String, usize)> {
+        let mut table: Vec<(char, String, usize)> = Vec::new();
+        let mut buf: String = String::new();
+        (0..=char::MAX as u32).filter_map(char::from_u32).for_each(|c| {
+            buf.clear();
+            let Some(rule) = self.rule_into(c, &mut buf) else { return };
+            let mut chars = buf.chars();
+            let unchanged: bool = chars.next() == Some(c) && chars.next().is_none();
+            if !(unchanged && c.is_ascii()) { table.push((c, buf.clone(), rule)); }
+        });
+        table
+    }
+
+    /// Create a chain from a table produced by [`Chain::compile`].
+    pub fn from_table(table: Vec<(char, String)>) -> Chain {
+        let mut chain: Chain = Chain::new();
+        chain.push_expander(map_translation(table));
+        chain.index();
+        chain
+    }
+
+    /// Run every test vector attached to the chain, returning the ones that failed.
+    pub fn self_test(&self) -> Result<(), Vec<TestFailure>> {
+        let failures: Vec<TestFailure> = self.tests.iter()
+            .filter_map(|vector| {
+                let actual: Result<String, Denied> = self.try_translate_str(&vector.input);
+                if actual.as_ref() == Ok(&vector.expect) { None }
+                else { Some(TestFailure { vector: vector.clone(), actual }) }
+            })
+            .collect();
+
+        if failures.is_empty() { Ok(()) } else { Err(failures) }
+    }
+}
+
+impl From<Vec<Translator>> for Chain {
+    fn from(translators: Vec<Translator>) -> Chain {
+        let mut chain: Chain = Chain {
+            rules: translators.into_iter().map(Rule::Char).collect(),
+            ..Chain::new()
+        };
+        chain.index();
+        chain
+    }
+}
+
+/// How many ranges a chain needs before [`Chain::index`] indexes them. With only a few, a
+/// binary search saves nothing over trying each one.
+const RANGE_INDEX_MIN: usize = 8;
+
+/// The most iterations a multirange translator can have and still be indexed (each iteration is
+/// one interval). Larger ones are tried in order along with the other rules.
+const MAX_INDEXED_ITERS: u32 = 4096;
+
+/// The range and multirange translators of a chain, merged into one sorted list of disjoint
+/// intervals that is searched with a binary search. Where translators overlap, each codepoint
+/// belongs to the earliest one that covers it, since that's the one that would handle it.
+#[derive(Debug, Clone, Default)]
+pub struct RangeIndex {
+    /// `(start, end, offset, rule)`: the codepoints from `start` to `end` (inclusive) are
+    /// translated to `ord - offset` by the rule with index `rule`.
+    spans: Vec<(u32, u32, u32, usize)>,
+    /// The indices of the rules that aren't indexed, in order.
+    others: Vec<usize>,
+}
+
+impl RangeIndex {
+    /// Index a list of rules.
+    pub fn new(rules: &[Rule]) -> RangeIndex {
+        let mut index: RangeIndex = RangeIndex::default();
+        for (rule, r) in rules.iter().enumerate() {
+            match r {
+                Rule::Char(Translator::Range { source, offset, size }) => {
+                    if *size > 0 {
+                        index.insert(*source, source.saturating_add(size - 1), *offset, rule);
+                    }
+                }
+                Rule::Char(Translator::MultiRange { source, target, size, slice, iters })
+                    if *iters <= MAX_INDEXED_ITERS => {
+                    let len: u32 = (*size).min(*slice);
+                    if len == 0 { continue; }
+                    // slices that would start past u32::MAX can't match anything
+                    let starts = (0..*iters)
+                        .map_while(|i| i.checked_mul(*slice).and_then(|d| source.checked_add(d)));
+                    for start in starts {
+                        let end: u32 = start.saturating_add(len - 1);
+                        index.insert(start, end, start.wrapping_sub(*target), rule);
+                    }
+                }
+                _ => index.others.push(rule),
+            }
+        }
+        index
+    }
+
+    /// How many intervals are indexed.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Whether nothing is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Find the indexed rule that covers a codepoint, along with what it translates it to.
+    pub fn find(&self, ord: u32) -> Option<(usize, u32)> {
+        let i: usize = self.spans.partition_point(|&(_, end, _, _)| end < ord);
+        self.spans.get(i)
+            .filter(|&&(start, _, _, _)| start <= ord)
+            .map(|&(_, _, offset, rule)| (rule, ord.wrapping_sub(offset)))
+    }
+
+    /// Add the parts of an interval that no earlier rule already covers.
+    fn insert(&mut self, start: u32, end: u32, offset: u32, rule: usize) {
+        let mut pieces: Vec<(u32, u32, u32, usize)> = Vec::new();
+        // the next codepoint that might be uncovered, which is past u32::MAX once a span ends there
+        let mut pos: u64 = start as u64;
+        let first: usize = self.spans.partition_point(|&(_, span_end, _, _)| span_end < start);
+        for &(span_start, span_end, _, _) in &self.spans[first..] {
+            if span_start > end { break; }
+            if span_start as u64 > pos { pieces.push((pos as u32, span_start - 1, offset, rule)); }
+            pos = pos.max(span_end as u64 + 1);
+        }
+        if pos <= end as u64 { pieces.push((pos as u32, end, offset, rule)); }
+        self.spans.extend(pieces);
+        self.spans.sort_unstable_by_key(|&(span_start, _, _, _)| span_start);
+    }
+}
+
+/// Find every non-empty match of any of the patterns in a string, as byte ranges, sorted by
+/// where they start. Matches of different patterns can overlap.
+fn matches(patterns: &[Regex], source: &str) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(usize, usize)> = patterns.iter()
+        .flat_map(|pattern| pattern.find_iter(source).map(|m| (m.start(), m.end())))
+        .filter(|(start, end)| start < end)
+        .collect();
+    spans.sort_unstable();
+    spans
+}
+
+/// The length of the longest ASCII-only prefix of some bytes. The bytes are checked a machine
+/// word at a time (any byte with its high bit set ends the run), with the tail that doesn't fill
+/// a word checked byte by byte.
+fn ascii_prefix(bytes: &[u8]) -> usize {
+    const WORD: usize = std::mem::size_of::<usize>();
+    const HIGH: usize = usize::from_ne_bytes([0x80; WORD]);
+    let mut len: usize = 0;
+    for chunk in bytes.chunks_exact(WORD) {
+        let word: usize = usize::from_ne_bytes(chunk.try_into().expect("chunk is one word long"));
+        if word & HIGH != 0 { break; }
+        len += WORD;
+    }
+    len + bytes[len..].iter().position(|b| !b.is_ascii()).unwrap_or(bytes.len() - len)
+}
+
+/// Append the UTF-8 encoding of a codepoint to a buffer, without checking that it's a valid
+/// character first. Surrogates and values past U+10FFFF come out as bytes that aren't valid UTF-8,
+/// so checking the whole buffer afterwards catches them.
+fn encode_ord(ord: u32, out: &mut Vec<u8>) {
+    match ord {
+        0..=0x7F => out.push(ord as u8),
+        0x80..=0x7FF => out.extend_from_slice(&[
+            0xC0 | (ord >> 6) as u8,
+            0x80 | (ord & 0x3F) as u8,
+        ]),
+        0x800..=0xFFFF => out.extend_from_slice(&[
+            0xE0 | (ord >> 12) as u8,
+            0x80 | ((ord >> 6) & 0x3F) as u8,
+            0x80 | (ord & 0x3F) as u8,
+        ]),
+        0x10000..=0x1FFFFF => out.extend_from_slice(&[
+            0xF0 | (ord >> 18) as u8,
+            0x80 | ((ord >> 12) & 0x3F) as u8,
+            0x80 | ((ord >> 6) & 0x3F) as u8,
+            0x80 | (ord & 0x3F) as u8,
+        ]),
+        _ => out.push(0xFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(source: u32, target: u32, size: u32) -> Rule {
+        Rule::Char(Translator::Range { source, offset: source - target, size })
+    }
+
+    #[test]
+    fn ranges_cover_their_first_and_last_codepoints() {
+        let index: RangeIndex = RangeIndex::new(&[range(0x100, 0x41, 16)]);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.find(0x100), Some((0, 0x41)));
+        assert_eq!(index.find(0x10F), Some((0, 0x50)));
+        assert_eq!(index.find(0xFF), None);
+    }
+
+    #[test]
+    fn lookups_past_the_end_miss() {
+        let index: RangeIndex = RangeIndex::new(&[range(0x100, 0x41, 16), range(0x200, 0x61, 4)]);
+        assert_eq!(index.find(0x110), None);
+        assert_eq!(index.find(0x204), None);
+        assert_eq!(index.find(u32::MAX), None);
+        assert_eq!(RangeIndex::new(&[]).find(0x100), None);
+    }
+
+    #[test]
+    fn adjacent_ranges_stay_separate() {
+        let index: RangeIndex = RangeIndex::new(&[range(0x100, 0x41, 16), range(0x110, 0x61, 16)]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.find(0x10F), Some((0, 0x50)));
+        assert_eq!(index.find(0x110), Some((1, 0x61)));
+        assert_eq!(index.find(0x11F), Some((1, 0x70)));
+    }
+
+    #[test]
+    fn overlapping_ranges_belong_to_the_earliest_rule() {
+        let index: RangeIndex = RangeIndex::new(&[
+            range(0x100, 0x41, 32),
+            range(0x110, 0x61, 32),
+            range(0xF0, 0x30, 0x50),
+        ]);
+        // the last rule only keeps the pieces on either side of the first two
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.find(0xF0), Some((2, 0x30)));
+        assert_eq!(index.find(0xFF), Some((2, 0x3F)));
+        assert_eq!(index.find(0x100), Some((0, 0x41)));
+        assert_eq!(index.find(0x115), Some((0, 0x56)));
+        assert_eq!(index.find(0x11F), Some((0, 0x60)));
+        assert_eq!(index.find(0x120), Some((1, 0x71)));
+        assert_eq!(index.find(0x12F), Some((1, 0x80)));
+        assert_eq!(index.find(0x130), Some((2, 0x70)));
+        assert_eq!(index.find(0x13F), Some((2, 0x7F)));
+        assert_eq!(index.find(0x140), None);
+    }
+
+    #[test]
+    fn multiranges_are_indexed_one_slice_at_a_time() {
+        let rule: Rule = Rule::Char(Translator::MultiRange { source: 0x200, target: 0x41, size: 2,
+                                                             slice: 4, iters: 3 });
+        let index: RangeIndex = RangeIndex::new(&[rule]);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.find(0x200), Some((0, 0x41)));
+        assert_eq!(index.find(0x201), Some((0, 0x42)));
+        assert_eq!(index.find(0x202), None);
+        assert_eq!(index.find(0x205), Some((0, 0x42)));
+        assert_eq!(index.find(0x209), Some((0, 0x42)));
+        assert_eq!(index.find(0x20A), None);
+    }
+
+    #[test]
+    fn ranges_at_the_top_of_u32_do_not_overflow() {
+        let index: RangeIndex = RangeIndex::new(&[
+            Rule::Char(Translator::Range { source: u32::MAX - 3, offset: 0, size: 16 }),
+            Rule::Char(Translator::Range { source: u32::MAX - 7, offset: 0, size: 16 }),
+            Rule::Char(Translator::MultiRange { source: u32::MAX - 9, target: 0, size: 1,
+                                                slice: 4, iters: 1000 }),
+        ]);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.find(u32::MAX), Some((0, u32::MAX)));
+        assert_eq!(index.find(u32::MAX - 7), Some((1, u32::MAX - 7)));
+        assert_eq!(index.find(u32::MAX - 9), Some((2, 0)));
+        assert_eq!(index.find(u32::MAX - 8), None);
+    }
+
+    #[test]
+    fn sources_at_the_top_of_u32_do_not_overflow() {
+        let chain: Chain = Chain::from(vec![
+            Translator::Range { source: u32::MAX - 1, offset: 0, size: 16 },
+            Translator::Range { source: 0x10FFF8, offset: 0x10FFF8 - 0x41, size: 16 },
+            Translator::MultiRange { source: 0x10FFF0, target: 0x41, size: 8, slice: u32::MAX / 2,
+                                     iters: 4 },
+        ]);
+        let sources: &CharSet = chain.sources.as_ref().unwrap();
+        assert!(sources.contains(0x10FFF0));
+        assert!(sources.contains(0x10FFFF));
+        assert!(!sources.contains(0x10FFEF));
+        assert_eq!(chain.translate_str("\u{10FFF8}"), "A");
+    }
+
+    #[test]
+    fn other_rules_are_not_indexed() {
+        let index: RangeIndex = RangeIndex::new(&[
+            Rule::Char(Translator::AsciiFilter),
+            range(0x100, 0x41, 16),
+            Rule::Char(Translator::Range { source: 0x300, offset: 0, size: 0 }),
+        ]);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.others, vec![0]);
+        assert_eq!(index.find(0x100), Some((1, 0x41)));
+        assert_eq!(index.find(0x300), None);
+    }
+
+    #[test]
+    fn self_test_reports_failing_vectors() {
+        let mut chain: Chain = Chain::from(vec![
+            Translator::Range { source: 0xFF21, offset: 0xFF21 - 0x41, size: 26 },
+        ]);
+        let vector = |input: &str, expect: &str| {
+            TestVector { input: input.to_string(), expect: expect.to_string() }
+        };
+        chain.tests = vec![vector("ＡＢＣ", "ABC"), vector("ＸＹＺ", "xyz")];
+        let failures: Vec<TestFailure> = chain.self_test().unwrap_err();
+        assert_eq!(failures, vec![TestFailure { vector: vector("ＸＹＺ", "xyz"),
+                                                actual: Ok("XYZ".to_string()) }]);
+        assert_eq!(failures[0].to_string(), "\"ＸＹＺ\": expected \"xyz\", got \"XYZ\"");
+
+        chain.tests.pop();
+        assert_eq!(chain.self_test(), Ok(()));
+    }
+}
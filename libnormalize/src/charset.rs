@@ -0,0 +1,73 @@
+/// Compact sets of codepoints.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// How many codepoints each leaf of a [`CharSet`] covers (as a power of two).
+const LEAF_BITS: u32 = 12;
+
+/// The number of `u64` words in a leaf.
+const LEAF_WORDS: usize = 1 << (LEAF_BITS - 6);
+
+/// The number of leaves needed to cover every codepoint up to U+10FFFF.
+const LEAVES: usize = (0x110000 >> LEAF_BITS) as usize;
+
+/// A set of codepoints, stored as a two-level bitmap: the codepoint space is split into blocks of
+/// 4096, and each block points at a leaf of 4096 bits. Blocks with nothing in them all share one
+/// empty leaf, so a set of a few scattered scripts takes a few kilobytes, and checking whether a
+/// codepoint is in the set is two array lookups.
+#[derive(Debug, Clone)]
+pub struct CharSet {
+    /// The leaf for each block of codepoints. Leaf 0 is always empty.
+    blocks: Vec<u16>,
+    leaves: Vec<[u64; LEAF_WORDS]>,
+}
+
+impl Default for CharSet {
+    fn default() -> CharSet {
+        CharSet::new()
+    }
+}
+
+impl CharSet {
+    /// Create an empty set.
+    pub fn new() -> CharSet {
+        CharSet { blocks: vec![0; LEAVES], leaves: vec![[0; LEAF_WORDS]] }
+    }
+
+    /// Whether a codepoint is in the set.
+    pub fn contains(&self, ord: u32) -> bool {
+        let Some(&leaf) = self.blocks.get((ord >> LEAF_BITS) as usize) else { return false; };
+        let bit: usize = (ord & ((1 << LEAF_BITS) - 1)) as usize;
+        self.leaves[leaf as usize][bit >> 6] & (1 << (bit & 63)) != 0
+    }
+
+    /// Add a codepoint to the set. Values past U+10FFFF are ignored.
+    pub fn insert(&mut self, ord: u32) {
+        self.insert_range(ord, ord);
+    }
+
+    /// Add an inclusive range of codepoints to the set. The part of it past U+10FFFF is ignored.
+    pub fn insert_range(&mut self, start: u32, end: u32) {
+        for ord in start..=end.min(char::MAX as u32) {
+            let block: usize = (ord >> LEAF_BITS) as usize;
+            if self.blocks[block] == 0 {
+                self.blocks[block] = self.leaves.len() as u16;
+                self.leaves.push([0; LEAF_WORDS]);
+            }
+            let bit: usize = (ord & ((1 << LEAF_BITS) - 1)) as usize;
+            self.leaves[self.blocks[block] as usize][bit >> 6] |= 1 << (bit & 63);
+        }
+    }
+}
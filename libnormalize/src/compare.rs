@@ -0,0 +1,164 @@
+/// Comparison of two strings for confusability, by their skeletons.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+use crate::chain::Chain;
+use crate::detect;
+
+/// How two strings compare, from the closest to the furthest apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    /// They are the same string.
+    Identical,
+    /// They differ, but have the same skeleton, so they look alike.
+    Confusable,
+    /// Their skeletons differ, but are at least as similar as the threshold asks for.
+    Similar,
+    Different,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Identical => write!(f, "identical"),
+            Verdict::Confusable => write!(f, "confusable"),
+            Verdict::Similar => write!(f, "similar"),
+            Verdict::Different => write!(f, "different"),
+        }
+    }
+}
+
+/// One step in turning one string into another, character by character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Same(char),
+    /// A character of the first string replaced with one of the second.
+    Substitute(char, char),
+    /// A character only the second string has.
+    Insert(char),
+    /// A character only the first string has.
+    Delete(char),
+}
+
+/// The result of comparing two strings (see [`compare`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub skeletons: (String, String),
+    /// The shortest way to turn the first string into the second.
+    pub edits: Vec<Edit>,
+    /// How similar the skeletons are, from 0 (nothing in common) to 1 (the same).
+    pub similarity: f64,
+    pub verdict: Verdict,
+}
+
+/// The skeleton of a string: what it looks like, with look-alike characters made the same. This
+/// follows UTS #39, with the chain standing in for its table of confusables: the string is put in
+/// NFD, invisible characters and bidi controls are removed, it is translated with the chain (and
+/// lowercased if `ignore_case` is set), and the result is put in NFD again. Two strings with the
+/// same skeleton are confusable, as far as the chain knows.
+pub fn skeleton(chain: &Chain, source: &str, ignore_case: bool) -> String {
+    let visible: String = source.nfd()
+        .filter(|&c| !detect::is_invisible(c) && !detect::is_bidi_control(c))
+        .collect();
+    let skeleton: String = chain.translate_str(&visible);
+    let skeleton: String = if ignore_case { skeleton.to_lowercase() } else { skeleton };
+    skeleton.nfd().collect()
+}
+
+/// Compare two strings by their skeletons (see [`skeleton`]). With a `threshold` (from 0 to 1),
+/// strings whose skeletons differ but are at least that similar are [`Verdict::Similar`], which
+/// catches near misses like "paypa1.com".
+pub fn compare(chain: &Chain, a: &str, b: &str, ignore_case: bool, threshold: Option<f64>)
+    -> Comparison {
+    let skeletons: (String, String) = (skeleton(chain, a, ignore_case),
+                                       skeleton(chain, b, ignore_case));
+    let similarity: f64 = similarity(&skeletons.0, &skeletons.1);
+    let verdict: Verdict = match () {
+        _ if a == b => Verdict::Identical,
+        _ if skeletons.0 == skeletons.1 => Verdict::Confusable,
+        _ if threshold.is_some_and(|threshold| similarity >= threshold) => Verdict::Similar,
+        _ => Verdict::Different,
+    };
+    Comparison { skeletons, edits: diff(a, b), similarity, verdict }
+}
+
+/// How similar two strings are, from 0 to 1: one minus their edit distance (in characters) over
+/// the length of the longer one. Two empty strings are the same.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let longest: usize = a.chars().count().max(b.chars().count());
+    if longest == 0 { return 1.0; }
+    let distance: usize = diff(a, b).iter().filter(|edit| !matches!(edit, Edit::Same(_))).count();
+    1.0 - distance as f64 / longest as f64
+}
+
+/// Find the shortest way to turn `a` into `b` with single-character substitutions, insertions and
+/// deletions (the Levenshtein distance), as a list of edits in order. This takes time and memory
+/// proportional to the product of the lengths, so it is meant for short strings like names.
+pub fn diff(a: &str, b: &str) -> Vec<Edit> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i characters of a and the first j of b
+    let mut distances: Vec<Vec<usize>> = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() { row[0] = i; }
+    for (j, distance) in distances[0].iter_mut().enumerate() { *distance = j; }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution: usize = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            distances[i][j] = substitution
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+        }
+    }
+
+    let mut edits: Vec<Edit> = Vec::new();
+    let (mut i, mut j): (usize, usize) = (a.len(), b.len());
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0
+            && distances[i][j] == distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]) {
+            edits.push(if a[i - 1] == b[j - 1] { Edit::Same(a[i - 1]) }
+                       else { Edit::Substitute(a[i - 1], b[j - 1]) });
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && distances[i][j] == distances[i - 1][j] + 1 {
+            edits.push(Edit::Delete(a[i - 1]));
+            i -= 1;
+        } else {
+            edits.push(Edit::Insert(b[j - 1]));
+            j -= 1;
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    #[test]
+    fn skeletons_are_decomposed() {
+        let chain: Chain = config::parse_str("[[translator]]\ntype = \"lookup\"\nsource = \"аǝ\"\n\
+                                              target = \"aé\"\n", Format::Toml).unwrap();
+        // precomposed and decomposed forms have the same skeleton
+        assert_eq!(skeleton(&chain, "caf\u{E9}", false), "cafe\u{301}");
+        assert_eq!(skeleton(&chain, "cafe\u{301}", false), "cafe\u{301}");
+        // what the chain produces is decomposed too
+        assert_eq!(skeleton(&chain, "\u{1DD}", false), "e\u{301}");
+        assert_eq!(skeleton(&chain, "p\u{200B}\u{430}Y", true), "pay");
+    }
+}
@@ -14,31 +14,591 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use toml::Table;
+use std::cmp::Reverse;
+use std::env;
 use std::fs;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use indexmap::IndexMap;
 use regex::Regex;
-use toml::Value::Boolean;
-use crate::translators::{Translator, ascii_filter, range_translation, multirange_translation,
-                         lookup_translation};
+use serde::{Deserialize, Serialize};
+use crate::chain::{Chain, ExitPolicy, TestVector};
+use crate::detect::{Class, Severity};
+use crate::presets;
+use crate::translators::{Translator, Expander, ascii_filter, range_translation,
+                         multirange_translation, lookup_translation, map_translation,
+                         sequence_translation, delete_translation};
+
+/// The file formats a configuration can be written in. All formats share the same schema (see
+/// [`Config`]), so a configuration can be converted between them without changing its meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Guess the format of a configuration file from its extension. Anything that isn't
+    /// recognized is assumed to be TOML.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Toml,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            other => Err(format!("unknown config format \"{}\" (expected toml, json or yaml)",
+                                 other)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Toml => write!(f, "toml"),
+            Format::Json => write!(f, "json"),
+            Format::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+/// A parsed configuration file. This is the model shared by every supported [`Format`]; the
+/// `global` section and the `include` list are handled separately. Translators are listed in
+/// `translator` (the `[[translator]]` array of tables); for older configs, every other top-level
+/// section is also treated as a translator.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Built-in presets to load beneath this configuration and its includes, as with `--preset`
+    /// (see [`crate::presets::compose`]). Like includes, they are resolved while loading.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub presets: Vec<String>,
+    #[serde(default, skip_serializing_if = "Merge::is_extend")]
+    pub merge: Merge,
+    #[serde(default, skip_serializing_if = "Global::is_empty")]
+    pub global: Global,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translator: Vec<Section>,
+    #[serde(default, skip_serializing_if = "CharList::is_empty")]
+    pub allow: CharList,
+    #[serde(default, skip_serializing_if = "CharList::is_empty")]
+    pub deny: CharList,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub test: Vec<TestCase>,
+    /// Severities for detection classes, keyed by class name (see [`crate::detect::Class`]).
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub severity: IndexMap<String, String>,
+    /// Named profiles, each layered on top of the rest of the configuration when it is selected
+    /// (see [`Config::select_profile`]).
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub profile: IndexMap<String, Config>,
+    #[serde(flatten)]
+    pub sections: IndexMap<String, Section>,
+    /// Every file this configuration was loaded from, including includes. This is filled in while
+    /// loading rather than read from the file.
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Override global options with any that are set in the environment (see
+    /// [`Global::from_env`]). Environment variables take precedence over every config file.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.global.extend(Global::from_env()?);
+        Ok(())
+    }
+
+    /// Layer another configuration on top of this one. Global options that are set in `other`
+    /// override the ones in `self`; translator sections from `other` are appended, except that a
+    /// section with the same name as an existing one replaces it in place.
+    pub fn extend(&mut self, other: Config) {
+        self.global.extend(other.global);
+        other.translator.into_iter().for_each(|sect| {
+            let existing: Option<&mut Section> = sect.name.as_ref().and_then(|name| {
+                self.translator.iter_mut().find(|s| s.name.as_ref() == Some(name))
+            });
+            match existing {
+                Some(existing) => *existing = sect,
+                None => self.translator.push(sect),
+            }
+        });
+        other.sections.into_iter().for_each(|(name, sect)| {
+            self.sections.insert(name, sect);
+        });
+        self.allow.source.extend(other.allow.source);
+        self.deny.source.extend(other.deny.source);
+        self.test.extend(other.test);
+        self.severity.extend(other.severity);
+        other.profile.into_iter().for_each(|(name, profile)| match self.profile.get_mut(&name) {
+            Some(existing) => existing.extend(profile),
+            None => { self.profile.insert(name, profile); }
+        });
+        self.files.extend(other.files);
+    }
+
+    /// Apply one of the configuration's named profiles. The profile extends the rest of the
+    /// configuration the same way a config layer does, or replaces it if the profile sets
+    /// `merge = "replace"`. Every profile is removed afterwards, including the selected one.
+    ///
+    /// Profiles can load presets, but can't include other files or define profiles of their own;
+    /// a profile that tries to is an error.
+    pub fn select_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        let profile: Config = match self.profile.shift_remove(name) {
+            Some(profile) => profile,
+            None if self.profile.is_empty() => {
+                return Err(ConfigError::invalid("profile", name, "no profiles are defined"));
+            }
+            None => {
+                let names: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+                return Err(ConfigError::invalid("profile", name, format!(
+                    "no such profile (expected one of {})", names.join(", "))));
+            }
+        };
+        self.profile.clear();
+        if !profile.include.is_empty() {
+            return Err(ConfigError::invalid("profile", name, "profiles can't include other files"));
+        }
+        if !profile.profile.is_empty() {
+            return Err(ConfigError::invalid("profile", name, "profiles can't define profiles"));
+        }
+
+        // a profile's presets go beneath the rest of it, like a file's
+        let mut base: Config = presets::compose(&profile.presets)?;
+        base.merge = profile.merge;
+        base.extend(profile);
+        let profile: Config = base;
+        match profile.merge {
+            Merge::Extend => self.extend(profile),
+            Merge::Replace => {
+                let files: Vec<PathBuf> = std::mem::take(&mut self.files);
+                *self = Config { merge: Merge::Extend, files, ..profile };
+            }
+        }
+        Ok(())
+    }
+
+    /// Every translator section in file order, paired with a label for error messages: the
+    /// section's name if it has one, or its position in the `[[translator]]` array otherwise.
+    /// Array entries come before named top-level sections.
+    pub fn entries(&self) -> Vec<(String, &Section)> {
+        let array = self.translator.iter().enumerate().map(|(i, sect)| {
+            (sect.name.clone().unwrap_or_else(|| format!("translator[{}]", i)), sect)
+        });
+        let named = self.sections.iter().map(|(name, sect)| (name.clone(), sect));
+        array.chain(named).collect()
+    }
+}
+
+/// How a configuration layer is combined with the layers below it (see [`resolve`]).
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Merge {
+    /// Add to the layers below, overriding options and same-named sections.
+    #[default]
+    Extend,
+    /// Discard the layers below entirely.
+    Replace,
+}
+
+impl Merge {
+    fn is_extend(&self) -> bool {
+        *self == Merge::Extend
+    }
+}
+
+/// A single configuration in a stack of configurations, along with where it came from.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    /// A short description of the layer, such as "user" or "project".
+    pub name: String,
+    /// The file the layer was loaded from, if any.
+    pub path: Option<PathBuf>,
+    pub config: Config,
+}
+
+/// Merge a stack of configuration layers into one configuration. Layers are given from lowest to
+/// highest precedence, and each one is applied on top of the ones before it with
+/// [`Config::extend`], unless it sets `merge = "replace"`, in which case everything below it is
+/// discarded first.
+///
+/// The CLI stacks its layers in this order (so later ones win):
+/// 1. built-in defaults
+/// 2. the user's config (see [`user_config_path`])
+/// 3. the project's config (see [`project_config_path`])
+/// 4. an explicitly chosen config (`UTF_NORMALIZE_CONFIG`)
+///
+/// Environment variable overrides are applied after all of these (see [`Config::apply_env`]).
+pub fn resolve(layers: &[Layer]) -> Config {
+    let mut merged: Config = Config::default();
+    for layer in layers {
+        if layer.config.merge == Merge::Replace { merged = Config::default(); }
+        merged.extend(layer.config.clone());
+    }
+    merged.merge = Merge::Extend;
+    merged
+}
+
+/// Options from the "global" section. Options are optional so that included configs can tell
+/// whether a value was actually set; unset options fall back to their defaults when the chain is
+/// built.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Global {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_ascii_filter: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub protect: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub only: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_normalize: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_normalize: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<String>,
+}
+
+impl Global {
+    /// Whether no options are set, in which case the section is left out when serializing.
+    pub fn is_empty(&self) -> bool {
+        self.use_ascii_filter.is_none() && self.fallback.is_none() && self.replacement.is_none()
+            && self.protect.is_empty() && self.only.is_empty() && self.pre_normalize.is_none()
+            && self.post_normalize.is_none() && self.exit_code.is_none()
+    }
+
+    /// Override any options that are set in `other`. Protect and only patterns are added to the
+    /// existing ones rather than replacing them.
+    pub fn extend(&mut self, other: Global) {
+        if other.use_ascii_filter.is_some() { self.use_ascii_filter = other.use_ascii_filter; }
+        if other.fallback.is_some() { self.fallback = other.fallback; }
+        if other.replacement.is_some() { self.replacement = other.replacement; }
+        self.protect.extend(other.protect);
+        self.only.extend(other.only);
+        if other.pre_normalize.is_some() { self.pre_normalize = other.pre_normalize; }
+        if other.post_normalize.is_some() { self.post_normalize = other.post_normalize; }
+        if other.exit_code.is_some() { self.exit_code = other.exit_code; }
+    }
+
+    /// Read global options from `UTF_NORMALIZE_*` environment variables. Only variables that are
+    /// set are filled in, so the result can be layered over a config file with
+    /// [`Global::extend`]. The recognized variables are:
+    /// - `UTF_NORMALIZE_ASCII_FILTER`: `use_ascii_filter` (`1`/`true`/`yes`/`on` or
+    ///   `0`/`false`/`no`/`off`)
+    /// - `UTF_NORMALIZE_FALLBACK`: `fallback`
+    /// - `UTF_NORMALIZE_REPLACEMENT`: `replacement`
+    /// - `UTF_NORMALIZE_PROTECT`: `protect`, one pattern per line; these are added to the config's
+    ///   patterns like any other protect patterns
+    /// - `UTF_NORMALIZE_PRE_NORMALIZE`: `pre_normalize`
+    /// - `UTF_NORMALIZE_POST_NORMALIZE`: `post_normalize`
+    /// - `UTF_NORMALIZE_EXIT_CODE`: `exit_code`
+    pub fn from_env() -> Result<Global, ConfigError> {
+        let mut global: Global = Global::default();
+        if let Some(value) = env_var("UTF_NORMALIZE_ASCII_FILTER") {
+            global.use_ascii_filter = Some(env_bool("UTF_NORMALIZE_ASCII_FILTER", &value)?);
+        }
+        global.fallback = env_var("UTF_NORMALIZE_FALLBACK");
+        global.replacement = env_var("UTF_NORMALIZE_REPLACEMENT");
+        if let Some(value) = env_var("UTF_NORMALIZE_PROTECT") {
+            global.protect = value.lines().filter(|line| !line.is_empty()).map(str::to_string)
+                .collect();
+        }
+        global.pre_normalize = env_var("UTF_NORMALIZE_PRE_NORMALIZE");
+        global.post_normalize = env_var("UTF_NORMALIZE_POST_NORMALIZE");
+        global.exit_code = env_var("UTF_NORMALIZE_EXIT_CODE");
+        Ok(global)
+    }
+}
+
+/// A fully commented starter config, with an example of every section type. It is valid as-is,
+/// and its test vectors pass.
+pub const TEMPLATE: &str = include_str!("template.toml");
+
+/// The environment variable that names a config file to use when none is given explicitly.
+pub const CONFIG_ENV: &str = "UTF_NORMALIZE_CONFIG";
+
+/// The config file named by the `UTF_NORMALIZE_CONFIG` environment variable, if it is set.
+pub fn env_config_path() -> Option<PathBuf> {
+    env_var(CONFIG_ENV).map(PathBuf::from)
+}
+
+/// The names a config file is looked for under in a config directory, in order of preference.
+pub const CONFIG_NAMES: [&str; 4] = ["config.toml", "config.json", "config.yaml", "config.yml"];
+
+/// Find the user's default config file: `utf-normalize/config.toml` (or `.json`/`.yaml`/`.yml`)
+/// inside the platform's config directory. That is `$XDG_CONFIG_HOME` (or `~/.config`) on Linux,
+/// `~/Library/Application Support` on macOS, and `%APPDATA%` on Windows. Returns `None` if there
+/// is no such file.
+pub fn user_config_path() -> Option<PathBuf> {
+    let dir: PathBuf = dirs::config_dir()?.join("utf-normalize");
+    CONFIG_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+/// The names of project-local config files, in order of preference.
+pub const PROJECT_CONFIG_NAMES: [&str; 4] = [".utf-normalize.toml", ".utf-normalize.json",
+                                             ".utf-normalize.yaml", ".utf-normalize.yml"];
+
+/// Find a project-local config file (`.utf-normalize.toml`, or `.json`/`.yaml`/`.yml`) by searching
+/// `start` and then each of its parent directories in turn, like `.editorconfig`. The closest one
+/// wins. `start` can be a file, in which case the search begins in the directory containing it.
+pub fn project_config_path(start: &Path) -> Option<PathBuf> {
+    let start: PathBuf = fs::canonicalize(start).unwrap_or(start.to_path_buf());
+    let first: &Path = if start.is_file() { start.parent()? } else { &start };
+    first.ancestors()
+        .flat_map(|dir| PROJECT_CONFIG_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// Read an environment variable, treating an empty value the same as an unset one.
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Parse a boolean environment variable.
+fn env_bool(name: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        other => Err(ConfigError::invalid("env", name, format!(
+            "\"{}\" is not a boolean (expected 1/true/yes/on or 0/false/no/off)", other))),
+    }
+}
+
+/// A list of characters and ranges, used by the "allow" and "deny" sections. Entries use the same
+/// notation as range sections (see [`parse_range`]).
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct CharList {
+    pub source: Vec<String>,
+}
+
+impl CharList {
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+}
+
+/// A test vector (one entry of the `[[test]]` array of tables).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TestCase {
+    pub input: String,
+    pub expect: String,
+}
+
+/// A single translator section. Every section can have a `priority`; the rest of its fields depend
+/// on its `type` (see [`SectionKind`]).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Section {
+    /// An optional name, used in error messages and to replace a section from an included file.
+    /// Top-level sections are named by their table header instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Sections with a higher priority are placed earlier in the chain, and so win when more than
+    /// one section handles the same character. Sections with equal priority keep their file order.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    #[serde(flatten)]
+    pub kind: SectionKind,
+    /// Where the section was defined. This is filled in while loading rather than read from the
+    /// file.
+    #[serde(skip)]
+    pub origin: Origin,
+}
+
+fn is_zero(n: &i32) -> bool {
+    *n == 0
+}
+
+/// Where a translator section was defined, used to point errors at a line and column.
+#[derive(Debug, Clone, Default)]
+pub struct Origin {
+    /// The file the section came from, and its format. This is `None` for configs parsed from a
+    /// string.
+    pub file: Option<(PathBuf, Format)>,
+    /// The section's position in its file's `[[translator]]` array, if it came from there.
+    pub index: Option<usize>,
+    /// The section's table name, if it was a named top-level section.
+    pub key: Option<String>,
+    /// The profile the section was defined in, if any.
+    pub profile: Option<String>,
+}
+
+/// A position within a configuration file. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+/// The translator-specific part of a section. The `type` field selects which translator is
+/// generated; see the translators module for what each field means.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SectionKind {
+    Range {
+        source: String,
+        target: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u32>,
+    },
+    Multirange {
+        source: String,
+        target: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u32>,
+        slice: u32,
+        iters: u32,
+    },
+    Lookup {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+        #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+        map: IndexMap<String, String>,
+    },
+    Delete { source: Vec<String> },
+}
 
 /// Parses a configuration file.
 /// ## Format
-/// Configuration files are written in TOML format. Each translator is defined as its own section,
-/// in a format like so:
+/// Configuration files are written in TOML format by default. Each translator is an entry in the
+/// `translator` array of tables, in a format like so:
 /// ```toml
 /// # Sample translator to make any lowercase letters uppercase and vice versa.
-/// [translator_1] # The section can be anything. Just make sure that it isn't "global".
-/// type = "range" # Can be one of either "lookup", "range", or "multirange"
+/// [[translator]]
+/// name = "to_lower" # Optional; used in error messages.
+/// type = "range" # Can be one of "lookup", "range", "multirange", or "delete"
 /// source = 'A' # See the translators module for each translator's config values.
 /// target = 'a'
 /// size = 26
 ///
-/// [translator_2]
+/// [[translator]]
 /// type = "range"
 /// source = 'a'
 /// target = 'A'
 /// size = 26
 /// ```
+/// The same schema can also be written in JSON, which is selected by a `.json` extension (or
+/// explicitly with [`parse_with_format`]):
+/// ```json
+/// {
+///   "global": { "use_ascii_filter": true },
+///   "translator": [
+///     { "type": "range", "source": "A", "target": "a", "size": 26 }
+///   ]
+/// }
+/// ```
+/// YAML is also supported, and is selected by a `.yaml` or `.yml` extension:
+/// ```yaml
+/// global:
+///   use_ascii_filter: true
+/// translator:
+///   - type: range
+///     source: A
+///     target: a
+///     size: 26
+/// ```
+/// ### Named Sections
+/// Older configs define each translator as its own named top-level section instead. These are
+/// still accepted, and are applied after any `[[translator]]` entries. The section can be named
+/// anything except "global", "include", "presets", "merge", "translator", "allow", "deny", "test",
+/// "severity" or "profile".
+/// ```toml
+/// [translator_1]
+/// type = "range"
+/// source = 'A'
+/// target = 'a'
+/// size = 26
+/// ```
+/// ## Ordering
+/// Translators are tried in order, and the first one that handles a character wins. By default,
+/// sections are applied in the order they appear in the file, but a section can set a `priority`
+/// to move it ahead of (or behind) the others. Higher priorities come first; the default is 0.
+/// ```toml
+/// [[translator]]
+/// type = "lookup"
+/// priority = 10 # checked before any section without a priority
+/// source = "ℌ"
+/// target = "H"
+/// ```
+/// ## Allow and Deny Lists
+/// The "allow" section lists characters that are never translated, even if a translator handles
+/// them. The "deny" section lists characters that are not allowed in the input at all; see
+/// [`Chain::try_translate_str`]. Both take a `source` list in the same notation as delete sections.
+/// ```toml
+/// [allow]
+/// source = ["é", "U+00C0..U+00FF"]
+///
+/// [deny]
+/// source = ["U+202A..U+202E", "U+2066..U+2069"] # bidirectional overrides
+/// ```
+/// ## Test Vectors
+/// A config can carry its own regression tests as a `[[test]]` array of tables. Each test gives an
+/// input and the exact output the chain should produce for it; they are run by
+/// [`Chain::self_test`].
+/// ```toml
+/// [[test]]
+/// input = "раураl"
+/// expect = "paypal"
+/// ```
+/// ## Severities
+/// The "severity" section sets how seriously each class of suspicious characters is taken by
+/// check and scan modes: "off", "info", "warn" or "error". Findings with severity "error" make
+/// those modes fail. The classes are `bidi_controls`, `invisible`, `confusables`,
+/// `mixed_scripts` and `denied` (see [`crate::detect::Class`]); any that aren't listed keep their
+/// defaults, which are shown here.
+/// ```toml
+/// [severity]
+/// bidi_controls = "error"
+/// invisible = "warn"
+/// confusables = "info"
+/// mixed_scripts = "warn"
+/// denied = "error"
+/// ```
+/// ## Profiles
+/// One file can hold several policies as named profiles under the "profile" table. A profile can
+/// contain anything a config file can, except includes and other profiles, and is layered on top
+/// of the rest of the file when it is selected (with `--profile` on the command line, or
+/// [`Config::select_profile`]). Profiles that aren't selected are ignored.
+/// ```toml
+/// [profile.strict.global]
+/// fallback = "error"
+///
+/// [profile.lenient]
+/// merge = "replace" # start from scratch rather than from the rest of the file
+/// global = { fallback = "keep" }
+/// ```
+/// ## Layering
+/// Several configs can apply at once (see [`resolve`]). Normally each one extends the ones with
+/// lower precedence, but a config can discard them and start from scratch instead:
+/// ```toml
+/// merge = "replace" # the default is "extend"
+/// ```
 /// ## Global Fields
 /// The "global" section handles global configs.
 /// ```toml
@@ -47,105 +607,953 @@ use crate::translators::{Translator, ascii_filter, range_translation, multirange
 /// ```
 /// ### Options
 /// - `use_ascii_filter: boolean`: Determines whether [`ascii_filter`] will be applied.
-fn parse(path: String) {
-    let data: String = fs::read_to_string(path).unwrap();
-    let mut config: Table = toml::from_str(&data).unwrap();
+/// - `fallback: string`: What to do with non-ASCII characters that no translator handles: "keep"
+///   (the default), "drop", "replace", "escape" or "error" (see [`crate::chain::Fallback`]).
+/// - `replacement: string`: What unhandled characters are replaced with when `fallback` is
+///   "replace". Defaults to "?".
+/// - `protect: [string]`: Regular expressions for protected regions, such as URLs or inline code.
+///   Text matched by any of them is passed through untouched: it isn't translated, and it isn't
+///   checked against the deny list or the fallback policy.
+/// - `only: [string]`: Regular expressions that scope translation to the regions they match, such
+///   as quoted strings. If any are given, everything outside their matches is protected.
+///
+/// - `pre_normalize: string`: The Unicode normalization form applied to the input before it is
+///   translated: "NFC", "NFD", "NFKC", "NFKD" or "none" (the default). "NFKD" takes care of
+///   ligatures and accents, so translators only need to handle the base characters.
+/// - `post_normalize: string`: The normalization form applied to the output, in the same
+///   notation. "NFC" recomposes anything the translators (or `pre_normalize`) left decomposed.
+/// - `exit_code: string`: When the command line tool fails (see [`crate::chain::ExitPolicy`]):
+///   "default", "never" (only on errors), "changes" (whenever anything is changed or found), or
+///   "info", "warn" or "error" (on findings of at least that severity).
+///
+/// ```toml
+/// [global]
+/// pre_normalize = "NFKD"
+/// post_normalize = "NFC"
+/// protect = ["https?://\\S+", "`[^`]*`"]
+/// ```
+///
+/// Global options can also be overridden with `UTF_NORMALIZE_*` environment variables (see
+/// [`Global::from_env`]). These aren't read by the parse functions themselves; applications opt in
+/// with [`Config::apply_env`].
+///
+/// ## Includes
+/// A configuration can pull in other configuration files with a top-level `include` list. Paths
+/// are relative to the file that includes them, and may be in any supported format.
+/// ```toml
+/// include = ["base.toml", "cyrillic.toml"]
+/// ```
+/// Includes are resolved depth-first, in the order they are listed, and the including file is
+/// applied last. This means that translators from included files come first in the chain (in
+/// include order), and the including file's own sections come after them. Global options set in
+/// a later file override earlier ones, and a section with the same name as an earlier section
+/// replaces it in place. A file that (directly or indirectly) includes itself is an error
+/// ([`ConfigError::IncludeCycle`]).
+///
+/// ## Ranges
+/// Instead of counting out `size` by hand, range and multirange sections can write `source` (or
+/// `target`) as an inclusive range, and the size is derived from it:
+/// ```toml
+/// [[translator]]
+/// name = "bold_upper"
+/// type = "range"
+/// source = "U+1D400..U+1D419"
+/// target = 'A'
+/// ```
+/// If more than one of `size`, a `source` range, or a `target` range is given, they must agree.
+///
+/// ## Lookup Tables
+/// Lookup sections pair up the characters of `source` and `target` by position, so both strings
+/// must contain the same number of characters. Codepoints can be written as escapes (in the format
+/// `\u{F0000}`) inside either string:
+/// ```toml
+/// [[translator]]
+/// name = "cyrillic"
+/// type = "lookup"
+/// source = 'а\u{0435}о'
+/// target = "aeo"
+/// ```
+/// To replace a character with more than one character (or with any string), add a `map` table
+/// to the lookup section. Keys are single characters (in any notation [`parse_codepoint`]
+/// accepts), and values are the replacement strings:
+/// ```toml
+/// [[translator]]
+/// name = "expansions"
+/// type = "lookup"
+/// map = { "½" = "1/2", "ﬁ" = "fi", "U+2026" = "..." }
+/// ```
+/// Long runs of contiguous characters can be written as a character class instead of listing
+/// every character. Inside the brackets, `a-z` is an inclusive range and anything else stands for
+/// itself, so these two are the same:
+/// ```toml
+/// [[translator]]
+/// name = "small_caps"
+/// type = "lookup"
+/// source = "[ᴀʙᴄᴅᴇ]"
+/// target = "[a-e]"
+/// ```
+/// To look up a string that really starts with `[` and ends with `]`, escape the first bracket
+/// as `\u{5B}`.
+///
+/// Keys of more than one character (that aren't a codepoint in some notation) are sequences: the
+/// longest key the text starts with is replaced, as long as no earlier translator handles its
+/// first character (see [`crate::translators::sequence_translation`]):
+/// ```toml
+/// [[translator]]
+/// name = "transliterate"
+/// type = "lookup"
+/// map = { "ый" = "iy", "й" = "y", "щ" = "shch" }
+/// ```
+///
+/// A lookup section can have `source`/`target`, `map`, or both; the one-to-one table is checked
+/// before the map.
+///
+/// ## Deletion
+/// Delete sections remove characters from the output entirely. `source` is a list of characters
+/// or ranges (in the same notation as range sections):
+/// ```toml
+/// [[translator]]
+/// name = "invisible"
+/// type = "delete"
+/// source = ["U+200B..U+200D", "U+2060", "U+FEFF"]
+/// ```
+/// Mapping a character to an empty string in a lookup `map` has the same effect.
+///
+/// ## Errors
+/// Any problem with the configuration is returned as a [`ConfigError`], which names the section
+/// and field at fault where there is one. For TOML files, the line and column of the field are
+/// included too (e.g. `translator[2].target: ... at dev.toml:14:10`); syntax errors from the TOML,
+/// JSON and YAML parsers carry their own positions.
+pub fn parse(path: String) -> Result<Chain, ConfigError> {
+    let format: Format = Format::from_path(Path::new(&path));
+    parse_with_format(path, format)
+}
+
+/// Parses a configuration file in an explicitly specified format, regardless of its extension.
+pub fn parse_with_format(path: String, format: Format) -> Result<Chain, ConfigError> {
+    build(&load(Path::new(&path), format)?)
+}
+
+/// Parses configuration data that has already been read into memory. Includes are resolved
+/// relative to the current working directory.
+pub fn parse_str(data: &str, format: Format) -> Result<Chain, ConfigError> {
+    let mut includes: Includes = Includes::default();
+    build(&resolve_includes(from_str(data, format)?, Path::new("."), &mut includes)?)
+}
+
+/// Deserialize configuration data without resolving its includes.
+pub fn from_str(data: &str, format: Format) -> Result<Config, ConfigError> {
+    let syntax = |message: String| ConfigError::Syntax { path: None, format, message };
+    let mut config: Config = match format {
+        Format::Toml => toml::from_str(data).map_err(|e| syntax(e.to_string())),
+        Format::Json => serde_json::from_str(data).map_err(|e| syntax(e.to_string())),
+        Format::Yaml => serde_yaml::from_str(data).map_err(|e| syntax(e.to_string())),
+    }?;
+
+    set_origins(&mut config, None);
+    config.profile.iter_mut().for_each(|(name, profile)| set_origins(profile, Some(name)));
+    Ok(config)
+}
+
+/// Record where each translator section of a config was defined within its file.
+fn set_origins(config: &mut Config, profile: Option<&str>) {
+    config.translator.iter_mut().enumerate().for_each(|(i, sect)| sect.origin.index = Some(i));
+    config.sections.iter_mut().for_each(|(key, sect)| sect.origin.key = Some(key.clone()));
+    config.translator.iter_mut().chain(config.sections.values_mut()).for_each(|sect| {
+        sect.origin.profile = profile.map(str::to_string);
+    });
+}
+
+/// Serialize a configuration in the given format. This is the inverse of [`from_str`], except that
+/// section origins are not preserved.
+pub fn to_string(config: &Config, format: Format) -> Result<String, ConfigError> {
+    let syntax = |message: String| ConfigError::Syntax { path: None, format, message };
+    match format {
+        Format::Toml => toml::to_string(config).map_err(|e| syntax(e.to_string())),
+        Format::Json => serde_json::to_string_pretty(config).map_err(|e| syntax(e.to_string())),
+        Format::Yaml => serde_yaml::to_string(config).map_err(|e| syntax(e.to_string())),
+    }
+}
+
+/// Load a configuration file and everything it includes into a single [`Config`].
+pub fn load(path: &Path, format: Format) -> Result<Config, ConfigError> {
+    let mut includes: Includes = Includes::default();
+    load_file(path, format, &mut includes)
+}
+
+/// The files an include tree is being loaded from.
+#[derive(Debug, Default)]
+struct Includes {
+    /// The canonical path of each file whose includes are being resolved, outermost first. A file
+    /// that includes one of these is part of a cycle.
+    stack: Vec<PathBuf>,
+    /// The canonical path of every file loaded so far. A file that is included again along another
+    /// branch of the tree (as in a diamond) is only merged in the first time.
+    loaded: Vec<PathBuf>,
+}
+
+/// Load a single file as part of an include tree, recording it in `includes`.
+fn load_file(path: &Path, format: Format, includes: &mut Includes)
+    -> Result<Config, ConfigError> {
+    let data: String = fs::read_to_string(path)
+        .map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+    let canonical: PathBuf = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    let base_dir: &Path = path.parent().unwrap_or(Path::new("."));
+
+    let mut own: Config = from_str(&data, format).map_err(|e| e.in_file(path))?;
+    let profiles = own.profile.values_mut()
+        .flat_map(|profile| profile.translator.iter_mut().chain(profile.sections.values_mut()));
+    own.translator.iter_mut().chain(own.sections.values_mut()).chain(profiles).for_each(|sect| {
+        sect.origin.file = Some((path.to_path_buf(), format));
+    });
+    own.files.push(path.to_path_buf());
+    includes.loaded.push(canonical.clone());
+    includes.stack.push(canonical);
+    let config: Result<Config, ConfigError> = resolve_includes(own, base_dir, includes);
+    includes.stack.pop();
+
+    config
+}
+
+/// Replace a configuration's preset and include lists with the presets and the contents of the
+/// files they name, skipping any files that were already merged in through another include.
+fn resolve_includes(own: Config, base_dir: &Path, includes: &mut Includes)
+    -> Result<Config, ConfigError> {
+    let mut merged: Config = presets::compose(&own.presets)?;
+    let merge: Merge = own.merge;
+
+    for include in &own.include {
+        let inc_path: PathBuf = base_dir.join(include);
+        let inc_canonical: PathBuf = fs::canonicalize(&inc_path).unwrap_or(inc_path.clone());
+        if includes.stack.contains(&inc_canonical) {
+            return Err(ConfigError::IncludeCycle { path: inc_path });
+        }
+        if includes.loaded.contains(&inc_canonical) { continue; }
+        let included: Config = load_file(&inc_path, Format::from_path(&inc_path), includes)?;
+        merged.extend(included);
+    }
+
+    merged.extend(Config { include: Vec::new(), presets: Vec::new(), ..own });
+    merged.merge = merge;
+    Ok(merged)
+}
 
-    let mut translators: Vec<Translator> = Vec::new();
+/// Generate the translator chain described by a configuration. Stops at the first invalid
+/// section.
+pub fn build(config: &Config) -> Result<Chain, ConfigError> {
+    let mut translators: Chain = Chain::new();
 
     // deal with the default config parameters
-    let use_af: bool = config.get("global.use_ascii_filter").unwrap_or(&Boolean(false))
-        .as_bool()
-        .unwrap_or(false);
-
-    if use_af { translators.push(ascii_filter()); }
-
-    config.keys().for_each(|section| {
-        let sect_table: &Table = config.get(section).unwrap().as_table().unwrap();
-        if (section != "global") {
-            let t_type: String = sect_table.get("type").unwrap().as_str().unwrap()
-                .to_string();
-
-            match t_type.as_str() {
-                "range" => {
-                    let t: Translator = parse_rt(sect_table, section);
-                    translators.push(t);
+    if config.global.use_ascii_filter.unwrap_or(false) {
+        translators.begin_group("use_ascii_filter");
+        translators.push(ascii_filter());
+    }
+    if let Some(fallback) = &config.global.fallback {
+        translators.fallback = fallback.parse()
+            .map_err(|e: String| ConfigError::invalid("global", "fallback", e))?;
+    }
+    if let Some(replacement) = &config.global.replacement {
+        translators.replacement = unescape(replacement, "global", "replacement")?;
+    }
+    if let Some(form) = &config.global.pre_normalize {
+        translators.pre_normalize = form.parse()
+            .map_err(|e: String| ConfigError::invalid("global", "pre_normalize", e))?;
+    }
+    if let Some(form) = &config.global.post_normalize {
+        translators.post_normalize = form.parse()
+            .map_err(|e: String| ConfigError::invalid("global", "post_normalize", e))?;
+    }
+    if let Some(policy) = &config.global.exit_code {
+        translators.exit_code = policy.parse::<ExitPolicy>()
+            .map_err(|e: String| ConfigError::invalid("global", "exit_code", e))?;
+    }
+    for pattern in &config.global.protect {
+        translators.protect.push(Regex::new(pattern)
+            .map_err(|e| ConfigError::invalid("global", "protect", e.to_string()))?);
+    }
+    for pattern in &config.global.only {
+        translators.only.push(Regex::new(pattern)
+            .map_err(|e| ConfigError::invalid("global", "only", e.to_string()))?);
+    }
+
+    translators.allow.extend(parse_ranges(&config.allow.source, "allow", "source")?);
+    translators.deny.extend(parse_ranges(&config.deny.source, "deny", "source")?);
+    for case in &config.test {
+        let input: String = unescape(&case.input, "test", "input")?;
+        let expect: String = unescape(&case.expect, "test", "expect")?;
+        translators.tests.push(TestVector { input, expect });
+    }
+    for (name, severity) in &config.severity {
+        let class: Class = name.parse()
+            .map_err(|e: String| ConfigError::invalid("severity", name, e))?;
+        let severity: Severity = severity.parse()
+            .map_err(|e: String| ConfigError::invalid("severity", name, e))?;
+        translators.severity.set(class, severity);
+    }
+
+    // sort_by_key is stable, so sections with the same priority stay in file order
+    let mut sections: Vec<(String, &Section)> = config.entries();
+    sections.sort_by_key(|(_, sect)| Reverse(sect.priority));
+
+    for (section, sect) in sections {
+        translators.begin_group(&section);
+        build_section(&mut translators, section.as_str(), sect)
+            .map_err(|e| e.locate(&sect.origin))?;
+    }
+    translators.index();
+
+    Ok(translators)
+}
+
+/// Generate the translator(s) for a single section and add them to the chain.
+fn build_section(translators: &mut Chain, section: &str, sect: &Section)
+    -> Result<(), ConfigError> {
+    match &sect.kind {
+        SectionKind::Range { source, target, size } => {
+            translators.push(parse_rt(source, target, *size, section)?);
+        }
+        SectionKind::Multirange { source, target, size, slice, iters } => {
+            translators.push(parse_mrt(source, target, *size, *slice, *iters, section)?);
+        }
+        SectionKind::Lookup { source, target, map } => {
+            match (source, target) {
+                (Some(source), Some(target)) => {
+                    translators.push(parse_lut(source, target, section)?);
                 }
-                "multirange" => {
-                    let t: Translator = parse_mrt(sect_table, section);
-                    translators.push(t);
+                (None, None) => {}
+                (None, Some(_)) => {
+                    return Err(ConfigError::invalid(section, "target",
+                                                    "a target was given without a source"));
                 }
-                "lookup" => {
-                    let t: Translator = parse_lut(sect_table, section);
-                    translators.push(t);
+                (Some(_), None) => {
+                    return Err(ConfigError::invalid(section, "source",
+                                                    "a source was given without a target"));
                 }
-                value => {
-                    handle_error_val("Invalid type", section, value);
+            }
+            if !map.is_empty() {
+                translators.push_expander(parse_map(map, section)?);
+            }
+        }
+        SectionKind::Delete { source } => {
+            translators.push_expander(parse_dt(source, section)?);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_rt(src_str: &str, trg_str: &str, size: Option<u32>, section: &str)
+    -> Result<Translator, ConfigError> {
+    let (source, target, size) = getspan(src_str, trg_str, size, section)?;
+
+    Ok(range_translation(source, target, size))
+}
+
+fn parse_mrt(src_str: &str, trg_str: &str, size: Option<u32>, slice: u32, iters: u32,
+             section: &str) -> Result<Translator, ConfigError> {
+    let (source, target, size) = getspan(src_str, trg_str, size, section)?;
+    if slice == 0 { return Err(ConfigError::invalid(section, "slice", "must be at least 1")); }
+    // the slices must all start at codepoints, or the translator would overflow looking for them
+    let last: Option<u32> = iters.checked_sub(1)
+        .map_or(Some(0), |i| i.checked_mul(slice))
+        .and_then(|span| (source as u32).checked_add(span));
+    if last.is_none_or(|last| last > char::MAX as u32) {
+        return Err(ConfigError::invalid(section, "iters", format!(
+            "{} slices of {} starting at U+{:04X} run past U+10FFFF", iters, slice,
+            source as u32)));
+    }
+
+    Ok(multirange_translation(source, target, size, slice, iters))
+}
+
+/// Work out the start of the source and target ranges, and the size of the range, from a section.
+/// The size can be given explicitly or derived from a range in `source` or `target`; if more than
+/// one of them is given, they must all agree.
+fn getspan(src_str: &str, trg_str: &str, size: Option<u32>, section: &str)
+    -> Result<(char, char, u32), ConfigError> {
+    let (source, src_size) = getrange(src_str, section, "source")?;
+    let (target, trg_size) = getrange(trg_str, section, "target")?;
+
+    let sizes: Vec<(&str, u32)> = [("size", size), ("source", src_size), ("target", trg_size)]
+        .into_iter()
+        .filter_map(|(name, s)| s.map(|s| (name, s)))
+        .collect();
+
+    match sizes.first() {
+        None => Err(ConfigError::invalid(section, "size", "missing (either set \"size\", or \
+            write source as a range like \"U+1D400..U+1D419\")")),
+        Some(&(first_name, first)) => {
+            for &(name, other) in &sizes[1..] {
+                if other != first {
+                    return Err(ConfigError::invalid(section, name, format!(
+                        "range size {} does not match {} ({})", other, first_name, first)));
+                }
+            }
+            // a size given for one end (or written out by hand) can carry the other end past the
+            // last codepoint
+            for (name, start) in [("source", source), ("target", target)] {
+                let end: u64 = start as u64 + first as u64;
+                if first > 0 && end - 1 > char::MAX as u64 {
+                    return Err(ConfigError::invalid(section, name, format!(
+                        "range of {} starting at U+{:04X} runs past U+10FFFF", first,
+                        start as u32)));
+                }
+            }
+            Ok((source, target, first))
+        }
+    }
+}
+
+fn parse_lut(src_str: &str, trg_str: &str, section: &str) -> Result<Translator, ConfigError> {
+    let source: String = expand_class(src_str, section, "source")?;
+    let target: String = expand_class(trg_str, section, "target")?;
+
+    // count characters rather than bytes, since most of the interesting characters are multibyte
+    let src_len: usize = source.chars().count();
+    let trg_len: usize = target.chars().count();
+    if src_len != trg_len {
+        return Err(ConfigError::invalid(section, "target", format!(
+            "has {} characters, but source has {} (they must be equal)", trg_len, src_len)));
+    }
+
+    Ok(lookup_translation(&source, &target))
+}
+
+fn parse_map(map: &IndexMap<String, String>, section: &str) -> Result<Expander, ConfigError> {
+    let mut pairs: Vec<(String, String)> = Vec::with_capacity(map.len());
+    let mut sequences: bool = false;
+    for (src_str, trg_str) in map {
+        let source: String = match parse_codepoint(src_str) {
+            Ok(c) => c.to_string(),
+            // a key of more than one character is a sequence, matched as it is written
+            Err(CodepointError::Malformed(e)) => {
+                let source: String = unescape(src_str, section, "map")?;
+                if source.chars().nth(1).is_none() {
+                    let e: CodepointError = CodepointError::Malformed(e);
+                    return Err(ConfigError::invalid(section, "map", e.to_string()));
                 }
+                sequences = true;
+                source
+            }
+            Err(e) => return Err(ConfigError::invalid(section, "map", e.to_string())),
+        };
+        let target: String = unescape(trg_str, section, "map")?;
+        pairs.push((source, target));
+    }
+
+    if sequences { return Ok(sequence_translation(pairs)); }
+    let pairs: Vec<(char, String)> = pairs.into_iter()
+        .filter_map(|(source, target)| Some((source.chars().next()?, target)))
+        .collect();
+    Ok(map_translation(pairs))
+}
+
+fn parse_dt(source: &[String], section: &str) -> Result<Expander, ConfigError> {
+    Ok(delete_translation(parse_ranges(source, section, "source")?))
+}
+
+/// Convert a list of characters and ranges into inclusive `(start, end)` pairs.
+fn parse_ranges(source: &[String], section: &str, field: &str)
+    -> Result<Vec<(char, char)>, ConfigError> {
+    let mut ranges: Vec<(char, char)> = Vec::with_capacity(source.len());
+    for src_str in source {
+        let (start, size) = getrange(src_str, section, field)?;
+        let end: char = char::from_u32(start as u32 + size.unwrap_or(1) - 1).unwrap_or(start);
+        ranges.push((start, end));
+    }
+
+    Ok(ranges)
+}
+
+/// Expand a lookup string written as a character class, like `[ᴀ-ᴢ0-9]`, into the characters it
+/// contains in order. Each `a-z` inside the brackets is an inclusive range, and anything else is a
+/// literal character; a `-` at the start or end of the class is literal too. Codepoint escapes are
+/// expanded before the class is. Strings that aren't a class (that don't start with `[` and end
+/// with `]` with something in between) are only unescaped.
+fn expand_class(input: &str, section: &str, field: &str) -> Result<String, ConfigError> {
+    let body: &str = match input.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(body) if !body.is_empty() => body,
+        _ => return unescape(input, section, field),
+    };
+
+    let chars: Vec<char> = unescape(body, section, field)?.chars().collect();
+    let mut result: String = String::with_capacity(chars.len());
+    let mut i: usize = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            let (start, end) = (chars[i], chars[i + 2]);
+            if end < start {
+                return Err(ConfigError::invalid(section, field,
+                                                CodepointError::Reversed(start, end).to_string()));
+            }
+            result.extend(start..=end);
+            i += 3;
+        } else {
+            result.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Expand any codepoint escapes (in the format `\u{F0000}`) inside a string into the characters
+/// they represent. This allows lookup tables to be written in TOML literal strings, where escapes
+/// are not otherwise processed.
+fn unescape(input: &str, section: &str, field: &str) -> Result<String, ConfigError> {
+    let escape_parser: Regex = Regex::new(r"\\u\{([0-9a-fA-F]{1,8})}").unwrap();
+    let mut result: String = String::with_capacity(input.len());
+    let mut last: usize = 0;
+
+    for caps in escape_parser.captures_iter(input) {
+        let whole = caps.get(0).unwrap();
+        let ord: u32 = u32::from_str_radix(&caps[1], 16).unwrap();
+        let c: char = char::from_u32(ord)
+            .ok_or_else(|| ConfigError::invalid(section, field,
+                                                CodepointError::Invalid(ord).to_string()))?;
+        result.push_str(&input[last..whole.start()]);
+        result.push(c);
+        last = whole.end();
+    }
+    result.push_str(&input[last..]);
+
+    Ok(result)
+}
+
+/// Errors that can occur while loading a configuration or building a chain from it.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A configuration file couldn't be read.
+    Io { path: PathBuf, source: std::io::Error },
+    /// A configuration file isn't valid in its format, or doesn't match the schema.
+    Syntax { path: Option<PathBuf>, format: Format, message: String },
+    /// A configuration file includes itself, directly or indirectly.
+    IncludeCycle { path: PathBuf },
+    /// A field in a section has an invalid value. The location of the field is included when it
+    /// can be found (currently, for TOML files).
+    Invalid { section: String, field: String, reason: String, location: Option<Location> },
+}
+
+impl ConfigError {
+    /// Shorthand for an [`ConfigError::Invalid`] error.
+    pub fn invalid(section: &str, field: &str, reason: impl Into<String>) -> ConfigError {
+        ConfigError::Invalid {
+            section: section.to_string(),
+            field: field.to_string(),
+            reason: reason.into(),
+            location: None,
+        }
+    }
+
+    /// Fill in the location of an [`ConfigError::Invalid`] error from the origin of its section.
+    fn locate(self, origin: &Origin) -> ConfigError {
+        match self {
+            ConfigError::Invalid { section, field, reason, location: None } => {
+                let location: Option<Location> = find_location(origin, &field);
+                ConfigError::Invalid { section, field, reason, location }
+            }
+            other => other,
+        }
+    }
+
+    /// Attach the path of the file being parsed to a syntax error.
+    fn in_file(self, file: &Path) -> ConfigError {
+        match self {
+            ConfigError::Syntax { path: None, format, message } => {
+                ConfigError::Syntax { path: Some(file.to_path_buf()), format, message }
             }
+            other => other,
         }
-    })
+    }
 }
 
-fn parse_rt(config: &Table, section: &str) -> Translator {
-    let src_str = config.get("source").unwrap().as_str().unwrap();
-    let trg_str = config.get("target").unwrap().as_str().unwrap();
-    let source: Option<char> = getchar(src_str, section);
-    let target: Option<char> = getchar(trg_str, section);
-    let size: u32 = config.get("size").unwrap().as_int().unwrap() as u32;
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "could not read {}: {}", path.display(), source)
+            }
+            ConfigError::Syntax { path: Some(path), format, message } => {
+                write!(f, "invalid {} in {}: {}", format, path.display(), message)
+            }
+            ConfigError::Syntax { path: None, format, message } => {
+                write!(f, "invalid {}: {}", format, message)
+            }
+            ConfigError::IncludeCycle { path } => {
+                write!(f, "include cycle: {} includes itself", path.display())
+            }
+            ConfigError::Invalid { section, field, reason, location: Some(location) } => {
+                write!(f, "{}.{}: {} at {}", section, field, reason, location)
+            }
+            ConfigError::Invalid { section, field, reason, location: None } => {
+                write!(f, "{}.{}: {}", section, field, reason)
+            }
+        }
+    }
+}
 
-    range_translation(source.unwrap(), target.unwrap(), size)
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
-fn parse_mrt(config: &Table, section: &str) -> Translator {
-    let src_str = config.get("source").unwrap().as_str().unwrap();
-    let trg_str = config.get("target").unwrap().as_str().unwrap();
-    let source: Option<char> = getchar(src_str, section);
-    let target: Option<char> = getchar(trg_str, section);
-    let size: u32 = config.get("size").unwrap().as_int().unwrap() as u32;
-    let slice: u32 = config.get("slice").unwrap().as_int().unwrap() as u32;
-    let iters: u32 = config.get("iters").unwrap().as_int().unwrap() as u32;
+/// Errors that can occur while reading a single codepoint from a configuration value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodepointError {
+    /// The value was an empty string.
+    Empty,
+    /// The value was neither a single character nor a recognized codepoint notation.
+    Malformed(String),
+    /// The value was a well-formed number, but not a Unicode scalar value (for example, a
+    /// surrogate or anything above `U+10FFFF`).
+    Invalid(u32),
+    /// The value was a range whose end comes before its start.
+    Reversed(char, char),
+}
 
-    multirange_translation(source.unwrap(), target.unwrap(), size, slice, iters)
+impl fmt::Display for CodepointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodepointError::Empty => write!(f, "expected a character, found an empty string"),
+            CodepointError::Malformed(input) => write!(f, "\"{}\" is not a single character or a \
+                codepoint (expected e.g. 'A', \"U+1D400\", \"\\u{{1D400}}\", \"0x1D400\" or \
+                \"119808\")", input),
+            CodepointError::Invalid(ord) => write!(f, "{:#X} is not a valid Unicode codepoint", ord),
+            CodepointError::Reversed(start, end) => write!(f, "range ends (U+{:04X}) before it \
+                starts (U+{:04X})", *end as u32, *start as u32),
+        }
+    }
 }
 
-fn parse_lut(config: &Table, section: &str) -> Translator {
-    let source: &str = config.get("source").unwrap().as_str().unwrap();
-    let target: &str = config.get("target").unwrap().as_str().unwrap();
+impl std::error::Error for CodepointError {}
 
-    if (source.len() != target.len()) {
-        handle_error_ne("Source and target lengths must be equal", section,
-                        source.len().to_string().as_str(), target.len().to_string().as_str());
+/// Parse a single codepoint from a configuration value. The following notations are accepted:
+/// - a literal character: `'A'`, `'𝐀'`
+/// - Unicode notation: `"U+1D400"`
+/// - an escape: `"\u{1D400}"`
+/// - hexadecimal: `"0x1D400"`
+/// - decimal (two or more digits, since a lone digit is treated as a literal character):
+///   `"119808"`
+pub fn parse_codepoint(input: &str) -> Result<char, CodepointError> {
+    let mut chars = input.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => return Err(CodepointError::Empty),
+        (Some(c), None) => return Ok(c),
+        _ => {}
     }
 
-    lookup_translation(source, target)
+    let malformed = || CodepointError::Malformed(input.to_string());
+    let trimmed: &str = input.trim();
+    let ord: u32 = if let Some(hex) = trimmed.strip_prefix("U+")
+        .or_else(|| trimmed.strip_prefix("u+"))
+        .or_else(|| trimmed.strip_prefix("0x"))
+        .or_else(|| trimmed.strip_prefix("0X")) {
+        parse_radix(hex, 16).ok_or_else(malformed)?
+    } else if let Some(hex) = trimmed.strip_prefix("\\u{").and_then(|s| s.strip_suffix('}')) {
+        parse_radix(hex, 16).ok_or_else(malformed)?
+    } else {
+        parse_radix(trimmed, 10).ok_or_else(malformed)?
+    };
+
+    char::from_u32(ord).ok_or(CodepointError::Invalid(ord))
 }
 
-/// Convert a string into a single character.
-fn getchar(input: &str, section: &str) -> Option<char> {
-    let char_parser = Regex::new(r"\\u\{([0-9a-fA-F]{1,8})}");
-    if (input.len() == 1) { input.chars().nth(0) }
-    else {
-        char_parser.unwrap().find(input).and_then(|pos| {
-            Some(char::from_u32(u32::from_str_radix(pos.as_str(), 16).unwrap()).unwrap())
-        })
+/// Parse either a single codepoint (see [`parse_codepoint`]) or an inclusive range of codepoints
+/// written as `start..end`, such as `"U+1D400..U+1D419"` or `"a..z"`. Returns the first codepoint,
+/// and for ranges, the number of codepoints in the range.
+pub fn parse_range(input: &str) -> Result<(char, Option<u32>), CodepointError> {
+    // a lone character is never a range, even if it happens to be a '.'
+    if input.chars().nth(1).is_some() {
+        if let Some((start, end)) = input.split_once("..") {
+            let start: char = parse_codepoint(start)?;
+            let end: char = parse_codepoint(end)?;
+            if end < start { return Err(CodepointError::Reversed(start, end)); }
+            return Ok((start, Some(end as u32 - start as u32 + 1)));
+        }
     }
-    handle_error_val("Invalid character input (must be one character, or a Unicode codepoint \
-    in the format \"\\u{F0000}\")", section, input);
-    None
+    parse_codepoint(input).map(|c| (c, None))
 }
 
-/// Print an error to stderr with context.
-fn handle_error(msg: &str, section: &str) {
-    eprintln!("[config] Error in section {}: {}", section, msg);
+/// Parse an unsigned number, rejecting signs and empty strings (which `from_str_radix` accepts or
+/// reports ambiguously).
+fn parse_radix(digits: &str, radix: u32) -> Option<u32> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) { return None; }
+    u32::from_str_radix(digits, radix).ok()
 }
 
-/// Print an error to stderr with context and an associated value.
-fn handle_error_val(msg: &str, section: &str, value: &str) {
-    eprintln!("[config] Error in section {}: {} ({})", section, msg, value);
+/// Find the line and column of a field in a section by re-reading the file it came from. Only
+/// TOML files are supported, since that's the only format with a span-preserving parser here;
+/// if the field itself can't be found, the start of the section is used instead.
+fn find_location(origin: &Origin, field: &str) -> Option<Location> {
+    let (path, format) = origin.file.as_ref()?;
+    if *format != Format::Toml { return None; }
+
+    let data: String = fs::read_to_string(path).ok()?;
+    let doc = toml_edit::ImDocument::parse(data.as_str()).ok()?;
+    let root: &toml_edit::Table = match &origin.profile {
+        Some(profile) => doc.as_table().get("profile")?.as_table()?.get(profile)?.as_table()?,
+        None => doc.as_table(),
+    };
+    let table: &toml_edit::Table = match (origin.index, &origin.key) {
+        (Some(i), _) => root.get("translator")?.as_array_of_tables()?.get(i)?,
+        (None, Some(key)) => root.get(key)?.as_table()?,
+        (None, None) => return None,
+    };
+    let offset: usize = table.get(field).and_then(|item| item.span())
+        .or_else(|| table.span())?
+        .start;
+
+    let before: &str = &data[..offset];
+    let line: usize = before.matches('\n').count() + 1;
+    let column: usize = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    Some(Location { path: path.clone(), line, column })
 }
 
-/// Print an error to stderr with context and two values that are supposed to match but do not.
-fn handle_error_ne(msg: &str, section: &str, left: &str, right: &str) {
-    eprintln!("[config] Error in section {}: {} ({} != {})", section, msg, left, right);
-}
\ No newline at end of file
+/// Convert a string into a character and an optional range size, as a config error for the given
+/// field if it can't be.
+fn getrange(input: &str, section: &str, field: &str) -> Result<(char, Option<u32>), ConfigError> {
+    parse_range(input).map_err(|e| ConfigError::invalid(section, field, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(toml: &str) -> String {
+        parse_str(toml, Format::Toml).unwrap_err().to_string()
+    }
+
+    /// Write config files into a fresh directory, returning it.
+    fn files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir()
+            .join(format!("utf-normalize-config-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn ranges_must_stay_within_unicode() {
+        let e: String = error("[[translator]]\ntype = \"range\"\nsource = \"U+1D400..U+1D419\"\n\
+            target = \"U+10FFF0\"\n");
+        assert!(e.contains("range of 26 starting at U+10FFF0 runs past U+10FFFF"), "{}", e);
+        let e: String = error("[[translator]]\ntype = \"range\"\nsource = \"U+10FFF0\"\n\
+            target = \"A\"\nsize = 17\n");
+        assert!(e.contains("source"), "{}", e);
+        assert!(parse_str("[[translator]]\ntype = \"range\"\nsource = \"U+10FFF0\"\n\
+            target = \"A\"\nsize = 16\n", Format::Toml).is_ok());
+    }
+
+    #[test]
+    fn range_errors_are_located() {
+        let dir: PathBuf = files("located", &[("reversed.toml", "[[translator]]\ntype = \"range\"\n\
+            source = \"U+1D419..U+1D400\"\ntarget = \"A\"\n")]);
+        let config: Config = load(&dir.join("reversed.toml"), Format::Toml).unwrap();
+        let Err(ConfigError::Invalid { field, reason, location, .. }) = build(&config) else {
+            panic!("expected an invalid field");
+        };
+        assert_eq!(field, "source");
+        assert!(reason.contains("range ends (U+1D400) before it starts (U+1D419)"), "{}", reason);
+        let location: Location = location.unwrap();
+        assert_eq!((location.line, location.column), (3, 10));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_global_sections_are_left_out() {
+        let mut config: Config = from_str("[[translator]]\ntype = \"lookup\"\nsource = \"é\"\n\
+            target = \"e\"\n", Format::Toml).unwrap();
+        let toml: String = to_string(&config, Format::Toml).unwrap();
+        assert!(!toml.contains("[global]"), "{}", toml);
+        config.global.fallback = Some("drop".to_string());
+        let toml: String = to_string(&config, Format::Toml).unwrap();
+        assert!(toml.starts_with("[global]\nfallback = \"drop\"\n"), "{}", toml);
+    }
+
+    #[test]
+    fn presets_load_beneath_the_file() {
+        let chain: Chain = parse_str("presets = [\"cyrillic\", \"fullwidth\"]\n\
+            [[translator]]\nname = \"cyrillic_lower\"\ntype = \"lookup\"\nsource = \"а\"\n\
+            target = \"4\"\n", Format::Toml).unwrap();
+        // the file's section replaces the preset's section with the same name
+        assert_eq!(chain.translate_str("раｙ"), "р4y");
+        assert!(error("presets = [\"klingon\"]\n").contains("unknown preset"));
+    }
+
+    #[test]
+    fn global_options_are_read_from_the_environment() {
+        let vars: [(&str, &str); 6] = [
+            ("UTF_NORMALIZE_ASCII_FILTER", "yes"),
+            ("UTF_NORMALIZE_FALLBACK", "replace"),
+            ("UTF_NORMALIZE_PROTECT", "`[^`]*`\nhttps?://\\S+\n"),
+            ("UTF_NORMALIZE_PRE_NORMALIZE", "nfkc"),
+            ("UTF_NORMALIZE_POST_NORMALIZE", "nfc"),
+            ("UTF_NORMALIZE_EXIT_CODE", "changes"),
+        ];
+        vars.iter().for_each(|(name, value)| env::set_var(name, value));
+        let global: Global = Global::from_env().unwrap();
+        assert_eq!(global.use_ascii_filter, Some(true));
+        assert_eq!(global.fallback.as_deref(), Some("replace"));
+        assert_eq!(global.protect, vec!["`[^`]*`", "https?://\\S+"]);
+        assert_eq!(global.pre_normalize.as_deref(), Some("nfkc"));
+        assert_eq!(global.post_normalize.as_deref(), Some("nfc"));
+        assert_eq!(global.exit_code.as_deref(), Some("changes"));
+
+        // empty variables count as unset
+        env::set_var("UTF_NORMALIZE_PROTECT", "");
+        env::remove_var("UTF_NORMALIZE_PRE_NORMALIZE");
+        let global: Global = Global::from_env().unwrap();
+        assert!(global.protect.is_empty());
+        assert!(global.pre_normalize.is_none());
+
+        vars.iter().for_each(|(name, _)| env::remove_var(name));
+        assert!(Global::from_env().unwrap().is_empty());
+    }
+
+    #[test]
+    fn presets_is_not_a_section_name() {
+        let err: String = error("[presets]\ntype = \"range\"\nsource = 'A'\ntarget = 'a'\n\
+                                 size = 26\n");
+        assert!(err.contains("[presets]") && err.contains("expected a sequence"), "{err}");
+    }
+
+    #[test]
+    fn profiles_can_load_presets() {
+        let mut config: Config = from_str("[profile.strict]\npresets = [\"cyrillic\"]\n",
+                                          Format::Toml).unwrap();
+        assert_eq!(build(&config).unwrap().translate_str("ра"), "ра");
+        config.select_profile("strict").unwrap();
+        assert!(config.presets.is_empty());
+        assert_eq!(build(&config).unwrap().translate_str("ра"), "pa");
+    }
+
+    #[test]
+    fn profiles_cannot_include_files() {
+        let mut config: Config = from_str("[profile.strict]\ninclude = [\"strict.toml\"]\n",
+                                          Format::Toml).unwrap();
+        let err: String = config.select_profile("strict").unwrap_err().to_string();
+        assert!(err.contains("profiles can't include other files"), "{err}");
+
+        let mut config: Config = from_str("[profile.strict.profile.stricter]\n", Format::Toml)
+            .unwrap();
+        let err: String = config.select_profile("strict").unwrap_err().to_string();
+        assert!(err.contains("profiles can't define profiles"), "{err}");
+    }
+
+    #[test]
+    fn map_keys_of_several_characters_match_the_longest() {
+        let chain: Chain = parse_str("[[translator]]\ntype = \"lookup\"\n\
+            map = { \"ый\" = \"iy\", \"й\" = \"y\", \"щ\" = \"shch\" }\n", Format::Toml).unwrap();
+        assert_eq!(chain.translate_str("новый йщ ы"), "новiy yshch ы");
+    }
+
+    #[test]
+    fn sequences_can_end_in_ascii() {
+        let chain: Chain = parse_str("[global]\nuse_ascii_filter = true\n[[translator]]\n\
+            type = \"lookup\"\nmap = { \"№1\" = \"first\", \"№\" = \"No.\" }\n", Format::Toml)
+            .unwrap();
+        assert_eq!(chain.translate_str("№1 and №2"), "first and No.2");
+    }
+
+    #[test]
+    fn earlier_translators_win_over_sequences() {
+        let chain: Chain = parse_str("[[translator]]\ntype = \"lookup\"\nsource = \"ы\"\n\
+            target = \"y\"\n[[translator]]\ntype = \"lookup\"\nmap = { \"ый\" = \"iy\" }\n",
+                                     Format::Toml).unwrap();
+        assert_eq!(chain.translate_str("ый"), "yй");
+    }
+
+    #[test]
+    fn diamond_includes_load_once() {
+        let dir: PathBuf = files("diamond", &[
+            ("a.toml", "include = [\"b.toml\", \"c.toml\"]\n"),
+            ("b.toml", "include = [\"d.toml\"]\n"),
+            ("c.toml", "include = [\"d.toml\"]\n"),
+            ("d.toml", "[[translator]]\ntype = \"lookup\"\nsource = \"é\"\ntarget = \"e\"\n"),
+        ]);
+        let config: Config = load(&dir.join("a.toml"), Format::Toml).unwrap();
+        assert_eq!(config.translator.len(), 1);
+        let names: Vec<&str> = config.files.iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .collect();
+        assert_eq!(names, ["d.toml", "b.toml", "c.toml", "a.toml"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir: PathBuf = files("cycle", &[
+            ("a.toml", "include = [\"b.toml\"]\n"),
+            ("b.toml", "include = [\"c.toml\"]\n"),
+            ("c.toml", "include = [\"a.toml\"]\n"),
+        ]);
+        let e: ConfigError = load(&dir.join("a.toml"), Format::Toml).unwrap_err();
+        let cycle: bool = matches!(&e, ConfigError::IncludeCycle { path }
+                                   if path.ends_with("a.toml"));
+        assert!(cycle, "{}", e);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multiranges_must_stay_within_unicode() {
+        let section: &str = "[[translator]]\ntype = \"multirange\"\nsource = \"U+1D400\"\n\
+            target = \"A\"\nsize = 26\n";
+        assert!(parse_str(&format!("{}slice = 52\niters = 13\n", section), Format::Toml).is_ok());
+        assert!(error(&format!("{}slice = 0\niters = 13\n", section)).contains("slice"));
+        let e: String = error(&format!("{}slice = 52\niters = 100000\n", section));
+        assert!(e.contains("run past U+10FFFF"), "{}", e);
+        let e: String = error(&format!("{}slice = 4000000000\niters = 3\n", section));
+        assert!(e.contains("run past U+10FFFF"), "{}", e);
+    }
+
+    #[test]
+    fn map_targets_can_be_strings() {
+        let chain: Chain = parse_str("[[translator]]\ntype = \"lookup\"\n\
+            map = { \"½\" = \"1/2\", \"ﬁ\" = \"fi\", \"U+2026\" = \"...\" }\n", Format::Toml)
+            .unwrap();
+        assert_eq!(chain.translate_str("½ a ﬁle…"), "1/2 a file...");
+    }
+
+    #[test]
+    fn delete_sections_remove_characters() {
+        let chain: Chain = parse_str("[[translator]]\ntype = \"delete\"\n\
+            source = [\"U+200B..U+200D\", \"U+2060\", \"U+FEFF\"]\n\
+            [[translator]]\ntype = \"lookup\"\nmap = { \"\u{AD}\" = \"\" }\n", Format::Toml)
+            .unwrap();
+        let text: &str = "\u{FEFF}zero\u{200B}width\u{200D} soft\u{AD}hyphen\u{2060}";
+        assert_eq!(chain.translate_str(text), "zerowidth softhyphen");
+    }
+
+    #[test]
+    fn higher_priorities_win_over_file_order() {
+        let sections: &str = "[[translator]]\ntype = \"lookup\"\nsource = \"ℌ\"\ntarget = \"h\"\n\
+            [[translator]]\ntype = \"lookup\"\nsource = \"ℌ\"\ntarget = \"H\"\n";
+        assert_eq!(parse_str(sections, Format::Toml).unwrap().translate_str("ℌi"), "hi");
+        let chain: Chain = parse_str(&format!("{}priority = 10\n", sections), Format::Toml)
+            .unwrap();
+        assert_eq!(chain.translate_str("ℌi"), "Hi");
+        let chain: Chain = parse_str(&format!("{}priority = -1\n\
+            [old]\ntype = \"lookup\"\nsource = \"ℌ\"\ntarget = \"x\"\n", sections), Format::Toml)
+            .unwrap();
+        assert_eq!(chain.translate_str("ℌi"), "hi");
+    }
+
+    #[test]
+    fn character_classes_expand_in_lookups() {
+        let chain: Chain = parse_str("[[translator]]\ntype = \"lookup\"\n\
+            source = \"[ᴀʙᴄᴅᴇ]\"\ntarget = \"[a-e]\"\n", Format::Toml).unwrap();
+        assert_eq!(chain.translate_str("ᴀʙᴄ ᴅᴇ ꜰ"), "abc de ꜰ");
+        let chain: Chain = parse_str("[[translator]]\ntype = \"lookup\"\n\
+            source = \"[Ａ-Ｃｘ]\"\ntarget = \"[A-Cx]\"\n", Format::Toml).unwrap();
+        assert_eq!(chain.translate_str("ＡＢＣｘＤ"), "ABCxＤ");
+        let e: String = error("[[translator]]\ntype = \"lookup\"\nsource = \"[ᴀʙᴄ]\"\n\
+            target = \"[a-b]\"\n");
+        assert!(e.contains("target"), "{}", e);
+    }
+}
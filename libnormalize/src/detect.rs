@@ -0,0 +1,299 @@
+/// Detection of suspicious characters, and the severities they are reported with.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::chain::Chain;
+use crate::unicode;
+
+/// A class of suspicious characters that check and scan modes report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub enum Class {
+    /// Bidirectional control characters, which can reorder how text is displayed without
+    /// changing how it is read by a compiler (the "Trojan Source" attack).
+    BidiControls,
+    /// Characters that don't render at all, like zero-width spaces and joiners.
+    Invisible,
+    /// Non-ASCII characters that the chain translates into something else, i.e. lookalikes of the
+    /// characters they are normalized to.
+    Confusables,
+    /// Letters from a different script than the rest of the word they are in, like a Cyrillic
+    /// "а" in an otherwise Latin word. Han, Hiragana, Katakana, Hangul and Bopomofo count as one
+    /// script, since they are written together.
+    MixedScripts,
+    /// Characters on the chain's deny list, which normalizing refuses to translate. Bidi controls
+    /// and invisible characters on the list are still reported in their own classes.
+    Denied,
+}
+
+impl Class {
+    /// Every class, in the order they are checked.
+    pub const ALL: [Class; 5] = [Class::BidiControls, Class::Invisible, Class::Denied,
+                                 Class::Confusables, Class::MixedScripts];
+
+    /// The name of the class, as it is written in configs and reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::BidiControls => "bidi_controls",
+            Class::Invisible => "invisible",
+            Class::Confusables => "confusables",
+            Class::MixedScripts => "mixed_scripts",
+            Class::Denied => "denied",
+        }
+    }
+}
+
+impl FromStr for Class {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Class::ALL.iter()
+            .find(|class| class.name() == s.to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown class \"{}\" (expected bidi_controls, invisible, \
+                                    confusables, mixed_scripts or denied)", s))
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How seriously a class of findings is taken. Severities are ordered, so the worst finding in a
+/// report is the maximum of their severities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(C)]
+pub enum Severity {
+    /// The class isn't reported at all.
+    Off,
+    /// Reported, but never affects the exit code.
+    Info,
+    /// Reported as a warning.
+    Warn,
+    /// Reported as an error, and makes check and scan modes fail.
+    Error,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Severity::Off),
+            "info" => Ok(Severity::Info),
+            "warn" | "warning" => Ok(Severity::Warn),
+            "error" => Ok(Severity::Error),
+            other => Err(format!("unknown severity \"{}\" (expected off, info, warn or error)",
+                                 other)),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Off => write!(f, "off"),
+            Severity::Info => write!(f, "info"),
+            Severity::Warn => write!(f, "warn"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The severity of each detection class. By default bidi controls and denied characters are
+/// errors, invisible characters and mixed scripts are warnings, and confusables are informational.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Severities {
+    pub bidi_controls: Severity,
+    pub invisible: Severity,
+    pub confusables: Severity,
+    pub mixed_scripts: Severity,
+    pub denied: Severity,
+}
+
+impl Default for Severities {
+    fn default() -> Severities {
+        Severities {
+            bidi_controls: Severity::Error,
+            invisible: Severity::Warn,
+            confusables: Severity::Info,
+            mixed_scripts: Severity::Warn,
+            denied: Severity::Error,
+        }
+    }
+}
+
+impl Severities {
+    /// The severity of a class.
+    pub fn get(&self, class: Class) -> Severity {
+        match class {
+            Class::BidiControls => self.bidi_controls,
+            Class::Invisible => self.invisible,
+            Class::Confusables => self.confusables,
+            Class::MixedScripts => self.mixed_scripts,
+            Class::Denied => self.denied,
+        }
+    }
+
+    /// Change the severity of a class.
+    pub fn set(&mut self, class: Class, severity: Severity) {
+        match class {
+            Class::BidiControls => self.bidi_controls = severity,
+            Class::Invisible => self.invisible = severity,
+            Class::Confusables => self.confusables = severity,
+            Class::MixedScripts => self.mixed_scripts = severity,
+            Class::Denied => self.denied = severity,
+        }
+    }
+}
+
+/// A suspicious character found in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub class: Class,
+    pub severity: Severity,
+    pub character: char,
+    /// The byte offset of the character in the input.
+    pub offset: usize,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} character U+{:04X} at byte {}", self.severity, self.class,
+               self.character as u32, self.offset)
+    }
+}
+
+/// Whether a character is a bidirectional control: the embedding, override and isolate controls,
+/// and the implicit directional marks.
+pub fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}'
+                | '\u{2066}'..='\u{2069}')
+}
+
+/// Whether a character is invisible: zero-width spaces, joiners and formatting characters,
+/// variation selectors and tag characters. Bidi controls are not included (see
+/// [`is_bidi_control`]).
+pub fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{034F}' | '\u{115F}' | '\u{1160}' | '\u{180E}'
+                | '\u{200B}'..='\u{200D}' | '\u{2060}'..='\u{2064}' | '\u{3164}'
+                | '\u{FE00}'..='\u{FE0F}' | '\u{FEFF}' | '\u{FFA0}'
+                | '\u{E0000}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
+/// Find the class of a character, if it is suspicious. A chain is needed to tell whether a
+/// character is denied or a confusable; allowed characters are never confusables.
+pub fn classify(chain: &Chain, c: char) -> Option<Class> {
+    if is_bidi_control(c) { return Some(Class::BidiControls); }
+    if is_invisible(c) { return Some(Class::Invisible); }
+    if chain.is_denied(c) { return Some(Class::Denied); }
+    if c.is_ascii() || chain.is_allowed(c) { return None; }
+
+    let mut buf: String = String::new();
+    if !chain.lookup_into(c, &mut buf) { return None; }
+    let mut chars = buf.chars();
+    let unchanged: bool = chars.next() == Some(c) && chars.next().is_none();
+    if unchanged { None } else { Some(Class::Confusables) }
+}
+
+/// Find every suspicious character in a string, with the severity the chain assigns to its class,
+/// in order. Classes with severity [`Severity::Off`] and protected regions are skipped. A
+/// character can be found in two classes: a confusable that is also in a mixed-script word.
+pub fn detect(chain: &Chain, source: &str) -> Vec<Finding> {
+    let segments: Vec<(usize, bool, &str)> = chain.segments(source);
+    let mut findings: Vec<Finding> = segments.iter()
+        .filter(|&&(_, protected, _)| !protected)
+        .flat_map(|&(base, _, text)| text.char_indices().map(move |(i, c)| (base + i, c)))
+        .filter_map(|(offset, character)| {
+            let class: Class = classify(chain, character)?;
+            let severity: Severity = chain.severity.get(class);
+            if severity == Severity::Off { return None; }
+            Some(Finding { class, severity, character, offset })
+        })
+        .collect();
+
+    let severity: Severity = chain.severity.get(Class::MixedScripts);
+    if severity != Severity::Off {
+        findings.extend(segments.iter()
+            .filter(|&&(_, protected, _)| !protected)
+            .flat_map(|&(base, _, text)| {
+                mixed_scripts(text).into_iter().map(move |(i, c)| (base + i, c))
+            })
+            .map(|(offset, character)| {
+                Finding { class: Class::MixedScripts, severity, character, offset }
+            }));
+        findings.sort_by_key(|finding| finding.offset);
+    }
+    findings
+}
+
+/// Find the letters in a string that are in a different script from the first letter of the word
+/// they are in, with their byte offsets. Words are runs of alphanumeric characters, underscores
+/// and combining marks; characters used by many scripts (like digits) don't count.
+pub fn mixed_scripts(source: &str) -> Vec<(usize, char)> {
+    let mut found: Vec<(usize, char)> = Vec::new();
+    // the script of the first letter of the current word, once there is one
+    let mut word: Option<&'static str> = None;
+    for (offset, c) in source.char_indices() {
+        let script: &'static str = script_group(c);
+        if !c.is_alphanumeric() && c != '_' && script != "Inherited" {
+            word = None;
+            continue;
+        }
+        if script == "Common" || script == "Inherited" { continue; }
+        match word {
+            None => word = Some(script),
+            Some(first) if first != script => found.push((offset, c)),
+            Some(_) => {}
+        }
+    }
+    found
+}
+
+/// The script of a character, with the scripts that are written together in Chinese, Japanese
+/// and Korean counted as one.
+fn script_group(c: char) -> &'static str {
+    match unicode::script(c) {
+        "Han" | "Hiragana" | "Katakana" | "Hangul" | "Bopomofo" => "Han",
+        script => script,
+    }
+}
+
+/// The worst severity among a set of findings, or [`Severity::Off`] if there are none.
+pub fn worst(findings: &[Finding]) -> Severity {
+    findings.iter().map(|f| f.severity).max().unwrap_or(Severity::Off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    #[test]
+    fn denied_characters_are_findings() {
+        let chain: Chain = config::parse_str("[deny]\nsource = [\"é\", \"U+202E\"]\n",
+                                             Format::Toml).unwrap();
+        let found: Vec<Finding> = detect(&chain, "né\u{202E}");
+        assert_eq!(found, [
+            Finding { class: Class::Denied, severity: Severity::Error, character: 'é', offset: 1 },
+            Finding { class: Class::BidiControls, severity: Severity::Error, character: '\u{202E}',
+                      offset: 3 },
+        ]);
+    }
+}
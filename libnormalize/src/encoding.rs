@@ -0,0 +1,406 @@
+/// Detection and conversion of input encodings other than UTF-8.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use crate::chain::{Chain, Fallback};
+use crate::stream::Utf8Decoder;
+
+/// How many bytes at the start of the input are looked at to guess its encoding.
+const SNIFF_SIZE: usize = 4 * 1024;
+
+/// How many bytes are read from the input, and decoded, at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The encoding input is read in. Whatever it is, it is converted to UTF-8 before it is
+/// normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputEncoding {
+    /// Guess: a byte order mark decides, then UTF-16 is recognized by its NUL bytes (see
+    /// [`utf16`]), and anything else is UTF-8 (or Windows-1252, if it isn't valid UTF-8 and the
+    /// fallback is enabled).
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Windows-1252, which is also used for Latin-1 (ISO-8859-1), as web browsers do: the two
+    /// only differ in the C1 control characters, which hardly ever appear in real text.
+    Windows1252,
+}
+
+impl InputEncoding {
+    /// The encoding to decode input with, given its first few bytes. With `fallback`, input that
+    /// isn't valid UTF-8 (and isn't recognized as anything else) is decoded as Windows-1252.
+    pub fn resolve(&self, sample: &[u8], fallback: bool) -> &'static Encoding {
+        match self {
+            InputEncoding::Utf8 => UTF_8,
+            InputEncoding::Utf16Le => UTF_16LE,
+            InputEncoding::Utf16Be => UTF_16BE,
+            InputEncoding::Windows1252 => WINDOWS_1252,
+            InputEncoding::Auto => {
+                if let Some((encoding, _)) = Encoding::for_bom(sample) { return encoding; }
+                if let Some(encoding) = utf16(sample) { return encoding; }
+                match std::str::from_utf8(sample) {
+                    // a character may be cut off at the end of the sample; that's still UTF-8
+                    Err(e) if fallback && e.error_len().is_some() => WINDOWS_1252,
+                    _ => UTF_8,
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "auto" => Ok(InputEncoding::Auto),
+            "utf-8" | "utf8" => Ok(InputEncoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(InputEncoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(InputEncoding::Utf16Be),
+            "windows-1252" | "cp1252" | "latin1" | "latin-1" | "iso-8859-1" => {
+                Ok(InputEncoding::Windows1252)
+            }
+            other => Err(format!("unknown encoding \"{}\" (expected auto, utf-8, utf-16le, \
+                                  utf-16be or windows-1252)", other)),
+        }
+    }
+}
+
+impl fmt::Display for InputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputEncoding::Auto => write!(f, "auto"),
+            InputEncoding::Utf8 => write!(f, "utf-8"),
+            InputEncoding::Utf16Le => write!(f, "utf-16le"),
+            InputEncoding::Utf16Be => write!(f, "utf-16be"),
+            InputEncoding::Windows1252 => write!(f, "windows-1252"),
+        }
+    }
+}
+
+/// Whether the start of some input looks like UTF-16, and if so, which byte order: either it
+/// starts with a UTF-16 byte order mark, or at least a quarter of its code units have a NUL high
+/// byte while hardly any have a NUL low byte (as in mostly-ASCII text). UTF-16 without a byte
+/// order mark that is mostly outside of ASCII (like Chinese or Japanese text) isn't recognized.
+pub fn utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    match Encoding::for_bom(sample) {
+        Some((encoding, _)) if encoding == UTF_16LE || encoding == UTF_16BE => {
+            return Some(encoding);
+        }
+        Some(_) => return None,
+        None => {}
+    }
+    let units: usize = sample.len() / 2;
+    if units == 0 { return None; }
+    let even: usize = sample.chunks_exact(2).filter(|unit| unit[0] == 0).count();
+    let odd: usize = sample.chunks_exact(2).filter(|unit| unit[1] == 0).count();
+    match (even * 4 >= units, odd * 4 >= units) {
+        (true, false) if odd * 16 < units => Some(UTF_16BE),
+        (false, true) if even * 16 < units => Some(UTF_16LE),
+        _ => None,
+    }
+}
+
+/// What to do with byte order marks (a U+FEFF at the very start of the text).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bom {
+    /// Don't write one, even if the input had one.
+    Strip,
+    /// Write one if the input had one.
+    #[default]
+    Keep,
+    /// Always write one.
+    Add,
+}
+
+impl Bom {
+    /// Whether output in `encoding` should start with a byte order mark, given whether the input
+    /// did. ASCII output never does, since ASCII can't represent one.
+    pub fn emit(&self, had_bom: bool, encoding: OutputEncoding) -> bool {
+        encoding != OutputEncoding::Ascii && match self {
+            Bom::Strip => false,
+            Bom::Keep => had_bom,
+            Bom::Add => true,
+        }
+    }
+}
+
+impl FromStr for Bom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strip" => Ok(Bom::Strip),
+            "keep" => Ok(Bom::Keep),
+            "add" => Ok(Bom::Add),
+            other => Err(format!("unknown BOM policy \"{}\" (expected strip, keep or add)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Bom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bom::Strip => write!(f, "strip"),
+            Bom::Keep => write!(f, "keep"),
+            Bom::Add => write!(f, "add"),
+        }
+    }
+}
+
+/// Reads input in any [`InputEncoding`], producing UTF-8. A byte order mark at the start of the
+/// input is removed rather than treated as text (see [`Transcoder::had_bom`]). UTF-8 input is
+/// otherwise passed through untouched; anything else is converted as it is read, with malformed
+/// sequences decoded as U+FFFD.
+pub struct Transcoder<R> {
+    inner: R,
+    encoding: &'static Encoding,
+    had_bom: bool,
+    /// `None` when the input is already UTF-8.
+    decoder: Option<Decoder>,
+    /// Bytes read from `inner` that haven't been decoded (or passed through) yet.
+    input: Vec<u8>,
+    consumed: usize,
+    /// Decoded bytes that haven't been read yet.
+    output: Vec<u8>,
+    written: usize,
+    /// Whether `inner` has run out, and whether the decoder has been told so.
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: Read> Transcoder<R> {
+    /// Start reading `inner`, deciding on its encoding from its first few kilobytes (see
+    /// [`InputEncoding::resolve`]).
+    pub fn new(mut inner: R, encoding: InputEncoding, fallback: bool)
+        -> io::Result<Transcoder<R>> {
+        let mut sample: Vec<u8> = Vec::with_capacity(SNIFF_SIZE);
+        let eof: bool = (&mut inner).take(SNIFF_SIZE as u64).read_to_end(&mut sample)?
+            < SNIFF_SIZE;
+        let resolved: &'static Encoding = encoding.resolve(&sample, fallback);
+        let bom: Option<usize> = Encoding::for_bom(&sample)
+            .and_then(|(encoding, len)| (encoding == resolved).then_some(len));
+        // the decoders remove byte order marks themselves; UTF-8 is passed through from after it
+        let (decoder, consumed): (Option<Decoder>, usize) = match resolved == UTF_8 {
+            true => (None, bom.unwrap_or(0)),
+            false => (Some(resolved.new_decoder_with_bom_removal()), 0),
+        };
+        Ok(Transcoder { inner, encoding: resolved, had_bom: bom.is_some(), decoder,
+                        input: sample, consumed, output: Vec::new(), written: 0, eof,
+                        finished: false })
+    }
+
+    /// The encoding the input is being read in.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Whether the input started with a byte order mark (which isn't part of what is read).
+    pub fn had_bom(&self) -> bool {
+        self.had_bom
+    }
+
+    /// Read more of `inner` once everything read so far has been used up.
+    fn refill(&mut self) -> io::Result<()> {
+        if self.consumed < self.input.len() || self.eof { return Ok(()); }
+        self.input.resize(CHUNK_SIZE, 0);
+        self.consumed = 0;
+        loop {
+            match self.inner.read(&mut self.input) {
+                Ok(n) => {
+                    self.input.truncate(n);
+                    self.eof = n == 0;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.input.clear();
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.written < self.output.len() {
+                let n: usize = buf.len().min(self.output.len() - self.written);
+                buf[..n].copy_from_slice(&self.output[self.written..self.written + n]);
+                self.written += n;
+                return Ok(n);
+            }
+            self.refill()?;
+            let decoder: &mut Decoder = match &mut self.decoder {
+                Some(decoder) => decoder,
+                None => {
+                    // UTF-8: hand over the sample, then read straight from `inner`
+                    if self.consumed == self.input.len() && self.eof { return Ok(0); }
+                    std::mem::swap(&mut self.input, &mut self.output);
+                    self.input.clear();
+                    self.written = self.consumed;
+                    self.consumed = 0;
+                    continue;
+                }
+            };
+            if self.finished { return Ok(0); }
+            let pending: &[u8] = &self.input[self.consumed..];
+            let capacity: usize = decoder.max_utf8_buffer_length(pending.len())
+                .unwrap_or(CHUNK_SIZE * 3).max(4);
+            self.output.resize(capacity, 0);
+            let (result, read, written, _) = decoder.decode_to_utf8(pending, &mut self.output,
+                                                                     self.eof);
+            self.consumed += read;
+            self.output.truncate(written);
+            self.written = 0;
+            if self.eof && result == CoderResult::InputEmpty { self.finished = true; }
+        }
+    }
+}
+
+/// The encoding output is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    /// UTF-16, little-endian, as many Windows tools expect.
+    Utf16Le,
+    /// 7-bit ASCII. Any non-ASCII characters left after translation (in protected regions, or
+    /// kept by the fallback policy or produced by rules) go through the chain's fallback policy,
+    /// with `keep` (and `error`) treated as `replace`, so that nothing outside ASCII is written.
+    Ascii,
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(OutputEncoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(OutputEncoding::Utf16Le),
+            "ascii" | "us-ascii" => Ok(OutputEncoding::Ascii),
+            other => Err(format!("unknown output encoding \"{}\" (expected utf-8, utf-16le or \
+                                  ascii)", other)),
+        }
+    }
+}
+
+impl fmt::Display for OutputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputEncoding::Utf8 => write!(f, "utf-8"),
+            OutputEncoding::Utf16Le => write!(f, "utf-16le"),
+            OutputEncoding::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+/// Writes UTF-8 text to `inner` in an [`OutputEncoding`]. Everything written to it must be
+/// UTF-8; a character split between two writes is put back together, and invalid UTF-8 is written
+/// as U+FFFD (or its ASCII replacement).
+pub struct Encoder<'a, W: Write> {
+    inner: W,
+    encoding: OutputEncoding,
+    chain: &'a Chain,
+    decoder: Utf8Decoder,
+    text: String,
+    out: Vec<u8>,
+    replaced: bool,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    /// Write to `inner` in `encoding`, using `chain`'s fallback policy for characters that ASCII
+    /// can't represent.
+    pub fn new(inner: W, encoding: OutputEncoding, chain: &'a Chain) -> Encoder<'a, W> {
+        Encoder { inner, encoding, chain, decoder: Utf8Decoder::new(), text: String::new(),
+                  out: Vec::new(), replaced: false }
+    }
+
+    /// Write a byte order mark, in the output encoding (except for ASCII, which has none). This
+    /// should come before any text.
+    pub fn write_bom(&mut self) -> io::Result<()> {
+        match self.encoding {
+            OutputEncoding::Utf8 => self.inner.write_all("\u{FEFF}".as_bytes()),
+            OutputEncoding::Utf16Le => self.inner.write_all(&0xFEFFu16.to_le_bytes()),
+            OutputEncoding::Ascii => Ok(()),
+        }
+    }
+
+    /// Whether any characters had to be replaced because the output encoding can't represent
+    /// them.
+    pub fn replaced(&self) -> bool {
+        self.replaced
+    }
+
+    /// Stop encoding, returning the writer underneath.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Encode a character as ASCII: as it is if it is ASCII, and otherwise by the chain's
+    /// fallback policy (with `?` if even that isn't ASCII).
+    fn push_ascii(&mut self, c: char, ascii: &mut String) {
+        if c.is_ascii() {
+            ascii.push(c);
+            return;
+        }
+        self.replaced = true;
+        let start: usize = ascii.len();
+        match self.chain.fallback {
+            Fallback::Keep | Fallback::Error => ascii.push_str(&self.chain.replacement),
+            _ => self.chain.fallback_into(c, ascii),
+        }
+        if !ascii[start..].is_ascii() {
+            ascii.truncate(start);
+            ascii.push('?');
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.clear();
+        self.decoder.decode(buf, &mut self.text);
+        self.out.clear();
+        match self.encoding {
+            OutputEncoding::Utf8 => self.out.extend_from_slice(self.text.as_bytes()),
+            OutputEncoding::Utf16Le => {
+                self.out.extend(self.text.encode_utf16().flat_map(u16::to_le_bytes));
+            }
+            OutputEncoding::Ascii => {
+                let text: String = std::mem::take(&mut self.text);
+                let mut ascii: String = String::with_capacity(text.len());
+                for c in text.chars() {
+                    self.push_ascii(c, &mut ascii);
+                }
+                self.out.extend_from_slice(ascii.as_bytes());
+                self.text = text;
+            }
+        }
+        self.inner.write_all(&self.out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,293 @@
+//! C interface to the library.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//!
+//! Every function here is exported unmangled with the C calling convention, so the library can be
+//! built as a `cdylib` and called from C or C++. The header for these functions,
+//! `include/libnormalize.h`, is generated from this module with cbindgen (see `cbindgen.toml`).
+//!
+//! Chains are handed out as opaque pointers, which must be freed with [`normalize_chain_free`],
+//! and strings returned by the library must be freed with [`normalize_string_free`]. Text is
+//! passed in as NUL-terminated UTF-8; invalid UTF-8 is decoded as U+FFFD. Functions that fail
+//! return NULL (or -1), and the reason can be read with [`normalize_last_error`]. A chain can be
+//! used from several threads at once.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use crate::chain::Chain;
+use crate::config::{self, Config, Format};
+use crate::detect::{self, Class, Finding, Severity};
+use crate::presets;
+use crate::utf8;
+
+thread_local! {
+    /// The message for the last error on this thread (see [`normalize_last_error`]).
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A suspicious character found by [`normalize_detect`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NormalizeFinding {
+    /// The class of suspicious character it is.
+    pub kind: Class,
+    pub severity: Severity,
+    /// The character's codepoint.
+    pub codepoint: u32,
+    /// The byte offset of the character in the text.
+    pub offset: usize,
+}
+
+impl From<Finding> for NormalizeFinding {
+    fn from(finding: Finding) -> NormalizeFinding {
+        NormalizeFinding { kind: finding.class, severity: finding.severity,
+                           codepoint: finding.character as u32, offset: finding.offset }
+    }
+}
+
+/// Run the body of an exported function. If it fails (or panics, which must not unwind into C),
+/// the error is saved for [`normalize_last_error`] and `failed` is returned instead.
+fn guard<T, F: FnOnce() -> Result<T, String>>(failed: T, body: F) -> T {
+    let result: Result<T, String> = panic::catch_unwind(AssertUnwindSafe(body))
+        .unwrap_or_else(|_| Err("libnormalize panicked".to_string()));
+    result.unwrap_or_else(|message| {
+        // C strings can't hold a NUL, so the message is cut off at the first one
+        let message: String = message.split('\0').next().unwrap_or_default().to_string();
+        LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+        failed
+    })
+}
+
+/// Read a string argument. Invalid UTF-8 is decoded as U+FFFD.
+///
+/// # Safety
+/// `s` must be NULL or point to a NUL-terminated string that outlives `'a`.
+unsafe fn text<'a>(s: *const c_char, name: &str) -> Result<Cow<'a, str>, String> {
+    if s.is_null() { return Err(format!("{} is NULL", name)); }
+    Ok(utf8::from_utf8_lossy(CStr::from_ptr(s).to_bytes()))
+}
+
+/// Borrow a chain argument.
+///
+/// # Safety
+/// `chain` must be NULL or a chain from this library that hasn't been freed.
+unsafe fn chain<'a>(chain: *const Chain) -> Result<&'a Chain, String> {
+    chain.as_ref().ok_or_else(|| "chain is NULL".to_string())
+}
+
+/// The message for the last error on the calling thread, or NULL if there hasn't been one. The
+/// message belongs to the library, and stays valid until the next error on the same thread.
+#[no_mangle]
+pub extern "C" fn normalize_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Build a chain from built-in presets: a comma-separated list of preset names like
+/// "math,cyrillic", or "all" for every one. NULL or an empty list selects the default preset.
+/// Returns NULL if a preset is unknown.
+///
+/// # Safety
+/// `names` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn normalize_chain_from_presets(names: *const c_char) -> *mut Chain {
+    guard(ptr::null_mut(), || {
+        let names: Cow<str> = if names.is_null() { Cow::Borrowed("") }
+                              else { text(names, "names")? };
+        let mut names: Vec<&str> = names.split(',').map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() { names.push(presets::DEFAULT); }
+        let config: Config = presets::compose(&names).map_err(|e| e.to_string())?;
+        let chain: Chain = config::build(&config).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(chain)))
+    })
+}
+
+/// Build a chain from a config, written in `format`: "toml", "json" or "yaml" (NULL means TOML).
+/// Includes are resolved relative to the current working directory. Returns NULL if the config is
+/// invalid.
+///
+/// # Safety
+/// `config` must point to a NUL-terminated string, and `format` must be NULL or point to one.
+#[no_mangle]
+pub unsafe extern "C" fn normalize_chain_from_config(config: *const c_char,
+                                                     format: *const c_char) -> *mut Chain {
+    guard(ptr::null_mut(), || {
+        let format: Format = if format.is_null() { Format::Toml }
+                             else { text(format, "format")?.parse()? };
+        let chain: Chain = config::parse_str(&text(config, "config")?, format)
+            .map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(chain)))
+    })
+}
+
+/// Free a chain. Does nothing if `chain` is NULL.
+///
+/// # Safety
+/// `chain` must be NULL or a chain from this library that hasn't been freed, and that no other
+/// thread is still using.
+#[no_mangle]
+pub unsafe extern "C" fn normalize_chain_free(chain: *mut Chain) {
+    if !chain.is_null() { drop(Box::from_raw(chain)); }
+}
+
+/// Normalize some text. Returns a new string, to be freed with [`normalize_string_free`], or NULL
+/// if an argument is NULL, the chain rejects a character in the text (one on its deny list, or
+/// one that no rule handles when its fallback is "error"), or the translation has a NUL character
+/// in it.
+///
+/// # Safety
+/// `chain` must be a chain from this library that hasn't been freed, and `text` must point to a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn normalize_str(chain: *const Chain, text: *const c_char) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let translated: String = self::chain(chain)?.try_translate_str(&self::text(text, "text")?)
+            .map_err(|denied| denied.to_string())?;
+        CString::new(translated).map(CString::into_raw)
+            .map_err(|_| "the translation has a NUL character in it".to_string())
+    })
+}
+
+/// Free a string returned by the library. Does nothing if `s` is NULL.
+///
+/// # Safety
+/// `s` must be NULL or a string returned by [`normalize_str`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn normalize_string_free(s: *mut c_char) {
+    if !s.is_null() { drop(CString::from_raw(s)); }
+}
+
+/// Find the suspicious characters in some text, with the severities the chain gives their
+/// classes, in order (see [`detect::detect`]). Up to `capacity` of them are written to `findings`,
+/// and the number found is returned, so calling it with a `capacity` of 0 counts them. Returns -1
+/// if an argument is NULL.
+///
+/// # Safety
+/// `chain` must be a chain from this library that hasn't been freed, `text` must point to a
+/// NUL-terminated string, and `findings` must have room for `capacity` findings (it may be NULL
+/// if `capacity` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn normalize_detect(chain: *const Chain, text: *const c_char,
+                                          findings: *mut NormalizeFinding, capacity: usize)
+    -> isize {
+    guard(-1, || {
+        let found: Vec<Finding> = detect::detect(self::chain(chain)?, &self::text(text, "text")?);
+        if capacity > 0 {
+            if findings.is_null() { return Err("findings is NULL".to_string()); }
+            for (i, finding) in found.iter().take(capacity).enumerate() {
+                findings.add(i).write(NormalizeFinding::from(*finding));
+            }
+        }
+        Ok(found.len() as isize)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_str_fails_on_denied_characters() {
+        let config: CString = CString::new("[deny]\nsource = [\"U+202E\"]\n").unwrap();
+        unsafe {
+            let chain: *mut Chain = normalize_chain_from_config(config.as_ptr(), ptr::null());
+            assert!(!chain.is_null());
+            let text: CString = CString::new("a\u{202E}b").unwrap();
+            assert!(normalize_str(chain, text.as_ptr()).is_null());
+            let error: &CStr = CStr::from_ptr(normalize_last_error());
+            assert_eq!(error.to_str().unwrap(), "denied character U+202E at byte 1");
+
+            let text: CString = CString::new("ab").unwrap();
+            let translated: *mut c_char = normalize_str(chain, text.as_ptr());
+            assert_eq!(CStr::from_ptr(translated).to_str().unwrap(), "ab");
+            normalize_string_free(translated);
+            normalize_chain_free(chain);
+        }
+    }
+
+    /// The message for the last error on this thread.
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(normalize_last_error()).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn normalize_detect_counts_and_fills_findings() {
+        let names: CString = CString::new("cyrillic").unwrap();
+        let text: CString = CString::new("pay\u{200B} or pаy").unwrap();
+        unsafe {
+            let chain: *mut Chain = normalize_chain_from_presets(names.as_ptr());
+            assert!(!chain.is_null());
+            let count: isize = normalize_detect(chain, text.as_ptr(), ptr::null_mut(), 0);
+            assert!(count >= 2, "{}", count);
+
+            let mut findings: Vec<NormalizeFinding> = Vec::with_capacity(count as usize);
+            let written: isize = normalize_detect(chain, text.as_ptr(), findings.as_mut_ptr(),
+                                                  count as usize);
+            assert_eq!(written, count);
+            findings.set_len(count as usize);
+            assert_eq!((findings[0].kind, findings[0].codepoint, findings[0].offset),
+                       (Class::Invisible, 0x200B, 3));
+            assert!(findings.iter().any(|finding| {
+                (finding.kind, finding.codepoint, finding.offset) == (Class::Confusables, 0x430, 11)
+            }));
+
+            // a smaller buffer gets the first findings, and the count is still the total
+            let mut first: Vec<NormalizeFinding> = Vec::with_capacity(1);
+            assert_eq!(normalize_detect(chain, text.as_ptr(), first.as_mut_ptr(), 1), count);
+            first.set_len(1);
+            assert_eq!(first[0].codepoint, 0x200B);
+            normalize_chain_free(chain);
+        }
+    }
+
+    #[test]
+    fn unknown_presets_set_the_last_error() {
+        let names: CString = CString::new("math, nope").unwrap();
+        unsafe {
+            assert!(normalize_chain_from_presets(names.as_ptr()).is_null());
+            let error: String = last_error();
+            assert!(error.contains("nope") && error.contains("unknown preset"), "{}", error);
+
+            let chain: *mut Chain = normalize_chain_from_presets(ptr::null());
+            assert!(!chain.is_null());
+            normalize_chain_free(chain);
+        }
+    }
+
+    #[test]
+    fn null_arguments_fail() {
+        let text: CString = CString::new("text").unwrap();
+        unsafe {
+            let chain: *mut Chain = normalize_chain_from_presets(ptr::null());
+            assert!(normalize_chain_from_config(ptr::null(), ptr::null()).is_null());
+            assert_eq!(last_error(), "config is NULL");
+            assert!(normalize_str(ptr::null(), text.as_ptr()).is_null());
+            assert_eq!(last_error(), "chain is NULL");
+            assert!(normalize_str(chain, ptr::null()).is_null());
+            assert_eq!(last_error(), "text is NULL");
+            assert_eq!(normalize_detect(ptr::null(), text.as_ptr(), ptr::null_mut(), 0), -1);
+            assert_eq!(normalize_detect(chain, ptr::null(), ptr::null_mut(), 0), -1);
+            assert_eq!(normalize_detect(chain, text.as_ptr(), ptr::null_mut(), 1), -1);
+            assert_eq!(last_error(), "findings is NULL");
+            normalize_string_free(ptr::null_mut());
+            normalize_chain_free(ptr::null_mut());
+            normalize_chain_free(chain);
+        }
+    }
+}
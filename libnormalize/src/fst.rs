@@ -0,0 +1,367 @@
+/// Finite state transducers for large tables of multi-character mappings.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// A read-only map from byte strings to strings, stored as a minimal acyclic finite state
+/// transducer (an FST). Keys are spelled out a byte at a time along transitions from the root,
+/// and each transition carries a piece of the value, so that a key's value is the pieces along
+/// its path joined together (plus a final piece on the state it ends at).
+///
+/// Keys that end the same way with the same values share their states, and values that start the
+/// same way share their pieces, so a big table takes far less memory than one entry per key: a
+/// map from codepoints (as UTF-8) to their replacements mostly collapses into a handful of states
+/// per Unicode block. A lookup takes one step per byte of the key, however big the table is, and
+/// [`Fst::longest_match`] finds the longest key at the start of some text in a single pass.
+///
+/// This is what sequence translators use for their tables, matching sources of any length with
+/// [`Fst::longest_match`] (see [`crate::translators::sequence_translation`]), and what map
+/// translators with many entries use for theirs (see [`crate::translators::map_translation`]).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fst {
+    states: Vec<State>,
+    /// The transitions of every state, each state's sorted by label and stored together.
+    transitions: Vec<Transition>,
+    /// Every distinct piece of a value, stored once.
+    pieces: String,
+    root: u32,
+    len: usize,
+}
+
+/// A state, with the range of its transitions in [`Fst::transitions`], and its final piece if a
+/// key ends here.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct State {
+    first: u32,
+    count: u32,
+    last: Option<Piece>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Transition {
+    label: u8,
+    target: u32,
+    piece: Piece,
+}
+
+/// A piece of a value, as a range of [`Fst::pieces`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Piece {
+    start: u32,
+    len: u32,
+}
+
+impl Fst {
+    /// Build a transducer. If a key appears more than once, the first occurrence wins.
+    pub fn new(mut entries: Vec<(Vec<u8>, String)>) -> Fst {
+        // sort_by is stable, so the first occurrence of a key is the one dedup keeps
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|a, b| a.0 == b.0);
+        let mut builder: Builder = Builder::default();
+        // the path of the last key: every state on it is still open, since the next key can add
+        // transitions to it
+        let mut open: Vec<Node> = vec![Node::default()];
+        let mut previous: &[u8] = &[];
+        for (key, value) in &entries {
+            let prefix: usize = key.iter().zip(previous).take_while(|(a, b)| a == b).count();
+            builder.close(&mut open, prefix + 1);
+
+            // move the part of each piece along the shared path that this value doesn't start
+            // with further down, so that the piece is shared by both
+            let mut value: &str = value;
+            for depth in 0..prefix {
+                let piece: &mut String = &mut open[depth].transitions.last_mut()
+                    .expect("every open state but the last has a transition").piece;
+                let common: usize = common_prefix(piece, value);
+                if common < piece.len() {
+                    let rest: String = piece.split_off(common);
+                    let child: &mut Node = &mut open[depth + 1];
+                    for transition in &mut child.transitions {
+                        transition.piece.insert_str(0, &rest);
+                    }
+                    if let Some(last) = &mut child.last { last.insert_str(0, &rest); }
+                }
+                value = &value[common..];
+            }
+
+            if key.len() == prefix {
+                // only the empty key can end on a state that is already open
+                open[prefix].last = Some(value.to_string());
+            } else {
+                for (i, &label) in key[prefix..].iter().enumerate() {
+                    let piece: String = if i == 0 { value.to_string() } else { String::new() };
+                    let node: &mut Node = open.last_mut().expect("the root is always open");
+                    node.transitions.push(Edge { label, piece, target: 0 });
+                    open.push(Node::default());
+                }
+                open.last_mut().expect("a state was just opened").last = Some(String::new());
+            }
+            previous = key;
+        }
+        builder.close(&mut open, 1);
+        let root: Node = open.pop().expect("the root is always open");
+        let root: u32 = builder.add(root);
+        Fst { states: builder.states, transitions: builder.transitions, pieces: builder.pieces,
+              root, len: entries.len() }
+    }
+
+    /// Look up a key.
+    pub fn get(&self, key: &[u8]) -> Option<String> {
+        let mut value: String = String::new();
+        self.get_with(key, |piece| value.push_str(piece)).then_some(value)
+    }
+
+    /// Look up a key, passing its value to `each` a piece at a time, so it can be copied
+    /// somewhere without being put together first. Returns whether the key was found; if it
+    /// wasn't, `each` isn't called at all.
+    pub fn get_with<F: FnMut(&str)>(&self, key: &[u8], mut each: F) -> bool {
+        let Some(last) = self.walk(key, |_| {}) else { return false; };
+        self.walk(key, &mut each);
+        each(self.piece(last));
+        true
+    }
+
+    /// Find the longest key that `text` starts with, returning its length and its value.
+    pub fn longest_match(&self, text: &[u8]) -> Option<(usize, String)> {
+        let mut value: String = String::new();
+        let mut found: Option<(usize, String)> = None;
+        let mut state: &State = &self.states[self.root as usize];
+        for i in 0..=text.len() {
+            if let Some(last) = state.last {
+                found = Some((i, format!("{}{}", value, self.piece(last))));
+            }
+            let Some(&byte) = text.get(i) else { break; };
+            let Some(transition) = self.transition(state, byte) else { break; };
+            value.push_str(self.piece(transition.piece));
+            state = &self.states[transition.target as usize];
+        }
+        found
+    }
+
+    /// The number of keys.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of states, which is how much the table has been compressed: a table of
+    /// unrelated keys needs about one per byte of the keys, and shared endings need fewer.
+    pub fn states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Every entry, in key order.
+    pub fn entries(&self) -> Vec<(Vec<u8>, String)> {
+        let mut entries: Vec<(Vec<u8>, String)> = Vec::with_capacity(self.len);
+        self.collect(self.root, &mut Vec::new(), &mut String::new(), &mut entries);
+        entries
+    }
+
+    fn collect(&self, state: u32, key: &mut Vec<u8>, value: &mut String,
+               entries: &mut Vec<(Vec<u8>, String)>) {
+        let state: &State = &self.states[state as usize];
+        if let Some(last) = state.last {
+            entries.push((key.clone(), format!("{}{}", value, self.piece(last))));
+        }
+        for transition in self.transitions_of(state) {
+            let len: usize = value.len();
+            key.push(transition.label);
+            value.push_str(self.piece(transition.piece));
+            self.collect(transition.target, key, value, entries);
+            key.pop();
+            value.truncate(len);
+        }
+    }
+
+    /// Follow a key from the root, passing the piece on each transition to `each`. Returns the
+    /// final piece of the state the key ends on, or `None` if it isn't a key (after which `each`
+    /// may have been given some pieces).
+    fn walk<F: FnMut(&str)>(&self, key: &[u8], mut each: F) -> Option<Piece> {
+        let mut state: &State = &self.states[self.root as usize];
+        for &byte in key {
+            let transition: &Transition = self.transition(state, byte)?;
+            each(self.piece(transition.piece));
+            state = &self.states[transition.target as usize];
+        }
+        state.last
+    }
+
+    fn transitions_of(&self, state: &State) -> &[Transition] {
+        &self.transitions[state.first as usize..(state.first + state.count) as usize]
+    }
+
+    fn transition(&self, state: &State, label: u8) -> Option<&Transition> {
+        let transitions: &[Transition] = self.transitions_of(state);
+        transitions.binary_search_by_key(&label, |transition| transition.label).ok()
+            .map(|i| &transitions[i])
+    }
+
+    fn piece(&self, piece: Piece) -> &str {
+        &self.pieces[piece.start as usize..(piece.start + piece.len) as usize]
+    }
+}
+
+/// A state that is still being built.
+#[derive(Debug, Default, PartialEq, Eq, Hash)]
+struct Node {
+    transitions: Vec<Edge>,
+    last: Option<String>,
+}
+
+/// A transition that is still being built. Its target is only known once the state it leads to
+/// has been closed.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Edge {
+    label: u8,
+    piece: String,
+    target: u32,
+}
+
+/// Collects the closed states of a transducer as it is built, merging states that are the same.
+#[derive(Default)]
+struct Builder {
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+    pieces: String,
+    /// Every closed state, by what it looks like, so that a state that is the same as one already
+    /// closed is replaced by it.
+    closed: HashMap<Node, u32>,
+    /// Where each distinct piece is in `pieces`.
+    interned: HashMap<String, Piece>,
+}
+
+impl Builder {
+    /// Close the open states deeper than `depth`, from the deepest up, pointing the last
+    /// transition of each one's parent at it.
+    fn close(&mut self, open: &mut Vec<Node>, depth: usize) {
+        while open.len() > depth {
+            let node: Node = open.pop().expect("there are more open states than depth");
+            let id: u32 = self.add(node);
+            open.last_mut().expect("the root is never closed here").transitions.last_mut()
+                .expect("an open state's parent leads to it").target = id;
+        }
+    }
+
+    /// Add a closed state, or find the same one added before, returning its index.
+    fn add(&mut self, node: Node) -> u32 {
+        if let Some(&id) = self.closed.get(&node) { return id; }
+        let first: u32 = self.transitions.len() as u32;
+        for edge in &node.transitions {
+            let piece: Piece = self.intern(&edge.piece);
+            self.transitions.push(Transition { label: edge.label, target: edge.target, piece });
+        }
+        let last: Option<Piece> = node.last.as_deref().map(|last| self.intern(last));
+        let id: u32 = self.states.len() as u32;
+        self.states.push(State { first, count: node.transitions.len() as u32, last });
+        self.closed.insert(node, id);
+        id
+    }
+
+    fn intern(&mut self, piece: &str) -> Piece {
+        if let Some(&found) = self.interned.get(piece) { return found; }
+        let found: Piece = Piece { start: self.pieces.len() as u32, len: piece.len() as u32 };
+        self.pieces.push_str(piece);
+        self.interned.insert(piece.to_string(), found);
+        found
+    }
+}
+
+/// The length of the longest common prefix of two strings that ends on a character boundary, so
+/// that every piece of a value is valid UTF-8 on its own.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices().zip(b.chars())
+        .find(|&((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fst(entries: &[(&str, &str)]) -> Fst {
+        Fst::new(entries.iter().map(|&(k, v)| (k.as_bytes().to_vec(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn finds_every_key() {
+        let entries: [(&str, &str); 6] = [("½", "1/2"), ("⅓", "1/3"), ("ﬁ", "fi"), ("ﬂ", "fl"),
+                                          ("ab", "x"), ("abc", "")];
+        let table: Fst = fst(&entries);
+        assert_eq!(table.len(), 6);
+        for (key, value) in entries {
+            assert_eq!(table.get(key.as_bytes()).as_deref(), Some(value), "{}", key);
+        }
+        let mut sorted: Vec<(Vec<u8>, String)> = entries.iter()
+            .map(|&(k, v)| (k.as_bytes().to_vec(), v.to_string())).collect();
+        sorted.sort();
+        assert_eq!(table.entries(), sorted);
+    }
+
+    #[test]
+    fn missing_keys() {
+        let table: Fst = fst(&[("ab", "x"), ("abc", "y")]);
+        assert_eq!(table.get(b"a"), None);
+        assert_eq!(table.get(b"abd"), None);
+        assert_eq!(table.get(b"abcd"), None);
+        assert_eq!(table.get(b""), None);
+        assert!(!table.get_with(b"a", |_| panic!("a missing key has no pieces")));
+    }
+
+    #[test]
+    fn empty_table() {
+        let table: Fst = Fst::new(Vec::new());
+        assert!(table.is_empty());
+        assert_eq!(table.get(b""), None);
+        assert_eq!(table.longest_match(b"abc"), None);
+        assert!(table.entries().is_empty());
+    }
+
+    #[test]
+    fn first_occurrence_wins() {
+        let table: Fst = fst(&[("a", "1"), ("a", "2"), ("", "empty")]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(b"a").as_deref(), Some("1"));
+        assert_eq!(table.get(b"").as_deref(), Some("empty"));
+    }
+
+    #[test]
+    fn longest_match() {
+        let table: Fst = fst(&[(":", "colon"), (":)", "🙂"), (":-)", "🙂"), (":-))", "😄")]);
+        assert_eq!(table.longest_match(b":-) hi"), Some((3, "🙂".to_string())));
+        assert_eq!(table.longest_match(b":-))"), Some((4, "😄".to_string())));
+        assert_eq!(table.longest_match(b":-("), Some((1, "colon".to_string())));
+        assert_eq!(table.longest_match(b"x:)"), None);
+    }
+
+    #[test]
+    fn shares_states_and_pieces() {
+        // the keys differ only in their last byte, so they all end on the same state, and the
+        // values' common start is stored once, on the path they share
+        let entries: Vec<(Vec<u8>, String)> = ('\u{FF21}'..='\u{FF3A}')
+            .map(|c| (c.to_string().into_bytes(), format!("wide {}", c)))
+            .collect();
+        let table: Fst = Fst::new(entries.clone());
+        assert!(table.states() < entries.len(), "{} states", table.states());
+        for (key, value) in &entries {
+            assert_eq!(table.get(key).as_ref(), Some(value));
+        }
+    }
+}
@@ -0,0 +1,67 @@
+/// Comment and string extraction from real tree-sitter grammars.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use tree_sitter::{Node, Parser, Tree, TreeCursor};
+use crate::source::Language;
+
+/// The tree-sitter grammar for a language.
+fn grammar(language: Language) -> tree_sitter::Language {
+    match language {
+        Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+        Language::Python => tree_sitter_python::LANGUAGE.into(),
+        Language::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+        Language::C => tree_sitter_c::LANGUAGE.into(),
+        Language::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+        Language::Java => tree_sitter_java::LANGUAGE.into(),
+        Language::Go => tree_sitter_go::LANGUAGE.into(),
+    }
+}
+
+/// Whether a node is a comment or a string (or character) literal. Grammars name these
+/// differently (`line_comment`, `string_literal`, `raw_string_literal`, `template_string`,
+/// `rune_literal`...), but consistently enough to go by the name.
+fn is_literal(node: &Node) -> bool {
+    let kind: &str = node.kind();
+    kind.ends_with("comment") || kind.contains("string") || kind.starts_with("char")
+        || kind == "rune_literal"
+}
+
+/// Find the comments and string literals in source code by parsing it, as byte ranges in order.
+/// Literals inside literals (like the strings in a template string's substitutions) are part of
+/// the outer one. Returns `None` if the grammar can't be loaded.
+///
+/// Source with syntax errors still parses, with the errors confined to the parts of the tree
+/// around them, so the literals elsewhere are still found.
+pub fn literals(language: Language, source: &str) -> Option<Vec<(usize, usize)>> {
+    let mut parser: Parser = Parser::new();
+    parser.set_language(&grammar(language)).ok()?;
+    let tree: Tree = parser.parse(source, None)?;
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut cursor: TreeCursor = tree.walk();
+    loop {
+        let node: Node = cursor.node();
+        if is_literal(&node) {
+            spans.push((node.start_byte(), node.end_byte()));
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        // move on to the next node that isn't inside this one
+        loop {
+            if cursor.goto_next_sibling() { break; }
+            if !cursor.goto_parent() { return Some(spans); }
+        }
+    }
+}
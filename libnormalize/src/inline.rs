@@ -0,0 +1,129 @@
+/// Short strings stored without a heap allocation.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+
+/// The most bytes an [`InlineStr`] holds without allocating. This keeps it the same size as a
+/// `String`.
+pub const INLINE_CAPACITY: usize = 22;
+
+/// An immutable string that is stored inline if it is at most [`INLINE_CAPACITY`] bytes long, and
+/// on the heap otherwise. Almost every replacement an expander makes (a ligature spelled out, a
+/// fraction like `1/2`, or nothing at all) is a few bytes long, so storing them this way means
+/// looking one up and copying it around never touches the heap, and a map translator's table
+/// holds its replacements itself rather than pointing at thousands of tiny allocations.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum InlineStr {
+    /// The first `len` bytes of `bytes`, which are always valid UTF-8.
+    Inline { len: u8, bytes: [u8; INLINE_CAPACITY] },
+    Heap(Box<str>),
+}
+
+impl InlineStr {
+    /// The empty string.
+    pub const fn new() -> InlineStr {
+        InlineStr::Inline { len: 0, bytes: [0; INLINE_CAPACITY] }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            // SAFETY: inline bytes are only ever copied from a whole `str` (see `From<&str>`), so
+            // the first `len` of them are valid UTF-8.
+            InlineStr::Inline { len, bytes } => unsafe {
+                std::str::from_utf8_unchecked(&bytes[..*len as usize])
+            },
+            InlineStr::Heap(s) => s,
+        }
+    }
+
+    /// Whether the string is stored inline, rather than on the heap.
+    pub fn is_inline(&self) -> bool {
+        matches!(self, InlineStr::Inline { .. })
+    }
+}
+
+impl Default for InlineStr {
+    fn default() -> InlineStr {
+        InlineStr::new()
+    }
+}
+
+impl From<&str> for InlineStr {
+    fn from(s: &str) -> InlineStr {
+        if s.len() > INLINE_CAPACITY { return InlineStr::Heap(s.into()); }
+        let mut bytes: [u8; INLINE_CAPACITY] = [0; INLINE_CAPACITY];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        InlineStr::Inline { len: s.len() as u8, bytes }
+    }
+}
+
+impl From<String> for InlineStr {
+    /// Strings that fit are copied inline (and their allocation freed); longer ones keep theirs.
+    fn from(s: String) -> InlineStr {
+        if s.len() > INLINE_CAPACITY { InlineStr::Heap(s.into_boxed_str()) }
+        else { InlineStr::from(s.as_str()) }
+    }
+}
+
+impl From<InlineStr> for String {
+    fn from(s: InlineStr) -> String {
+        match s {
+            InlineStr::Heap(s) => s.into_string(),
+            inline => inline.as_str().to_string(),
+        }
+    }
+}
+
+impl Deref for InlineStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for InlineStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for InlineStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for InlineStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for InlineStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for InlineStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
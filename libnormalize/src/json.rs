@@ -0,0 +1,195 @@
+/// Translation of the strings in JSON documents.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, Read, Write};
+use serde::de::IgnoredAny;
+use serde_json::{Deserializer, StreamDeserializer};
+use serde_json::de::StrRead;
+use crate::chain::Chain;
+use crate::stream;
+use crate::utf8;
+
+/// Translate the string values in JSON input, leaving object keys, numbers, and the structure
+/// alone. With `pointers` (JSON pointers, like `/user/name`), only the strings under those parts
+/// of each document are translated; pointers that don't exist in a document are ignored.
+///
+/// The input can hold several documents one after another (like JSON Lines). Everything but the
+/// strings that translation changes is copied over byte for byte, so numbers (like `1.50` or
+/// `1e3`), whitespace and escapes are kept as they were written; a string that changes is written
+/// back with only the escapes JSON requires. Since the input is checked to be valid JSON before
+/// anything is written, it is read into memory in full; invalid UTF-8 in it is decoded as U+FFFD.
+/// A character that a strict chain rejects (see [`Chain::is_strict`]) stops the translation with
+/// an error giving the JSON pointer of the string it is in.
+///
+/// Returns whether the translation changed anything.
+pub fn translate_json<R: Read, W: Write>(chain: &Chain, mut input: R, mut output: W,
+                                         pointers: &[String]) -> io::Result<bool> {
+    if let Some(pointer) = pointers.iter().find(|p| !p.is_empty() && !p.starts_with('/')) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("invalid JSON pointer \"{}\" (it must start with /)",
+                                          pointer)));
+    }
+    let mut bytes: Vec<u8> = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let text: String = utf8::from_utf8_lossy(&bytes).into_owned();
+
+    // the scan below assumes valid JSON, so check all of it first
+    let documents: StreamDeserializer<StrRead, IgnoredAny> =
+        Deserializer::from_str(&text).into_iter::<IgnoredAny>();
+    for document in documents {
+        document.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+
+    let bytes: &[u8] = text.as_bytes();
+    let mut stack: Vec<Frame> = Vec::new();
+    // everything before this has been written
+    let mut written: usize = 0;
+    let mut changed: bool = false;
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => stack.push(Frame::Object(None)),
+            b'[' => stack.push(Frame::Array(0)),
+            b'}' | b']' => { stack.pop(); }
+            b',' => match stack.last_mut() {
+                Some(Frame::Object(key)) => *key = None,
+                Some(Frame::Array(index)) => *index += 1,
+                None => {}
+            },
+            b'"' => {
+                let end: usize = string_end(bytes, i);
+                let token: &str = &text[i..end];
+                if let Some(Frame::Object(key @ None)) = stack.last_mut() {
+                    *key = Some(decode(token)?);
+                } else {
+                    let pointer: String = pointer(&stack);
+                    if selected(pointers, &pointer) {
+                        let string: String = decode(token)?;
+                        let translated: String = chain.try_translate_str(&string)
+                            .map_err(|denied| {
+                                let place: &str = if pointer.is_empty() { "the document" }
+                                                  else { &pointer };
+                                stream::rejected(&denied, &format!("in the string at {}", place))
+                            })?;
+                        if translated != string {
+                            output.write_all(&bytes[written..i])?;
+                            serde_json::to_writer(&mut output, &translated)?;
+                            written = end;
+                            changed = true;
+                        }
+                    }
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    output.write_all(&bytes[written..])?;
+    output.flush()?;
+    Ok(changed)
+}
+
+/// Where the scan is in a document: in an object, with the key of the current entry once it has
+/// been read, or in an array, with the index of the current item.
+enum Frame {
+    Object(Option<String>),
+    Array(usize),
+}
+
+/// The JSON pointer of the value the scan is at.
+fn pointer(stack: &[Frame]) -> String {
+    let mut pointer: String = String::new();
+    for frame in stack {
+        match frame {
+            Frame::Object(key) => {
+                pointer.push('/');
+                pointer.push_str(&key.as_deref().unwrap_or_default().replace('~', "~0")
+                    .replace('/', "~1"));
+            }
+            Frame::Array(index) => pointer.push_str(&format!("/{}", index)),
+        }
+    }
+    pointer
+}
+
+/// Whether the value at `pointer` is under one of the selected `pointers` (or there aren't any).
+fn selected(pointers: &[String], pointer: &str) -> bool {
+    pointers.is_empty() || pointers.iter().any(|selected| {
+        pointer.strip_prefix(selected.as_str()).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/')
+        })
+    })
+}
+
+/// Find the end of the string token that starts with the quote at `start`, just past its closing
+/// quote.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i: usize = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Decode a string token, quotes and all.
+fn decode(token: &str) -> io::Result<String> {
+    serde_json::from_str(token).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    #[test]
+    fn error_fallback_names_the_string() {
+        let chain: Chain = config::parse_str("[global]\nfallback = \"error\"\n", Format::Toml)
+            .unwrap();
+        let input: &str = r#"{"a/b": ["ok", "né"]}"#;
+        let e: io::Error = translate_json(&chain, input.as_bytes(), io::sink(), &[]).unwrap_err();
+        assert_eq!(e.to_string(), "unmapped character U+00E9 in the string at /a~1b/1");
+    }
+
+    fn translate(input: &str, pointers: &[String]) -> String {
+        let chain: Chain = config::parse_str("[[translator]]\ntype = \"lookup\"\nsource = \"é\"\n\
+                                              target = \"e\"\n", Format::Toml).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        translate_json(&chain, input.as_bytes(), &mut output, pointers).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn numbers_are_copied_as_written() {
+        let input: &str = "{\"price\": 1.50, \"big\": 12345678901234567890.1, \"é\": \"né\"}\n";
+        assert_eq!(translate(input, &[]),
+                   "{\"price\": 1.50, \"big\": 12345678901234567890.1, \"é\": \"ne\"}\n");
+    }
+
+    #[test]
+    fn pointers_select_strings() {
+        let input: &str = "{\"a\": [\"é\", {\"b\": \"é\"}], \"ab\": \"\\u00e9\"}\n[\"é\"]\n";
+        assert_eq!(translate(input, &["/a/1".to_string()]),
+                   "{\"a\": [\"é\", {\"b\": \"e\"}], \"ab\": \"\\u00e9\"}\n[\"é\"]\n");
+        assert_eq!(translate(input, &["/ab".to_string()]),
+                   "{\"a\": [\"é\", {\"b\": \"é\"}], \"ab\": \"e\"}\n[\"é\"]\n");
+    }
+}
@@ -0,0 +1,202 @@
+/// Inferring lookup translators from example input and output.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use indexmap::IndexMap;
+use crate::config::{Section, SectionKind};
+
+/// The substitutions inferred from a set of examples, as a lookup table. `table` holds the
+/// one-to-one substitutions and `map` holds the one-to-many ones (including deletions, which map
+/// to an empty string).
+#[derive(Debug, Default, Clone)]
+pub struct Learned {
+    pub table: IndexMap<char, char>,
+    pub map: IndexMap<char, String>,
+    /// Anything in the examples that couldn't be turned into a substitution.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A part of an example that the learner couldn't use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// A character was seen being replaced by two different things. The first replacement is the
+    /// one that is kept.
+    Inconsistent { source: char, kept: String, other: String },
+    /// A run of several characters was replaced by a run of a different length, so it's not
+    /// clear which character became what.
+    Ambiguous { source: String, target: String },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Inconsistent { source, kept, other } => {
+                write!(f, "{:?} (U+{:04X}) maps to both {:?} and {:?}; keeping {:?}", source,
+                       *source as u32, kept, other, kept)
+            }
+            Conflict::Ambiguous { source, target } => {
+                write!(f, "can't tell how {:?} maps onto {:?}", source, target)
+            }
+        }
+    }
+}
+
+impl Learned {
+    /// Convert the learned substitutions into a lookup section, named `name`.
+    pub fn to_section(&self, name: &str) -> Section {
+        let source: String = self.table.keys().collect();
+        let target: String = self.table.values().collect();
+        let has_table: bool = !self.table.is_empty();
+        Section {
+            name: Some(name.to_string()),
+            priority: 0,
+            kind: SectionKind::Lookup {
+                source: if has_table { Some(source) } else { None },
+                target: if has_table { Some(target) } else { None },
+                map: self.map.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+            },
+            origin: Default::default(),
+        }
+    }
+
+    /// Record that `source` was replaced with `target`.
+    fn add(&mut self, source: char, target: &str) {
+        let existing: Option<String> = self.table.get(&source).map(|c| c.to_string())
+            .or_else(|| self.map.get(&source).cloned());
+        match existing {
+            Some(kept) if kept == target => {}
+            Some(kept) => {
+                let conflict: Conflict = Conflict::Inconsistent { source, kept,
+                                                                  other: target.to_string() };
+                if !self.conflicts.contains(&conflict) { self.conflicts.push(conflict); }
+            }
+            None => {
+                let mut chars = target.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(t), None) => { self.table.insert(source, t); }
+                    _ => { self.map.insert(source, target.to_string()); }
+                }
+            }
+        }
+    }
+}
+
+/// One step in an alignment of an example's input and output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Replace,
+    Delete,
+    Insert,
+}
+
+/// Infer the substitutions that turn each `dirty` string into its `clean` counterpart.
+///
+/// Each pair is aligned character by character (using the smallest number of edits), and every
+/// run of changed characters becomes a substitution: runs of equal length are paired up
+/// character by character, and a single character that became several characters (or none) is
+/// recorded as a one-to-many mapping. Anything else is reported as a conflict rather than
+/// guessed at.
+///
+/// ## Example
+/// ```rs
+/// let learned: Learned = learn(&[("раураl", "paypal"), ("ﬁle", "file")]);
+/// // learned.table == {'р': 'p', 'а': 'a', 'у': 'y'}, learned.map == {'ﬁ': "fi"}
+/// ```
+pub fn learn<S: AsRef<str>>(pairs: &[(S, S)]) -> Learned {
+    let mut learned: Learned = Learned::default();
+    for (dirty, clean) in pairs {
+        let dirty: Vec<char> = dirty.as_ref().chars().collect();
+        let clean: Vec<char> = clean.as_ref().chars().collect();
+        learn_pair(&mut learned, &dirty, &clean);
+    }
+    learned
+}
+
+fn learn_pair(learned: &mut Learned, dirty: &[char], clean: &[char]) {
+    let ops: Vec<Op> = align(dirty, clean);
+
+    // walk the alignment, collecting each run of changes between kept characters
+    let (mut i, mut j) = (0, 0);
+    let (mut run_src, mut run_trg): (String, String) = (String::new(), String::new());
+    for op in ops.into_iter().chain([Op::Keep]) {
+        if op == Op::Keep {
+            if !run_src.is_empty() || !run_trg.is_empty() {
+                learn_run(learned, &run_src, &run_trg);
+                run_src.clear();
+                run_trg.clear();
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if op != Op::Insert { run_src.push(dirty[i]); i += 1; }
+        if op != Op::Delete { run_trg.push(clean[j]); j += 1; }
+    }
+}
+
+fn learn_run(learned: &mut Learned, source: &str, target: &str) {
+    let src_len: usize = source.chars().count();
+    let trg_len: usize = target.chars().count();
+    if src_len == trg_len {
+        source.chars().zip(target.chars()).for_each(|(s, t)| learned.add(s, &t.to_string()));
+    } else if src_len == 1 {
+        learned.add(source.chars().next().unwrap(), target);
+    } else {
+        let conflict: Conflict = Conflict::Ambiguous { source: source.to_string(),
+                                                       target: target.to_string() };
+        if !learned.conflicts.contains(&conflict) { learned.conflicts.push(conflict); }
+    }
+}
+
+/// Find a minimal sequence of edits that turns `a` into `b` (a standard edit distance table with
+/// a backtrace). Replacements are preferred over a delete and an insert, so that substituted
+/// characters line up with what they were substituted for.
+fn align(a: &[char], b: &[char]) -> Vec<Op> {
+    let (n, m) = (a.len(), b.len());
+    let mut dist: Vec<Vec<usize>> = vec![vec![0; m + 1]; n + 1];
+    (0..=n).for_each(|i| dist[i][0] = i);
+    (0..=m).for_each(|j| dist[0][j] = j);
+    for i in 1..=n {
+        for j in 1..=m {
+            let replace: usize = dist[i - 1][j - 1] + if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            dist[i][j] = replace.min(dist[i - 1][j] + 1).min(dist[i][j - 1] + 1);
+        }
+    }
+
+    let mut ops: Vec<Op> = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 {
+            let same: bool = a[i - 1] == b[j - 1];
+            if dist[i][j] == dist[i - 1][j - 1] + if same { 0 } else { 1 } {
+                ops.push(if same { Op::Keep } else { Op::Replace });
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if i > 0 && dist[i][j] == dist[i - 1][j] + 1 {
+            ops.push(Op::Delete);
+            i -= 1;
+        } else {
+            ops.push(Op::Insert);
+            j -= 1;
+        }
+    }
+    ops.reverse();
+    ops
+}
@@ -1,4 +1,4 @@
-/// Backend module / API for utf-normalize applications.
+//! Backend module / API for utf-normalize applications.
 //     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
 //
 //     This program is free software: you can redistribute it and/or modify
@@ -13,5 +13,53 @@
 //
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//!
+//! Text is normalized by a [`Chain`] of translators, which is usually built from a [`Config`]:
+//! either one of the built-in [`presets`], or a config file in TOML, JSON or YAML (see
+//! [`config`]). The most commonly used types are re-exported here, and everything else is in the
+//! module it belongs to.
+//!
+//! ## Example
+//! Build a chain from two of the built-in presets, and normalize some text with it:
+//! ```rs
+//! use libnormalize::{config, presets, Chain};
+//!
+//! let chain: Chain = config::build(&presets::compose(&["math", "cyrillic"])?)?;
+//! assert_eq!(chain.translate_str("𝐛еаns"), "beans");
+//! ```
+//!
+//! A chain can also translate streams as they are read (see [`stream`]), only some parts of
+//! structured text (see [`json`], [`tabular`], [`markdown`] and [`source`]), and look for
+//! suspicious characters without changing anything (see [`detect`]).
 pub mod translators;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod chain;
+pub mod cache;
+pub mod learn;
+pub mod detect;
+pub mod reload;
+pub mod stream;
+pub mod presets;
+pub mod unicode;
+pub mod lint;
+pub mod encoding;
+pub mod tabular;
+pub mod json;
+pub mod markdown;
+pub mod source;
+#[cfg(feature = "tree-sitter")]
+pub mod grammar;
+pub mod obfuscate;
+pub mod compare;
+pub mod bench;
+pub mod utf8;
+pub mod phf;
+pub mod fst;
+pub mod charset;
+pub mod inline;
+pub mod ffi;
+
+pub use chain::{Chain, Fallback, NormalForm};
+pub use config::{Config, ConfigError};
+pub use detect::{Finding, Severity};
+pub use translators::{Expander, Translator};
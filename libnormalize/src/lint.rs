@@ -0,0 +1,95 @@
+/// Checks for mistakes in a config that aren't errors, such as sections that overlap.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use crate::chain::Chain;
+
+/// Two groups of rules (usually config sections) that both handle some characters, but map them
+/// differently. The group that comes first in the chain wins, so the other one's mappings for
+/// those characters never apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// The group whose mappings are used.
+    pub winner: String,
+    /// The group whose mappings are shadowed.
+    pub loser: String,
+    /// How many characters are affected.
+    pub count: usize,
+    /// The first affected character.
+    pub example: char,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" is shadowed by \"{}\" for {} character{} (e.g. U+{:04X}), which they map \
+                   differently", self.loser, self.winner, self.count,
+               if self.count == 1 { "" } else { "s" }, self.example as u32)
+    }
+}
+
+/// Find every pair of groups in a chain that map the same characters differently. This runs every
+/// rule on every Unicode scalar value, so like [`Chain::compile`] it is slow.
+pub fn overlaps(chain: &Chain) -> Vec<Overlap> {
+    find_overlaps(chain).into_iter().map(|(_, overlap)| overlap).collect()
+}
+
+/// Like [`overlaps`], but as pairs of group indexes (the winner, then the loser), counting rules
+/// before the first group as group 0 and the group starting at `chain.names[i]` as group `i + 1`.
+pub(crate) fn shadowing(chain: &Chain) -> Vec<(usize, usize)> {
+    find_overlaps(chain).into_iter().map(|(groups, _)| groups).collect()
+}
+
+/// Find every overlap, along with the indexes of the two groups.
+fn find_overlaps(chain: &Chain) -> Vec<((usize, usize), Overlap)> {
+    let mut found: HashMap<(usize, usize), Overlap> = HashMap::new();
+    let mut order: Vec<(usize, usize)> = Vec::new();
+    let mut winner: String = String::new();
+    let mut other: String = String::new();
+    for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+        let mut rules = chain.rules.iter().enumerate();
+        winner.clear();
+        let Some((first, _)) = rules.find(|(_, rule)| rule.apply_into(c, &mut winner)) else {
+            continue;
+        };
+        for (rule, _) in rules.filter(|(_, rule)| {
+            other.clear();
+            rule.apply_into(c, &mut other) && other != winner
+        }) {
+            let key: (usize, usize) = (group(chain, first), group(chain, rule));
+            // rules in the same group can't shadow each other in a way anyone could fix
+            if key.0 == key.1 { continue; }
+            found.entry(key)
+                .or_insert_with(|| {
+                    order.push(key);
+                    Overlap { winner: name(chain, first), loser: name(chain, rule), count: 0,
+                              example: c }
+                })
+                .count += 1;
+        }
+    }
+    order.into_iter().filter_map(|key| found.remove(&key).map(|overlap| (key, overlap))).collect()
+}
+
+/// The index of the group a rule belongs to, counting rules before the first group as group 0.
+fn group(chain: &Chain, rule: usize) -> usize {
+    chain.names.partition_point(|&(start, _)| start <= rule)
+}
+
+/// The name of the group a rule belongs to, for messages.
+fn name(chain: &Chain, rule: usize) -> String {
+    chain.rule_name(rule).map(str::to_string).unwrap_or_else(|| format!("rule {}", rule))
+}
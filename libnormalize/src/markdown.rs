@@ -0,0 +1,331 @@
+/// Translation of the prose in Markdown documents.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use crate::chain::Chain;
+use crate::stream::{self, Tracker};
+use crate::utf8;
+
+/// Translate a Markdown document a line at a time, leaving code and links alone: fenced and
+/// indented code blocks, inline code spans, link destinations and reference labels (the `(url)`
+/// in `[text](url)` and the `[label]` in `[text][label]`), link reference definitions, autolinks
+/// (`<url>`), and bare `http://` and `https://` URLs are copied over unchanged, and only the prose
+/// around them is translated. Invalid UTF-8 is decoded as U+FFFD. A character that a strict chain rejects (see
+/// [`Chain::is_strict`]) stops the translation with an error saying which line it is on.
+///
+/// Returns whether the translation changed anything.
+pub fn translate_markdown<R: BufRead, W: Write>(chain: &Chain, mut input: R, mut output: W)
+    -> io::Result<bool> {
+    let mut markdown: Markdown = Markdown::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut changed: bool = false;
+    let mut tracker: Tracker = Tracker::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 { break; }
+        let text: Cow<str> = utf8::from_utf8_lossy(&line);
+        for (start, protected, segment) in markdown.segments(&text) {
+            if protected {
+                output.write_all(segment.as_bytes())?;
+                continue;
+            }
+            let translated: String = chain.try_translate_str(segment).map_err(|mut denied| {
+                denied.offset += start;
+                stream::denied_error(denied, &text, &mut tracker)
+            })?;
+            changed |= translated != segment;
+            output.write_all(translated.as_bytes())?;
+        }
+        if chain.is_strict() { tracker.feed(&text, []); }
+    }
+    output.flush()?;
+    Ok(changed)
+}
+
+/// Splits a Markdown document into prose and code, a line at a time, keeping track of whether
+/// it is inside a fenced code block, a paragraph or a list item.
+#[derive(Debug, Clone, Default)]
+pub struct Markdown {
+    /// The character (a backtick or a tilde) and length of the fence that opened the code block
+    /// we're in, if we're in one.
+    fence: Option<(u8, usize)>,
+    /// Whether the last line was part of a paragraph. An indented line right after one continues
+    /// the paragraph rather than starting a code block.
+    paragraph: bool,
+    /// The column the content of the list item we're in starts at, if we're in one. Code blocks
+    /// in a list item are indented four columns past it.
+    list: Option<usize>,
+}
+
+impl Markdown {
+    pub fn new() -> Markdown {
+        Markdown::default()
+    }
+
+    /// Split the next line of the document (including its newline) into prose and code, in the
+    /// same form as [`Chain::segments`]: each region with its byte offset in `line` and whether
+    /// it is protected (code, or part of a link).
+    pub fn segments<'a>(&mut self, line: &'a str) -> Vec<(usize, bool, &'a str)> {
+        let mut segments: Vec<(usize, bool, &'a str)> = Vec::new();
+        let mut pos: usize = 0;
+        for (start, end) in self.protected(line) {
+            if start > pos { segments.push((pos, false, &line[pos..start])); }
+            segments.push((start, true, &line[start..end]));
+            pos = end;
+        }
+        if pos < line.len() { segments.push((pos, false, &line[pos..])); }
+        segments
+    }
+
+    /// Find the protected regions of a line, as byte ranges in order.
+    fn protected(&mut self, line: &str) -> Vec<(usize, usize)> {
+        let indent: usize = line.bytes().take(4).take_while(|&b| b == b' ').count();
+        let marker: Option<(u8, usize)> = if indent < 4 { fence(&line[indent..]) } else { None };
+        if let Some((c, n)) = self.fence {
+            let closes: bool = marker.is_some_and(|(mc, mn)| {
+                mc == c && mn >= n && line[indent + mn..].trim().is_empty()
+            });
+            if closes { self.fence = None; }
+            return vec![(0, line.len())];
+        }
+        if line.trim().is_empty() {
+            self.paragraph = false;
+            return Vec::new();
+        }
+
+        let (columns, bytes) = indentation(line);
+        if columns < self.list.unwrap_or(0) && !self.paragraph { self.list = None; }
+        if columns >= self.list.unwrap_or(0) + 4 && !self.paragraph {
+            return vec![(0, line.len())];
+        }
+        if let Some((c, n)) = marker {
+            // the info string after a backtick fence can't have backticks in it; if it does, the
+            // line is a code span instead
+            if c != b'`' || !line[indent + n..].contains('`') {
+                self.fence = Some((c, n));
+                self.paragraph = false;
+                return vec![(0, line.len())];
+            }
+        }
+
+        if columns < 4 {
+            if let Some(width) = list_marker(&line[bytes..]) { self.list = Some(columns + width); }
+        }
+        // a heading is a line of its own, so it doesn't start a paragraph
+        self.paragraph = !line[bytes..].starts_with('#');
+        inline(line)
+    }
+}
+
+/// If `text` starts with a code fence (three or more backticks or tildes), return its character
+/// and length.
+fn fence(text: &str) -> Option<(u8, usize)> {
+    let c: u8 = *text.as_bytes().first()?;
+    if c != b'`' && c != b'~' { return None; }
+    let n: usize = run(text.as_bytes(), c);
+    (n >= 3).then_some((c, n))
+}
+
+/// The width of the indentation at the start of a line, in columns (with tabs stopping every four
+/// columns) and in bytes.
+fn indentation(line: &str) -> (usize, usize) {
+    let mut columns: usize = 0;
+    for (i, b) in line.bytes().enumerate() {
+        match b {
+            b' ' => columns += 1,
+            b'\t' => columns += 4 - columns % 4,
+            _ => return (columns, i),
+        }
+    }
+    (columns, line.len())
+}
+
+/// If `text` starts a list item (with `-`, `+`, `*`, or a number followed by `.` or `)`), return
+/// the width of its marker and the spaces after it: the column its content starts at, relative
+/// to the marker.
+fn list_marker(text: &str) -> Option<usize> {
+    let bytes: &[u8] = text.as_bytes();
+    let digits: usize = bytes.iter().take(9).take_while(|b| b.is_ascii_digit()).count();
+    let marker: usize = match bytes.first()? {
+        b'-' | b'+' | b'*' => 1,
+        _ if digits > 0 && matches!(bytes.get(digits), Some(b'.' | b')')) => digits + 1,
+        _ => return None,
+    };
+    let spaces: usize = run(&bytes[marker..], b' ');
+    let rest: &str = text[marker + spaces..].trim_end();
+    if spaces == 0 && !rest.is_empty() { return None; }
+    // content that starts five or more columns after the marker is an indented code block, so
+    // the item itself starts one column after the marker
+    Some(if rest.is_empty() || spaces > 4 { marker + 1 } else { marker + spaces })
+}
+
+/// Find the code spans and links in a line of prose.
+fn inline(line: &str) -> Vec<(usize, usize)> {
+    let bytes: &[u8] = line.as_bytes();
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut i: usize = 0;
+    if let Some(end) = definition(line) {
+        spans.push((0, end));
+        i = end;
+    }
+    while i < bytes.len() {
+        let len: Option<usize> = match bytes[i] {
+            b'\\' => {
+                // an escaped character is always prose
+                i += 2;
+                continue;
+            }
+            b'`' => {
+                let n: usize = run(&bytes[i..], b'`');
+                match code_span(&bytes[i + n..], n) {
+                    Some(len) => Some(n + len),
+                    None => {
+                        // an unmatched run of backticks is just text
+                        i += n;
+                        continue;
+                    }
+                }
+            }
+            b'(' if i > 0 && bytes[i - 1] == b']' => destination(&bytes[i..]),
+            b'[' if i > 0 && bytes[i - 1] == b']' => {
+                bytes[i..].iter().position(|&b| b == b']').map(|end| end + 1)
+            }
+            b'<' => autolink(&line[i..]),
+            b'h' if i == 0 || !bytes[i - 1].is_ascii_alphanumeric() => url(&line[i..]),
+            _ => None,
+        };
+        match len {
+            Some(len) => {
+                spans.push((i, i + len));
+                i += len;
+            }
+            None => i += 1,
+        }
+    }
+    spans
+}
+
+/// The number of times `c` is repeated at the start of `bytes`.
+fn run(bytes: &[u8], c: u8) -> usize {
+    bytes.iter().take_while(|&&b| b == c).count()
+}
+
+/// Find the end of a code span opened by `n` backticks, given the rest of the line: the end of
+/// the next run of exactly `n` backticks.
+fn code_span(bytes: &[u8], n: usize) -> Option<usize> {
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let m: usize = run(&bytes[i..], b'`');
+        if m == n { return Some(i + m); }
+        i += m;
+    }
+    None
+}
+
+/// The length of a link destination in parentheses (with its title, if it has one), counting
+/// balanced parentheses inside it.
+fn destination(bytes: &[u8]) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 { return Some(i + 1); }
+            }
+            b'\n' => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The length of an autolink (like `<https://example.com>`) at the start of `text`.
+fn autolink(text: &str) -> Option<usize> {
+    let rest: &str = &text[1..];
+    let scheme: usize = rest.find(':')?;
+    let valid: bool = (2..=32).contains(&scheme)
+        && rest.as_bytes()[0].is_ascii_alphabetic()
+        && rest[..scheme].bytes().all(|b| b.is_ascii_alphanumeric() || b"+.-".contains(&b));
+    if !valid { return None; }
+    let end: usize = rest.find(|c: char| c == '>' || c == '<' || c.is_whitespace())?;
+    (rest.as_bytes()[end] == b'>').then_some(end + 2)
+}
+
+/// The length of a bare URL at the start of `text`, leaving out punctuation at the end that is
+/// more likely to belong to the sentence.
+fn url(text: &str) -> Option<usize> {
+    if !text.starts_with("http://") && !text.starts_with("https://") { return None; }
+    let end: usize = text.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(text.len());
+    Some(text[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', ')']).len())
+}
+
+/// The length of the label and destination of a link reference definition (like
+/// `[label]: https://example.com`) that `line` is, if it is one.
+fn definition(line: &str) -> Option<usize> {
+    let trimmed: &str = line.trim_start_matches(' ');
+    let indent: usize = line.len() - trimmed.len();
+    if indent > 3 || !trimmed.starts_with('[') { return None; }
+    let close: usize = trimmed.find("]:")?;
+    if close < 2 || trimmed[1..close].contains(['[', ']']) { return None; }
+    let rest: &str = &trimmed[close + 2..];
+    let start: usize = rest.len() - rest.trim_start().len();
+    let len: usize = rest[start..].find(char::is_whitespace).unwrap_or(rest.len() - start);
+    if len == 0 { return None; }
+    Some(indent + close + 2 + start + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    fn translate(text: &str) -> String {
+        let chain: Chain = config::parse_str("[[translator]]\ntype = \"lookup\"\nsource = \"é\"\n\
+            target = \"e\"\n", Format::Toml).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        translate_markdown(&chain, text.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn indented_code_blocks_are_protected() {
+        let text: &str = "café\n\n    let café = 1;\n\n    café();\n\tcafé();\ncafé\n";
+        assert_eq!(translate(text), "cafe\n\n    let café = 1;\n\n    café();\n\tcafé();\ncafe\n");
+    }
+
+    #[test]
+    fn indented_lines_can_continue_a_paragraph() {
+        assert_eq!(translate("café\n    café\n"), "cafe\n    cafe\n");
+        assert_eq!(translate("# café\n    café\n"), "# cafe\n    café\n");
+    }
+
+    #[test]
+    fn code_blocks_in_list_items_are_indented_past_the_item() {
+        let text: &str = "- café\n\n    café\n\n      café\n\n10. café\n\n        café\n";
+        assert_eq!(translate(text),
+                   "- cafe\n\n    cafe\n\n      café\n\n10. cafe\n\n        café\n");
+        assert_eq!(translate("- café\n\ncafé\n\n    café\n"), "- cafe\n\ncafe\n\n    café\n");
+    }
+}
@@ -0,0 +1,83 @@
+/// Homoglyph obfuscation: running a chain backwards, for red-team and filter testing.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use crate::chain::Chain;
+use crate::detect::{self, Class};
+
+/// Replaces ASCII characters with homoglyphs, producing text that looks the same but isn't, for
+/// phishing-awareness training and for testing filters. The homoglyphs come from inverting a
+/// chain: every confusable the chain turns into a single ASCII character (see
+/// [`detect::classify`]) is a homoglyph for that character, so normalizing the output with the
+/// same chain gives back the input.
+#[derive(Debug, Clone)]
+pub struct Obfuscator {
+    /// The homoglyphs each ASCII character can be replaced with, in codepoint order. Characters
+    /// that aren't in the map, or have no homoglyphs, are left alone.
+    pub homoglyphs: BTreeMap<char, Vec<char>>,
+    /// The fraction of the characters that have homoglyphs to replace, from 0 to 1.
+    pub rate: f64,
+    /// The state of the random number generator (SplitMix64) that decides which characters are
+    /// replaced, and with which homoglyph.
+    state: u64,
+}
+
+impl Obfuscator {
+    /// Invert a chain into an obfuscator that replaces every character it can. The same `seed`
+    /// always gives the same choices for the same input.
+    pub fn new(chain: &Chain, seed: u64) -> Obfuscator {
+        let mut homoglyphs: BTreeMap<char, Vec<char>> = BTreeMap::new();
+        for (c, replacement) in chain.compile() {
+            let mut chars = replacement.chars();
+            let (Some(ascii), None) = (chars.next(), chars.next()) else { continue };
+            if !ascii.is_ascii_graphic() || detect::classify(chain, c) != Some(Class::Confusables) {
+                continue;
+            }
+            homoglyphs.entry(ascii).or_default().push(c);
+        }
+        Obfuscator { homoglyphs, rate: 1.0, state: seed }
+    }
+
+    /// Obfuscate a string. Each character with homoglyphs is replaced with one of them, picked at
+    /// random, with a probability of `rate`.
+    pub fn obfuscate(&mut self, source: &str) -> String {
+        let mut out: String = String::with_capacity(source.len() * 2);
+        for c in source.chars() {
+            let candidates: usize = self.homoglyphs.get(&c).map_or(0, Vec::len);
+            if candidates == 0 || self.next_f64() >= self.rate {
+                out.push(c);
+                continue;
+            }
+            let pick: usize = (self.next_u64() % candidates as u64) as usize;
+            out.push(self.homoglyphs[&c][pick]);
+        }
+        out
+    }
+
+    /// The next random number.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z: u64 = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// The next random number, between 0 (inclusive) and 1 (exclusive).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,201 @@
+/// Perfect hash tables for looking up codepoints.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// How many keys share a bucket on average. Every bucket gets its own displacement, so fewer keys
+/// per bucket makes the table quicker to build but the displacement list longer.
+const BUCKET_SIZE: usize = 4;
+
+/// How many displacements to try for a bucket before giving up and building a bigger table.
+const MAX_DISPLACEMENT: u32 = 1 << 16;
+
+/// Marks a slot with no entry in it.
+const EMPTY: u32 = u32::MAX;
+
+/// A read-only map from codepoints to values, built once with a perfect hash function so that a
+/// lookup is always two hashes and a single comparison, however big the table is. The keys are
+/// hashed into buckets, and each bucket is given a displacement that sends every key in it to its
+/// own slot (the "hash and displace" construction), so no two keys ever collide.
+///
+/// This is what lookup translators and map translators use for their tables, which can hold
+/// thousands of entries (as with the confusables preset); map tables too big even for this are
+/// stored as an FST instead (see [`crate::fst::Fst`]).
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "HashMap<u32, V>", into = "HashMap<u32, V>")]
+#[serde(bound(serialize = "V: Clone + Serialize", deserialize = "V: Deserialize<'de>"))]
+pub struct PerfectMap<V> {
+    /// The entries, sorted by key.
+    entries: Vec<(u32, V)>,
+    /// The displacement for each bucket.
+    displacements: Vec<u32>,
+    /// The index in `entries` of the entry in each slot, or `EMPTY`. There are a power of two of
+    /// them, so a hash is reduced to a slot with a mask.
+    slots: Vec<u32>,
+}
+
+impl<V> PerfectMap<V> {
+    /// Build a table. If a key appears more than once, the first occurrence wins.
+    pub fn new(mut entries: Vec<(u32, V)>) -> PerfectMap<V> {
+        // sort_by_key is stable, so the first occurrence of a key is the one dedup keeps
+        entries.sort_by_key(|&(key, _)| key);
+        entries.dedup_by_key(|entry| entry.0);
+        let buckets: usize = entries.len().div_ceil(BUCKET_SIZE).max(1);
+        let mut size: usize = (entries.len() + entries.len() / 4).next_power_of_two();
+        loop {
+            if let Some((displacements, slots)) = place(&entries, buckets, size) {
+                return PerfectMap { entries, displacements, slots };
+            }
+            size *= 2;
+        }
+    }
+
+    /// Look up a codepoint.
+    pub fn get(&self, key: u32) -> Option<&V> {
+        let bucket: usize = hash(key, 0) as usize % self.displacements.len();
+        let slot: usize = hash(key, self.displacements[bucket]) as usize & (self.slots.len() - 1);
+        self.entries.get(self.slots[slot] as usize)
+            .filter(|&&(found, _)| found == key)
+            .map(|(_, value)| value)
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every entry, in codepoint order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &V)> {
+        self.entries.iter().map(|(key, value)| (*key, value))
+    }
+}
+
+impl<V> From<HashMap<u32, V>> for PerfectMap<V> {
+    fn from(table: HashMap<u32, V>) -> PerfectMap<V> {
+        PerfectMap::new(table.into_iter().collect())
+    }
+}
+
+impl<V> From<PerfectMap<V>> for HashMap<u32, V> {
+    fn from(table: PerfectMap<V>) -> HashMap<u32, V> {
+        table.entries.into_iter().collect()
+    }
+}
+
+/// Find a displacement for every bucket so that all the keys land in different slots of a table
+/// with `size` slots, starting with the fullest buckets (which are the hardest to place). Returns
+/// the displacements and the slots, or `None` if some bucket couldn't be placed.
+fn place<V>(entries: &[(u32, V)], buckets: usize, size: usize) -> Option<(Vec<u32>, Vec<u32>)> {
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); buckets];
+    for (i, &(key, _)) in entries.iter().enumerate() {
+        members[hash(key, 0) as usize % buckets].push(i);
+    }
+    let mut order: Vec<usize> = (0..buckets).filter(|&bucket| !members[bucket].is_empty())
+        .collect();
+    order.sort_by_key(|&bucket| Reverse(members[bucket].len()));
+
+    let mut displacements: Vec<u32> = vec![0; buckets];
+    let mut slots: Vec<u32> = vec![EMPTY; size];
+    let mut taken: Vec<usize> = Vec::new();
+    for bucket in order {
+        displacements[bucket] = (1..=MAX_DISPLACEMENT).find(|&displacement| {
+            taken.clear();
+            members[bucket].iter().all(|&i| {
+                let slot: usize = hash(entries[i].0, displacement) as usize & (size - 1);
+                let free: bool = slots[slot] == EMPTY && !taken.contains(&slot);
+                taken.push(slot);
+                free
+            })
+        })?;
+        for (&i, &slot) in members[bucket].iter().zip(&taken) {
+            slots[slot] = i as u32;
+        }
+    }
+    Some((displacements, slots))
+}
+
+/// Hash a codepoint with a seed (the finalizer from MurmurHash3, which mixes every bit of the
+/// input into every bit of the output).
+fn hash(key: u32, seed: u32) -> u32 {
+    let mut x: u32 = key ^ seed.wrapping_mul(0x9E37_79B9);
+    x = (x ^ (x >> 16)).wrapping_mul(0x85EB_CA6B);
+    x = (x ^ (x >> 13)).wrapping_mul(0xC2B2_AE35);
+    x ^ (x >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_key() {
+        let keys: Vec<u32> = (0..5000).map(|i| i * 7 + 0x80).chain([0, 0x10FFFF]).collect();
+        let table: PerfectMap<u32> = PerfectMap::new(keys.iter().map(|&k| (k, k + 1)).collect());
+        assert_eq!(table.len(), keys.len());
+        for key in keys {
+            assert_eq!(table.get(key), Some(&(key + 1)));
+        }
+    }
+
+    #[test]
+    fn missing_keys() {
+        let table: PerfectMap<u32> = PerfectMap::new((0..100).map(|i| (i * 2, i)).collect());
+        for key in (0..200).filter(|key| key % 2 == 1).chain([200, 0x10FFFF, u32::MAX]) {
+            assert_eq!(table.get(key), None, "{:#x}", key);
+        }
+    }
+
+    #[test]
+    fn keys_that_hash_to_the_same_bucket() {
+        // enough keys that all hash to bucket 0 to fill several buckets' worth
+        let entries: Vec<(u32, u32)> = (0..).filter(|&key| hash(key, 0).is_multiple_of(4))
+            .take(16)
+            .map(|key| (key, key))
+            .collect();
+        let table: PerfectMap<u32> = PerfectMap::new(entries.clone());
+        assert_eq!(table.displacements.len(), 4);
+        for (key, value) in entries {
+            assert_eq!(table.get(key), Some(&value));
+        }
+        let mut slots: Vec<u32> = table.slots.iter().copied().filter(|&i| i != EMPTY).collect();
+        slots.sort_unstable();
+        assert_eq!(slots, (0..16).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn first_occurrence_wins() {
+        let table: PerfectMap<&str> = PerfectMap::new(vec![(1, "a"), (2, "b"), (1, "c")]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(1), Some(&"a"));
+        assert_eq!(table.iter().collect::<Vec<(u32, &&str)>>(), vec![(1, &"a"), (2, &"b")]);
+    }
+
+    #[test]
+    fn empty_table() {
+        let table: PerfectMap<u32> = PerfectMap::new(Vec::new());
+        assert!(table.is_empty());
+        assert_eq!(table.get(0), None);
+        assert_eq!(table.get(u32::MAX), None);
+        assert_eq!(table.iter().count(), 0);
+    }
+}
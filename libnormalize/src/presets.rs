@@ -0,0 +1,334 @@
+/// Built-in translator configurations.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use indexmap::IndexMap;
+use tracing::warn;
+use crate::cache;
+use crate::config::{self, Config, ConfigError, Format, Global, Location, Section, SectionKind};
+
+/// A configuration that ships with the library. Presets are written in the same TOML format as
+/// config files (see [`crate::config::parse`]), so any of them can be used as a starting point
+/// for a custom config.
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The Unicode blocks the preset has translators for.
+    pub blocks: &'static [&'static str],
+    /// The preset's configuration, in TOML.
+    pub source: &'static str,
+    /// The same configuration as [`Preset::source`], serialized as JSON by the build script
+    /// (`build.rs`) so that it doesn't have to be parsed as TOML at runtime.
+    pub data: &'static [u8],
+}
+
+impl Preset {
+    /// Load the preset's configuration from its serialized [`Preset::data`]. Each of the built-in
+    /// presets in [`PRESETS`] is only loaded the first time it is used; later calls get a copy of
+    /// the result.
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        let parse = || {
+            let data: &str = std::str::from_utf8(self.data).map_err(|e| e.to_string())?;
+            config::from_str(data, Format::Json).map_err(|e| e.to_string())
+        };
+        let parsed: Result<Config, String> =
+            match PRESETS.iter().position(|preset| std::ptr::eq(preset, self)) {
+                Some(i) => PARSED[i].get_or_init(parse).clone(),
+                None => parse(),
+            };
+        parsed.map_err(|e| ConfigError::invalid("preset", self.name, e))
+    }
+}
+
+/// The parsed configuration of each built-in preset, in the same order as [`PRESETS`], filled in
+/// the first time each one is used (see [`Preset::config`]).
+static PARSED: [OnceLock<Result<Config, String>>; PRESETS.len()] =
+    [const { OnceLock::new() }; PRESETS.len()];
+
+/// Every built-in preset.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "math",
+        description: "Mathematical bold, italic, script, fraktur, double-struck, sans-serif and \
+                      monospace letters and digits",
+        blocks: &["Mathematical Alphanumeric Symbols", "Letterlike Symbols"],
+        source: include_str!("presets/math.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/math.json")),
+    },
+    Preset {
+        name: "fullwidth",
+        description: "Fullwidth ASCII and the ideographic space",
+        blocks: &["Halfwidth and Fullwidth Forms", "CJK Symbols and Punctuation"],
+        source: include_str!("presets/fullwidth.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/fullwidth.json")),
+    },
+    Preset {
+        name: "cyrillic",
+        description: "Cyrillic letters that look like Latin letters",
+        blocks: &["Cyrillic", "Cyrillic Supplement"],
+        source: include_str!("presets/cyrillic.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/cyrillic.json")),
+    },
+    Preset {
+        name: "greek",
+        description: "Greek letters that look like Latin letters",
+        blocks: &["Greek and Coptic"],
+        source: include_str!("presets/greek.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/greek.json")),
+    },
+    Preset {
+        name: "punctuation",
+        description: "Typographic quotes, dashes, ellipses and spaces",
+        blocks: &["Latin-1 Supplement", "General Punctuation", "Mathematical Operators"],
+        source: include_str!("presets/punctuation.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/punctuation.json")),
+    },
+    Preset {
+        name: "ligatures",
+        description: "Latin ligatures like ﬁ and ﬀ, split into their letters",
+        blocks: &["Latin Extended-A", "Alphabetic Presentation Forms"],
+        source: include_str!("presets/ligatures.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/ligatures.json")),
+    },
+    Preset {
+        name: "invisible",
+        description: "Removes zero-width and other invisible formatting characters",
+        blocks: &["Latin-1 Supplement", "General Punctuation", "Arabic Presentation Forms-B"],
+        source: include_str!("presets/invisible.toml"),
+        data: include_bytes!(concat!(env!("OUT_DIR"), "/presets/invisible.json")),
+    },
+];
+
+/// The preset used when there is no other configuration.
+pub const DEFAULT: &str = "math";
+
+/// The name that selects every preset at once, in the order they are listed in [`PRESETS`].
+pub const ALL: &str = "all";
+
+/// Find a preset by name.
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// Combine several presets into one configuration, in the order they are given, the same way
+/// config layers are combined (see [`crate::config::resolve`]). [`ALL`] stands for every preset.
+pub fn compose<S: AsRef<str>>(names: &[S]) -> Result<Config, ConfigError> {
+    compose_with(names, &[])
+}
+
+/// Like [`compose`], but with presets loaded from a data directory (see [`load_dir`]) as well as
+/// the built-in ones. A loaded preset with the same name as a built-in one replaces it, and
+/// [`ALL`] includes the loaded presets, after the built-in ones.
+pub fn compose_with<S: AsRef<str>>(names: &[S], external: &[External])
+    -> Result<Config, ConfigError> {
+    let mut composed: Config = Config::default();
+    for name in names {
+        let name: &str = name.as_ref();
+        if name == ALL {
+            for preset in PRESETS {
+                let config: Config = match config_of(preset.name, external) {
+                    Some(config) => config,
+                    None => preset.config()?,
+                };
+                composed.extend(config);
+            }
+            external.iter().filter(|preset| find(&preset.name).is_none())
+                .for_each(|preset| composed.extend(preset.config.clone()));
+            continue;
+        }
+        let config: Config = match (config_of(name, external), find(name)) {
+            (Some(config), _) => config,
+            (None, Some(preset)) => preset.config()?,
+            (None, None) => {
+                let known: Vec<&str> = PRESETS.iter().map(|preset| preset.name)
+                    .chain(external.iter().map(|preset| preset.name.as_str()))
+                    .collect();
+                return Err(ConfigError::invalid("preset", name, format!(
+                    "unknown preset (expected one of {}, or {})", known.join(", "), ALL)));
+            }
+        };
+        composed.extend(config);
+    }
+    Ok(composed)
+}
+
+/// The configuration of the loaded preset with this name, if there is one.
+fn config_of(name: &str, external: &[External]) -> Option<Config> {
+    external.iter().find(|preset| preset.name == name).map(|preset| preset.config.clone())
+}
+
+/// A preset loaded from a data directory at runtime, rather than built in.
+#[derive(Debug, Clone)]
+pub struct External {
+    pub name: String,
+    /// The file it was loaded from.
+    pub path: PathBuf,
+    pub config: Config,
+}
+
+/// The environment variable that names a data directory to load presets from.
+pub const DATA_DIR_ENV: &str = "UTF_NORMALIZE_DATA_DIR";
+
+/// The data directory named by the `UTF_NORMALIZE_DATA_DIR` environment variable, if it is set.
+pub fn env_data_dir() -> Option<PathBuf> {
+    std::env::var_os(DATA_DIR_ENV).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+/// The name of the file in a data directory that is read as Unicode's confusables data (see
+/// [`parse_confusables`]).
+pub const CONFUSABLES_FILE: &str = "confusables.txt";
+
+/// Load every preset in a data directory, so that organizations can ship updated tables without
+/// rebuilding. Config files (`.toml`, `.json`, `.yaml` or `.yml`) become presets named after the
+/// file, and `confusables.txt` (in the format of Unicode's confusables data) becomes the
+/// "confusables" preset. Other files are ignored. Presets are returned in file name order.
+pub fn load_dir(dir: &Path) -> Result<Vec<External>, ConfigError> {
+    load_dir_cached(dir, None)
+}
+
+/// Like [`load_dir`], but `confusables.txt` is precompiled into a table in `cache_dir` (if given),
+/// which later calls load instead of parsing the text again until `confusables.txt` changes.
+/// Failing to write the table is only a warning.
+pub fn load_dir_cached(dir: &Path, cache_dir: Option<&Path>)
+    -> Result<Vec<External>, ConfigError> {
+    let io_error = |source: std::io::Error| ConfigError::Io { path: dir.to_path_buf(), source };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>().map_err(io_error)?;
+    paths.sort();
+
+    let mut presets: Vec<External> = Vec::new();
+    for path in paths.into_iter().filter(|path| path.is_file()) {
+        let extension: Option<&str> = path.extension().and_then(|ext| ext.to_str());
+        let config: Config = if path.file_name().is_some_and(|name| name == CONFUSABLES_FILE) {
+            let mut config: Config = confusables_config(load_confusables(&path, cache_dir)?);
+            config.files.push(path.clone());
+            config
+        } else if matches!(extension, Some("toml" | "json" | "yaml" | "yml")) {
+            config::load(&path, Format::from_path(&path))?
+        } else {
+            continue;
+        };
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+        presets.push(External { name: name.to_string(), path: path.clone(), config });
+    }
+    Ok(presets)
+}
+
+/// Read the table in a `confusables.txt`, from its precompiled copy in `cache_dir` if that is
+/// still fresh, and otherwise by parsing it (and precompiling it for next time).
+fn load_confusables(path: &Path, cache_dir: Option<&Path>)
+    -> Result<Vec<(char, String)>, ConfigError> {
+    let cached: Option<PathBuf> = cache_dir.map(|dir| cache::table_path(dir, path));
+    if let Some(table) = cached.as_deref().and_then(cache::load_table) {
+        return Ok(table);
+    }
+    let data: String = fs::read_to_string(path)
+        .map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+    let table: Vec<(char, String)> = confusables_table(&data, path)?;
+    if let Some(cached) = cached {
+        if let Err(e) = cache::store_table(&cached, &table, path) {
+            warn!("[cache] Could not write {}: {}", cached.display(), e);
+        }
+    }
+    Ok(table)
+}
+
+/// Read Unicode's confusables data (`confusables.txt` from UTS #39) into a configuration with one
+/// lookup translator, named "confusables". Each line maps a character to its prototype, as
+/// codepoints in hex: `0430 ; 0061 ; MA # ...`. Only characters outside ASCII whose prototypes
+/// are all ASCII are kept, since the rest wouldn't make the text any more ASCII. `path` is used
+/// in errors.
+pub fn parse_confusables(data: &str, path: &Path) -> Result<Config, ConfigError> {
+    confusables_table(data, path).map(confusables_config)
+}
+
+/// Parse Unicode's confusables data into a table of the characters to replace and their
+/// prototypes, in file order (see [`parse_confusables`]).
+fn confusables_table(data: &str, path: &Path) -> Result<Vec<(char, String)>, ConfigError> {
+    let mut table: IndexMap<char, String> = IndexMap::new();
+    for (i, line) in data.trim_start_matches('\u{FEFF}').lines().enumerate() {
+        let fields: Vec<&str> = line.split('#').next().unwrap_or("").split(';')
+            .map(str::trim)
+            .collect();
+        if fields.len() == 1 && fields[0].is_empty() { continue; }
+        let parsed: Option<(Vec<char>, String)> = match fields.as_slice() {
+            [source, target, ..] => codepoints(source).zip(codepoints(target)
+                .map(|target| target.into_iter().collect())),
+            _ => None,
+        };
+        let Some((source, target)) = parsed else {
+            let location: Location = Location { path: path.to_path_buf(), line: i + 1,
+                                                column: 1 };
+            return Err(ConfigError::Invalid {
+                section: "confusables".to_string(),
+                field: "source".to_string(),
+                reason: "expected a codepoint and its prototype, in hex and separated by ';'"
+                    .to_string(),
+                location: Some(location),
+            });
+        };
+        if let [c] = source[..] {
+            if !c.is_ascii() && !target.is_empty() && target.is_ascii() {
+                table.insert(c, target);
+            }
+        }
+    }
+    Ok(table.into_iter().collect())
+}
+
+/// Build the "confusables" preset from a table read by [`confusables_table`].
+fn confusables_config(table: Vec<(char, String)>) -> Config {
+    let map: IndexMap<String, String> = table.into_iter()
+        .map(|(c, target)| (format!("U+{:04X}", c as u32), target))
+        .collect();
+    let section: Section = Section {
+        name: Some("confusables".to_string()),
+        priority: 0,
+        kind: SectionKind::Lookup { source: None, target: None, map },
+        origin: Default::default(),
+    };
+    Config {
+        global: Global { use_ascii_filter: Some(true), ..Global::default() },
+        translator: vec![section],
+        ..Config::default()
+    }
+}
+
+/// Parse codepoints written in hex and separated by spaces, like `0072 006E`.
+fn codepoints(field: &str) -> Option<Vec<char>> {
+    field.split_whitespace()
+        .map(|hex| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_presets_match_their_source() {
+        for preset in PRESETS {
+            let embedded: Config = preset.config().unwrap();
+            let parsed: Config = config::from_str(preset.source, Format::Toml).unwrap();
+            assert_eq!(config::to_string(&embedded, Format::Toml).unwrap(),
+                       config::to_string(&parsed, Format::Toml).unwrap(), "{}", preset.name);
+        }
+    }
+}
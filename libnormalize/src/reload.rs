@@ -0,0 +1,175 @@
+/// Reloading of translator chains when their configuration changes.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::cache::Dependency;
+use crate::chain::Chain;
+use crate::config::ConfigError;
+
+/// Builds a chain, returning it along with every config file it was built from.
+pub type Loader = Box<dyn Fn() -> Result<(Chain, Vec<PathBuf>), ConfigError> + Send + Sync>;
+
+/// Holds the active chain for a long-running process, and rebuilds it when any of the config files
+/// it was built from change.
+///
+/// The chain is handed out as an `Arc`, so swapping in a new one is atomic: a caller that is in
+/// the middle of translating with the old chain keeps using it, and the next call to
+/// [`Reloader::chain`] gets the new one. If rebuilding fails, the old chain stays active.
+pub struct Reloader {
+    loader: Loader,
+    state: Mutex<State>,
+}
+
+struct State {
+    chain: Arc<Chain>,
+    files: Vec<PathBuf>,
+    deps: Vec<Dependency>,
+    version: u64,
+}
+
+impl Reloader {
+    /// Build the initial chain with `loader`. The same loader is called again for every reload,
+    /// so it should find and read the config files itself rather than capture parsed configs.
+    pub fn new(loader: Loader) -> Result<Reloader, ConfigError> {
+        let (chain, files) = loader()?;
+        let deps: Vec<Dependency> = dependencies(&files);
+        Ok(Reloader {
+            loader,
+            state: Mutex::new(State { chain: Arc::new(chain), files, deps, version: 1 }),
+        })
+    }
+
+    /// The active chain.
+    pub fn chain(&self) -> Arc<Chain> {
+        self.lock().chain.clone()
+    }
+
+    /// The version of the active chain. This starts at 1, and goes up by one every time a new
+    /// chain is swapped in.
+    pub fn version(&self) -> u64 {
+        self.lock().version
+    }
+
+    /// The config files the active chain was built from.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.lock().files.clone()
+    }
+
+    /// Check whether any config file has changed (or disappeared) since the active chain was
+    /// built, and rebuild it if so. Returns the new version if a chain was swapped in, and
+    /// `None` if nothing changed. A failed rebuild isn't retried until the files change again.
+    pub fn poll(&self) -> Result<Option<u64>, ConfigError> {
+        if self.lock().deps.iter().all(Dependency::is_fresh) { return Ok(None); }
+        self.reload().map(Some).inspect_err(|_| {
+            let mut state: MutexGuard<State> = self.lock();
+            state.deps = dependencies(&state.files);
+        })
+    }
+
+    /// Rebuild the chain unconditionally and swap it in, returning its version.
+    pub fn reload(&self) -> Result<u64, ConfigError> {
+        let (chain, files) = (self.loader)()?;
+        let deps: Vec<Dependency> = dependencies(&files);
+        let mut state: MutexGuard<State> = self.lock();
+        state.chain = Arc::new(chain);
+        state.files = files;
+        state.deps = deps;
+        state.version += 1;
+        Ok(state.version)
+    }
+
+    /// Poll for changes every `interval` on a background thread, calling `on_reload` with the
+    /// result of every reload attempt (a new version, or the error that kept the old chain
+    /// active). The thread runs for as long as the process does.
+    pub fn watch<F>(self: Arc<Self>, interval: Duration, on_reload: F) -> JoinHandle<()>
+        where F: Fn(Result<u64, ConfigError>) + Send + 'static {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match self.poll() {
+                Ok(None) => {}
+                Ok(Some(version)) => on_reload(Ok(version)),
+                Err(e) => on_reload(Err(e)),
+            }
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // a panic while holding the lock can't leave the state half-updated, so ignore poisoning
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Snapshot the metadata of every file a chain was built from. Files that can't be read are left
+/// out; they will be picked up by a reload that is triggered by some other change.
+fn dependencies(files: &[PathBuf]) -> Vec<Dependency> {
+    files.iter().filter_map(|path| Dependency::of(path).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::config::{self, Config, Format};
+
+    /// Load a chain from a config file, like a long-running process would.
+    fn loader(path: PathBuf) -> Loader {
+        Box::new(move || {
+            let config: Config = config::load(&path, Format::Toml)?;
+            Ok((config::build(&config)?, config.files))
+        })
+    }
+
+    #[test]
+    fn edited_configs_are_reloaded() {
+        let dir: PathBuf = std::env::temp_dir()
+            .join(format!("utf-normalize-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join("config.toml");
+        let write = |path: &Path, target: &str| {
+            fs::write(path, format!("[[translator]]\ntype = \"lookup\"\nsource = \"а\"\n\
+                                     target = \"{}\"\n", target)).unwrap();
+        };
+        write(&path, "a");
+
+        let reloader: Reloader = Reloader::new(loader(path.clone())).unwrap();
+        assert_eq!(reloader.version(), 1);
+        assert_eq!(reloader.files(), vec![path.clone()]);
+        assert_eq!(reloader.poll().unwrap(), None);
+        let old: Arc<Chain> = reloader.chain();
+
+        write(&path, "ä");
+        assert_eq!(reloader.poll().unwrap(), Some(2));
+        assert_eq!(reloader.chain().translate_str("ра"), "рä");
+        assert_eq!(old.translate_str("ра"), "рa");
+        assert_eq!(reloader.poll().unwrap(), None);
+
+        // a broken edit keeps the last good chain, and isn't retried until the file changes again
+        fs::write(&path, "[[translator]]\ntype = \"lookup\"\nsource = \"а\"\n").unwrap();
+        assert!(reloader.poll().is_err());
+        assert_eq!(reloader.poll().unwrap(), None);
+        assert_eq!(reloader.version(), 2);
+        assert_eq!(reloader.chain().translate_str("ра"), "рä");
+
+        write(&path, "A");
+        assert_eq!(reloader.poll().unwrap(), Some(3));
+        assert_eq!(reloader.chain().translate_str("ра"), "рA");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
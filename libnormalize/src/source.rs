@@ -0,0 +1,309 @@
+/// Lightweight lexers that find the comments and string literals in source code.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use crate::chain::Chain;
+use crate::stream::{self, Tracker};
+use crate::utf8;
+
+/// A programming language whose comments and string literals can be picked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    /// JavaScript and TypeScript.
+    JavaScript,
+    C,
+    Cpp,
+    /// Java, and C#, which shares its comment and string syntax closely enough.
+    Java,
+    Go,
+}
+
+/// A kind of string literal.
+struct Quote {
+    /// The delimiter that opens and closes it.
+    delimiter: &'static str,
+    /// Whether a backslash escapes the character after it.
+    escapes: bool,
+    /// Whether it can span lines. If it can't, a string left open ends at the end of the line.
+    multiline: bool,
+}
+
+/// The comment and string syntax of a language.
+struct Syntax {
+    line_comment: &'static str,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Whether block comments nest.
+    nested: bool,
+    /// The kinds of string literals, with longer delimiters before their prefixes.
+    quotes: &'static [Quote],
+    /// Whether `'` also starts lifetimes and labels, so it only starts a literal when it is a
+    /// whole character literal, and whether `r#"..."#` raw strings exist (both as in Rust).
+    rust: bool,
+}
+
+const fn quote(delimiter: &'static str, escapes: bool, multiline: bool) -> Quote {
+    Quote { delimiter, escapes, multiline }
+}
+
+const RUST: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested: true,
+    quotes: &[quote("\"", true, true), quote("'", true, false)],
+    rust: true,
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comment: "#",
+    block_comment: None,
+    nested: false,
+    quotes: &[quote("\"\"\"", true, true), quote("'''", true, true), quote("\"", true, false),
+              quote("'", true, false)],
+    rust: false,
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested: false,
+    quotes: &[quote("\"", true, false), quote("'", true, false), quote("`", true, true)],
+    rust: false,
+};
+
+const C: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested: false,
+    quotes: &[quote("\"", true, false), quote("'", true, false)],
+    rust: false,
+};
+
+const GO: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    nested: false,
+    quotes: &[quote("\"", true, false), quote("'", true, false), quote("`", false, true)],
+    rust: false,
+};
+
+impl Language {
+    fn syntax(&self) -> &'static Syntax {
+        match self {
+            Language::Rust => &RUST,
+            Language::Python => &PYTHON,
+            Language::JavaScript => &JAVASCRIPT,
+            Language::C | Language::Cpp | Language::Java => &C,
+            Language::Go => &GO,
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => Ok(Language::Rust),
+            "python" | "py" => Ok(Language::Python),
+            "js" | "javascript" | "ts" | "typescript" => Ok(Language::JavaScript),
+            "c" => Ok(Language::C),
+            "cpp" | "c++" => Ok(Language::Cpp),
+            "java" | "cs" | "c#" | "csharp" => Ok(Language::Java),
+            "go" => Ok(Language::Go),
+            other => Err(format!("unknown language \"{}\" (expected rust, python, js, c, cpp, java \
+                                  or go)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Language::Rust => write!(f, "rust"),
+            Language::Python => write!(f, "python"),
+            Language::JavaScript => write!(f, "js"),
+            Language::C => write!(f, "c"),
+            Language::Cpp => write!(f, "cpp"),
+            Language::Java => write!(f, "java"),
+            Language::Go => write!(f, "go"),
+        }
+    }
+}
+
+/// Find the comments and string literals in source code, as byte ranges in order (delimiters
+/// included).
+///
+/// With the `tree-sitter` feature, the source is parsed with the language's real grammar (see
+/// [`crate::grammar`]). Otherwise, or if the grammar can't be loaded, it is run through a lexer
+/// that knows just enough of each language to tell where comments and strings start and end, and
+/// can be misled by things like JavaScript regular expression literals that contain quotes.
+pub fn literals(language: Language, source: &str) -> Vec<(usize, usize)> {
+    #[cfg(feature = "tree-sitter")]
+    if let Some(spans) = crate::grammar::literals(language, source) { return spans; }
+    lex(language, source)
+}
+
+/// Find the comments and string literals in source code with a lightweight lexer.
+fn lex(language: Language, source: &str) -> Vec<(usize, usize)> {
+    let syntax: &Syntax = language.syntax();
+    let bytes: &[u8] = source.as_bytes();
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let rest: &[u8] = &bytes[i..];
+        let end: Option<usize> = match syntax.block_comment {
+            _ if rest.starts_with(syntax.line_comment.as_bytes()) => {
+                Some(bytes[i..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |n| i + n))
+            }
+            Some((open, close)) if rest.starts_with(open.as_bytes()) => {
+                Some(block_comment_end(bytes, i, open, close, syntax.nested))
+            }
+            _ if syntax.rust && bytes[i] == b'r' && starts_literal(bytes, i) => {
+                raw_string_end(bytes, i)
+            }
+            _ => syntax.quotes.iter()
+                .find(|q| rest.starts_with(q.delimiter.as_bytes()))
+                .filter(|q| !syntax.rust || q.delimiter != "'" || is_char_literal(source, i))
+                .map(|q| string_end(bytes, i, q)),
+        };
+        match end {
+            Some(end) => {
+                spans.push((i, end));
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+    spans
+}
+
+/// Split source code into its comments and string literals and the code between them, in the
+/// same form as [`Chain::segments`]: each region with its byte offset in `source` and whether it
+/// is protected, with the code protected.
+pub fn segments(language: Language, source: &str) -> Vec<(usize, bool, &str)> {
+    let mut segments: Vec<(usize, bool, &str)> = Vec::new();
+    let mut pos: usize = 0;
+    for (start, end) in literals(language, source) {
+        if start > pos { segments.push((pos, true, &source[pos..start])); }
+        segments.push((start, false, &source[start..end]));
+        pos = end;
+    }
+    if pos < source.len() { segments.push((pos, true, &source[pos..])); }
+    segments
+}
+
+/// Translate only the comments and string literals in source code, copying the code between them
+/// over unchanged. Since the input has to be lexed from the start, it is read into memory in full;
+/// invalid UTF-8 in it is decoded as U+FFFD. A character that a strict chain rejects (see
+/// [`Chain::is_strict`]) stops the translation with an error saying where it is, before anything
+/// after the literal it is in is written.
+///
+/// Returns whether the translation changed anything.
+pub fn translate_source<R: Read, W: Write>(chain: &Chain, language: Language, mut input: R,
+                                           mut output: W) -> io::Result<bool> {
+    let mut bytes: Vec<u8> = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let text: String = utf8::from_utf8_lossy(&bytes).into_owned();
+    let mut changed: bool = false;
+    for (start, protected, segment) in segments(language, &text) {
+        if protected {
+            output.write_all(segment.as_bytes())?;
+            continue;
+        }
+        let translated: String = chain.try_translate_str(segment).map_err(|mut denied| {
+            denied.offset += start;
+            stream::denied_error(denied, &text, &mut Tracker::new())
+        })?;
+        changed |= translated != segment;
+        output.write_all(translated.as_bytes())?;
+    }
+    output.flush()?;
+    Ok(changed)
+}
+
+/// Find the end of a block comment that starts at `start`.
+fn block_comment_end(bytes: &[u8], start: usize, open: &str, close: &str, nested: bool)
+    -> usize {
+    let mut depth: usize = 1;
+    let mut i: usize = start + open.len();
+    while i < bytes.len() {
+        if bytes[i..].starts_with(close.as_bytes()) {
+            i += close.len();
+            depth -= 1;
+            if depth == 0 { return i; }
+        } else if nested && bytes[i..].starts_with(open.as_bytes()) {
+            i += open.len();
+            depth += 1;
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// Find the end of a string literal that starts at `start`. A string left open runs to the end of
+/// the input, or of the line if it can't span lines.
+fn string_end(bytes: &[u8], start: usize, quote: &Quote) -> usize {
+    let delimiter: &[u8] = quote.delimiter.as_bytes();
+    let mut i: usize = start + delimiter.len();
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if quote.escapes => i += 2,
+            b'\n' if !quote.multiline => return i,
+            _ if bytes[i..].starts_with(delimiter) => return i + delimiter.len(),
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Whether the `r` at `start` begins a token rather than ending an identifier (allowing for the
+/// `b` of a raw byte string).
+fn starts_literal(bytes: &[u8], start: usize) -> bool {
+    let ident = |i: usize| bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_';
+    match start {
+        0 => true,
+        1 => bytes[0] == b'b' || !ident(0),
+        _ => !ident(start - 1) || (bytes[start - 1] == b'b' && !ident(start - 2)),
+    }
+}
+
+/// Find the end of a Rust raw string (`r"..."` or `r#"..."#`) that starts at `start`, if there is
+/// one there.
+fn raw_string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let hashes: usize = bytes[start + 1..].iter().take_while(|&&b| b == b'#').count();
+    let open: usize = start + 1 + hashes;
+    if bytes.get(open) != Some(&b'"') { return None; }
+    let mut close: Vec<u8> = vec![b'"'];
+    close.resize(1 + hashes, b'#');
+    let end: usize = bytes[open + 1..].windows(close.len()).position(|w| w == close)
+        .map_or(bytes.len(), |n| open + 1 + n + close.len());
+    Some(end)
+}
+
+/// Whether the `'` at `start` opens a Rust character literal, rather than a lifetime or a label.
+fn is_char_literal(source: &str, start: usize) -> bool {
+    let mut chars = source[start + 1..].chars();
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
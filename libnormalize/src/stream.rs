@@ -0,0 +1,522 @@
+/// Translation of text as it is read, rather than all at once.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read, Write};
+use std::panic;
+use std::thread;
+use crate::chain::{Change, Chain, Denied};
+use crate::detect::{self, Finding};
+use crate::utf8;
+
+/// How many bytes are read from the input at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How much of a line is held back waiting for its newline before it is translated anyway.
+const MAX_PENDING: usize = 1024 * 1024;
+
+/// About how much text each thread is given at a time by [`translate_parallel`].
+const BATCH_SIZE: usize = 4 * 1024 * 1024;
+
+/// Decodes UTF-8 that arrives in pieces. A multibyte character that is split between two pieces
+/// is held back until the rest of it arrives; bytes that can never be valid UTF-8 are decoded as
+/// U+FFFD REPLACEMENT CHARACTER, the same way `String::from_utf8_lossy` does.
+/// Validation uses [`utf8::from_utf8`], so large chunks are checked with SIMD instructions.
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Utf8Decoder {
+        Utf8Decoder { pending: Vec::new() }
+    }
+
+    /// Decode the next piece of input, appending every complete character to `out`.
+    pub fn decode(&mut self, bytes: &[u8], out: &mut String) {
+        self.pending.extend_from_slice(bytes);
+        let mut start: usize = 0;
+        loop {
+            match utf8::from_utf8(&self.pending[start..]) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    start = self.pending.len();
+                    break;
+                }
+                Err(e) => {
+                    let valid: usize = e.valid_up_to();
+                    out.push_str(utf8::from_utf8(&self.pending[start..start + valid])
+                        .unwrap_or_default());
+                    start += valid;
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push('\u{FFFD}');
+                            start += len;
+                        }
+                        // the input ends partway through a character
+                        None => break,
+                    }
+                }
+            }
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Finish decoding. If the input ended partway through a character, that character is
+    /// decoded as U+FFFD.
+    pub fn finish(&mut self, out: &mut String) {
+        if !self.pending.is_empty() {
+            out.push('\u{FFFD}');
+            self.pending.clear();
+        }
+    }
+}
+
+/// Read text from `input` a chunk at a time, calling `each` with pieces that end at a newline
+/// (so that protected regions and normalization see whole lines) and, once the input ends, with
+/// whatever is left. UTF-8 sequences split between reads are put back together, and invalid UTF-8
+/// is decoded as U+FFFD. Only one chunk and the line in progress are held in memory, except that
+/// a line longer than `MAX_PENDING` is passed on in pieces rather than held until it ends.
+pub fn read_chunks<R: Read, F: FnMut(&str) -> io::Result<()>>(mut input: R, mut each: F)
+    -> io::Result<()> {
+    let mut buf: Vec<u8> = vec![0; CHUNK_SIZE];
+    let mut decoder: Utf8Decoder = Utf8Decoder::new();
+    let mut text: String = String::new();
+    loop {
+        let n: usize = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        decoder.decode(&buf[..n], &mut text);
+        let end: Option<usize> = match text.rfind('\n') {
+            Some(end) => Some(end + 1),
+            None if text.len() > MAX_PENDING => Some(text.len()),
+            None => None,
+        };
+        if let Some(end) = end {
+            each(&text[..end])?;
+            text.drain(..end);
+        }
+    }
+    decoder.finish(&mut text);
+    each(&text)
+}
+
+/// Translate everything from `input` into `output` as it arrives, so that piped input doesn't
+/// have to be read in full before any output is written, and inputs of any size can be translated
+/// in a small, fixed amount of memory (see [`read_chunks`]).
+///
+/// `output` is flushed after every read, so a buffered writer only holds on to output until the
+/// input stalls or the next chunk is read; wrap unbuffered outputs in a `BufWriter`.
+///
+/// Chunks that translation can't change (see [`Chain::leaves_unchanged`]) are written out as they
+/// are, without being translated, so already-clean input goes through at close to the speed it
+/// can be read.
+///
+/// If the chain is strict (see [`Chain::is_strict`]), a denied character, or an unhandled one
+/// when the fallback is [`crate::chain::Fallback::Error`], stops the translation with an
+/// [`io::ErrorKind::InvalidData`] error that says where in the input it is. Everything before the
+/// chunk it is in has already been written by then.
+///
+/// Returns whether the translation changed anything, i.e. whether the output differs from the
+/// decoded input.
+pub fn translate_reader<R: Read, W: Write>(chain: &Chain, input: R, mut output: W)
+    -> io::Result<bool> {
+    let mut changed: bool = false;
+    let mut translated: String = String::new();
+    let mut tracker: Tracker = Tracker::new();
+    read_chunks(input, |text| {
+        match translate_piece(chain, text, &mut translated) {
+            Ok(true) => {
+                changed |= translated != text;
+                output.write_all(translated.as_bytes())?;
+            }
+            Ok(false) => output.write_all(text.as_bytes())?,
+            Err(denied) => return Err(denied_error(denied, text, &mut tracker)),
+        }
+        advance(chain, &mut tracker, text);
+        output.flush()
+    })?;
+    Ok(changed)
+}
+
+/// Translate one piece of a stream into `out`, which is cleared first, with
+/// [`Chain::try_translate_str_into`] (which only fails for strict chains). Returns `false`,
+/// leaving `out` empty, if translation can't change the piece (see [`Chain::leaves_unchanged`]),
+/// so that it can be written out as it is.
+fn translate_piece(chain: &Chain, text: &str, out: &mut String) -> Result<bool, Denied> {
+    out.clear();
+    if chain.leaves_unchanged(text) { return Ok(false); }
+    chain.try_translate_str_into(text, out)?;
+    Ok(true)
+}
+
+/// Move `tracker` past a piece of a stream once it has been translated. Positions are only needed
+/// to report characters that a strict chain rejects (see [`denied_error`]), so the tracker is
+/// left alone for other chains.
+fn advance(chain: &Chain, tracker: &mut Tracker, text: &str) {
+    if chain.is_strict() { tracker.feed(text, []); }
+}
+
+/// The error for a character that a strict chain rejected in a piece of a stream, saying where in
+/// the stream it is. `tracker` must be at the start of the piece.
+pub fn denied_error(denied: Denied, text: &str, tracker: &mut Tracker) -> io::Error {
+    // with pre_normalize, the offset of an unhandled character is in the normalized text, so it
+    // may not fall on a character boundary of the piece
+    let mut offset: usize = denied.offset.min(text.len());
+    while !text.is_char_boundary(offset) { offset -= 1; }
+    let position: Position = tracker.feed(text, [offset])[0];
+    rejected(&denied, &format!("at line {}, column {}", position.line, position.column))
+}
+
+/// The error for a character that a strict chain rejected, with `place` saying where it is (for
+/// input that doesn't have lines and columns to point to, like a JSON string).
+pub fn rejected(denied: &Denied, place: &str) -> io::Error {
+    let what: &str = if denied.unmapped { "unmapped" } else { "denied" };
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("{} character U+{:04X} {}", what, denied.character as u32, place))
+}
+
+/// Like [`translate_reader`], but translating on `threads` threads at once (or one per core, if
+/// `threads` is 0), to make use of every core on a single very large input. The input is read into
+/// batches of about `BATCH_SIZE` bytes, each ending at a newline like the chunks of
+/// [`read_chunks`]; once there is a batch for every thread, they are all translated at once and
+/// written out in order, so the output is exactly what [`translate_reader`] would write. Batches
+/// that translation can't change are written out as they are.
+///
+/// Output is only written (and `output` flushed) once a whole round of batches is done, so this
+/// is no good for following piped input as it arrives, and up to two batches per thread (the
+/// input and its translation) are held in memory at a time. Strict chains stop at the first
+/// character they reject, as with [`translate_reader`].
+///
+/// Returns whether the translation changed anything.
+pub fn translate_parallel<R: Read, W: Write>(chain: &Chain, input: R, mut output: W,
+                                             threads: usize) -> io::Result<bool> {
+    let threads: usize = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let mut changed: bool = false;
+    let mut batches: Vec<String> = vec![String::new()];
+    let mut tracker: Tracker = Tracker::new();
+    read_chunks(input, |text| {
+        let batch: &mut String = batches.last_mut().expect("there is always a batch being filled");
+        batch.push_str(text);
+        if batch.len() >= BATCH_SIZE {
+            if batches.len() < threads {
+                batches.push(String::new());
+            } else {
+                changed |= translate_batches(chain, &mut batches, &mut output, &mut tracker)?;
+            }
+        }
+        Ok(())
+    })?;
+    changed |= translate_batches(chain, &mut batches, &mut output, &mut tracker)?;
+    Ok(changed)
+}
+
+/// Translate a round of batches for [`translate_parallel`], one thread each, write the results
+/// out in order, and leave `batches` with a single empty batch to fill next. `tracker` is kept at
+/// the start of the next batch, as for [`translate_reader`]. Returns whether the translation
+/// changed anything.
+fn translate_batches<W: Write>(chain: &Chain, batches: &mut Vec<String>, output: &mut W,
+                               tracker: &mut Tracker) -> io::Result<bool> {
+    // batches that come back as `None` can be written out as they are
+    let translated: Vec<Result<Option<String>, Denied>> = thread::scope(|scope| {
+        let workers: Vec<thread::ScopedJoinHandle<Result<Option<String>, Denied>>> = batches
+            .iter()
+            .map(|batch| scope.spawn(move || {
+                let mut out: String = String::new();
+                Ok(translate_piece(chain, batch, &mut out)?.then_some(out))
+            }))
+            .collect();
+        workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+    let mut changed: bool = false;
+    for (batch, translated) in batches.iter().zip(translated) {
+        match translated {
+            Ok(Some(translated)) => {
+                changed |= translated != *batch;
+                output.write_all(translated.as_bytes())?;
+            }
+            Ok(None) => output.write_all(batch.as_bytes())?,
+            Err(denied) => {
+                output.flush()?;
+                return Err(denied_error(denied, batch, tracker));
+            }
+        }
+        advance(chain, tracker, batch);
+    }
+    output.flush()?;
+    batches.clear();
+    batches.push(String::new());
+    Ok(changed)
+}
+
+/// Translate a stream one record at a time, where each record ends with `delimiter` (usually a
+/// newline), and flush `output` after every record. This is slower than [`translate_reader`], but
+/// output appears as soon as each record is complete, even when the input arrives slowly (as with
+/// `tail -f`). Invalid UTF-8 in a record is decoded as U+FFFD. Strict chains stop at the first
+/// character they reject, as with [`translate_reader`].
+///
+/// Returns whether the translation changed anything.
+pub fn translate_records<R: BufRead, W: Write>(chain: &Chain, mut input: R, mut output: W,
+                                               delimiter: u8) -> io::Result<bool> {
+    let mut record: Vec<u8> = Vec::new();
+    let mut translated: String = String::new();
+    let mut changed: bool = false;
+    let mut tracker: Tracker = Tracker::new();
+    loop {
+        record.clear();
+        if input.read_until(delimiter, &mut record)? == 0 { break; }
+        let text: Cow<str> = utf8::from_utf8_lossy(&record);
+        match translate_piece(chain, &text, &mut translated) {
+            Ok(true) => {
+                changed |= translated != text;
+                output.write_all(translated.as_bytes())?;
+            }
+            Ok(false) => output.write_all(text.as_bytes())?,
+            Err(denied) => return Err(denied_error(denied, &text, &mut tracker)),
+        }
+        advance(chain, &mut tracker, &text);
+        output.flush()?;
+    }
+    Ok(changed)
+}
+
+/// Where something is in a stream of text. Lines and columns start at 1, and columns count
+/// characters rather than bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// The byte offset from the start of the decoded input.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Keeps track of line and column numbers while text is fed through it piece by piece.
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    /// The position at the end of everything fed in so far.
+    position: Position,
+}
+
+impl Default for Tracker {
+    fn default() -> Tracker {
+        Tracker::new()
+    }
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker { position: Position { offset: 0, line: 1, column: 1 } }
+    }
+
+    /// Find the positions of byte offsets in the next piece of input (in increasing order), then
+    /// move past the piece.
+    pub fn feed(&mut self, text: &str, offsets: impl IntoIterator<Item = usize>) -> Vec<Position> {
+        let start: usize = self.position.offset;
+        let mut done: usize = 0;
+        let mut advance = |position: &mut Position, offset: usize| {
+            for c in text[done..offset].chars() {
+                if c == '\n' {
+                    position.line += 1;
+                    position.column = 1;
+                } else {
+                    position.column += 1;
+                }
+            }
+            position.offset = start + offset;
+            done = offset;
+        };
+        let positions: Vec<Position> = offsets.into_iter()
+            .map(|offset| {
+                advance(&mut self.position, offset);
+                self.position
+            })
+            .collect();
+        advance(&mut self.position, text.len());
+        positions
+    }
+}
+
+/// Find every change that translating a stream would make (see [`Chain::changes`]), along with
+/// its position, calling `found` for each one in order. Offsets are from the start of the decoded
+/// input. Like [`translate_reader`], the input is read in chunks and never held in memory all at
+/// once.
+pub fn changes_reader<R: Read, F: FnMut(Position, Change)>(chain: &Chain, input: R,
+                                                            mut found: F) -> io::Result<()> {
+    let mut tracker: Tracker = Tracker::new();
+    read_chunks(input, |text| {
+        let changes: Vec<Change> = chain.changes(text);
+        let positions: Vec<Position> = tracker.feed(text, changes.iter().map(|c| c.offset));
+        for (position, change) in positions.into_iter().zip(changes) {
+            found(position, Change { offset: position.offset, ..change });
+        }
+        Ok(())
+    })
+}
+
+/// Find every suspicious character in a stream (see [`crate::detect::detect`]), along with its
+/// position, calling `found` for each one in order. Offsets are from the start of the decoded
+/// input. The input is read in chunks that end at newlines (see [`read_chunks`]), so mixed
+/// scripts are only noticed within a line.
+pub fn detect_reader<R: Read, F: FnMut(Position, Finding)>(chain: &Chain, input: R,
+                                                            mut found: F) -> io::Result<()> {
+    let mut tracker: Tracker = Tracker::new();
+    read_chunks(input, |text| {
+        let findings: Vec<Finding> = detect::detect(chain, text);
+        let positions: Vec<Position> = tracker.feed(text, findings.iter().map(|f| f.offset));
+        for (position, finding) in positions.into_iter().zip(findings) {
+            found(position, Finding { offset: position.offset, ..finding });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Format};
+
+    fn strict() -> Chain {
+        config::parse_str("[global]\nfallback = \"error\"\n", Format::Toml).unwrap()
+    }
+
+    /// Decode some bytes in pieces, split at the given offsets.
+    fn decode_split(bytes: &[u8], splits: &[usize]) -> String {
+        let mut decoder: Utf8Decoder = Utf8Decoder::new();
+        let mut out: String = String::new();
+        let mut start: usize = 0;
+        for &end in splits.iter().chain([bytes.len()].iter()) {
+            decoder.decode(&bytes[start..end], &mut out);
+            start = end;
+        }
+        decoder.finish(&mut out);
+        out
+    }
+
+    #[test]
+    fn characters_split_between_chunks() {
+        let text: &str = "aé€𝐀z";
+        let bytes: &[u8] = text.as_bytes();
+        for split in 0..=bytes.len() {
+            assert_eq!(decode_split(bytes, &[split]), text, "split at {}", split);
+        }
+        let every_byte: Vec<usize> = (1..bytes.len()).collect();
+        assert_eq!(decode_split(bytes, &every_byte), text);
+    }
+
+    #[test]
+    fn split_characters_are_held_back_until_they_are_complete() {
+        let mut decoder: Utf8Decoder = Utf8Decoder::new();
+        let mut out: String = String::new();
+        decoder.decode(b"a\xF0\x9D", &mut out);
+        assert_eq!(out, "a");
+        decoder.decode(b"\x90", &mut out);
+        assert_eq!(out, "a");
+        decoder.decode(b"\x80b", &mut out);
+        assert_eq!(out, "a𝐀b");
+    }
+
+    #[test]
+    fn invalid_bytes_at_the_end_of_the_input() {
+        let cases: [&[u8]; 5] = [b"a\xFF", b"a\xE2\x82", b"a\xF0\x9D\x90", b"\xC3", b"a\x80\xE2"];
+        for bytes in cases {
+            let expected: String = String::from_utf8_lossy(bytes).into_owned();
+            for split in 0..=bytes.len() {
+                assert_eq!(decode_split(bytes, &[split]), expected, "{:?} at {}", bytes, split);
+            }
+        }
+        assert_eq!(decode_split(b"a\xE2\x82", &[]), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn invalid_bytes_between_chunks() {
+        let bytes: &[u8] = b"a\xE2\x82z\xFF\xF0\x9D\x90\x80";
+        let expected: String = String::from_utf8_lossy(bytes).into_owned();
+        assert_eq!(expected, "a\u{FFFD}z\u{FFFD}𝐀");
+        for split in 0..=bytes.len() {
+            assert_eq!(decode_split(bytes, &[split]), expected, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn error_fallback_fails_on_an_unmapped_character() {
+        let mut output: Vec<u8> = Vec::new();
+        let e: io::Error = translate_reader(&strict(), "ok\nné\n".as_bytes(), &mut output)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "unmapped character U+00E9 at line 2, column 2");
+    }
+
+    #[test]
+    fn error_fallback_fails_in_parallel_and_by_record() {
+        let input: &str = "ok\nstill ok\nné\n";
+        let e: io::Error = translate_parallel(&strict(), input.as_bytes(), io::sink(), 2)
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unmapped character U+00E9 at line 3, column 2");
+        let e: io::Error = translate_records(&strict(), input.as_bytes(), io::sink(), b'\n')
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unmapped character U+00E9 at line 3, column 2");
+    }
+
+    #[test]
+    fn denied_characters_fail() {
+        let chain: Chain = config::parse_str("[deny]\nsource = [\"U+202E\"]\n", Format::Toml)
+            .unwrap();
+        let e: io::Error = translate_reader(&chain, "a\u{202E}b".as_bytes(), io::sink())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "denied character U+202E at line 1, column 2");
+        assert!(translate_reader(&chain, "ab".as_bytes(), io::sink()).is_ok());
+    }
+
+    #[test]
+    fn error_fallback_passes_ascii() {
+        let mut output: Vec<u8> = Vec::new();
+        assert!(!translate_reader(&strict(), "just ascii\n".as_bytes(), &mut output).unwrap());
+        assert_eq!(output, b"just ascii\n");
+    }
+
+    #[test]
+    fn parallel_output_matches_sequential_output() {
+        let chain: Chain = config::parse_str("presets = [\"math\", \"cyrillic\"]\n", Format::Toml)
+            .unwrap();
+        // a line that ends with a four-byte character split across the first two chunks, then
+        // enough lines for more than one round of batches
+        let mut input: String = "a".repeat(CHUNK_SIZE - 2);
+        input.push_str("𝐀\n");
+        while input.len() < 2 * BATCH_SIZE + CHUNK_SIZE {
+            input.push_str("𝐇𝐞𝐥𝐥𝐨, раураl!\n");
+            input.push_str(&"plain ascii line\n".repeat(100));
+        }
+        input.push_str("no newline at the end 𝐙");
+
+        let mut sequential: Vec<u8> = Vec::new();
+        assert!(translate_reader(&chain, input.as_bytes(), &mut sequential).unwrap());
+        assert!(sequential.starts_with(format!("{}A\nHello, paypal!", "a".repeat(CHUNK_SIZE - 2))
+            .as_bytes()));
+        for threads in [1, 2] {
+            let mut parallel: Vec<u8> = Vec::new();
+            assert!(translate_parallel(&chain, input.as_bytes(), &mut parallel, threads).unwrap());
+            assert!(parallel == sequential, "{} threads", threads);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+/// Translation of delimited text (CSV, TSV and the like), one column at a time.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use csv::{ByteRecord, Reader, ReaderBuilder, Writer, WriterBuilder};
+use crate::chain::Chain;
+use crate::stream;
+use crate::utf8;
+
+/// Translate delimited text a record at a time, translating only the fields in the selected
+/// `columns` and copying the rest over byte for byte. Quoting is understood, so delimiters and
+/// newlines inside quoted fields don't split them; fields are quoted in the output only where they
+/// need to be, and records end with `\n`.
+///
+/// Columns are given by the name in the header (the first record), or by number, starting at 1.
+/// If any are given by name, the header is copied over untranslated. With no columns, every field
+/// of every record is translated. Invalid UTF-8 in a translated field is decoded as U+FFFD. A
+/// character that a strict chain rejects (see [`Chain::is_strict`]) stops the translation with an
+/// error saying which record and field it is in.
+///
+/// Returns whether the translation changed anything.
+pub fn translate_table<R: Read, W: Write>(chain: &Chain, input: R, output: W, delimiter: u8,
+                                          columns: &[String]) -> io::Result<bool> {
+    let mut reader: Reader<R> = ReaderBuilder::new().delimiter(delimiter).has_headers(false)
+        .flexible(true).from_reader(input);
+    let mut writer: Writer<W> = WriterBuilder::new().delimiter(delimiter).flexible(true)
+        .from_writer(output);
+    let by_name: bool = columns.iter().any(|column| column.parse::<usize>().is_err());
+    let mut record: ByteRecord = ByteRecord::new();
+    let mut translated: ByteRecord = ByteRecord::new();
+    // which fields to translate, by index; `None` for all of them
+    let mut selected: Option<Vec<usize>> = None;
+    let mut first: bool = true;
+    let mut changed: bool = false;
+    while reader.read_byte_record(&mut record).map_err(io_error)? {
+        if first {
+            first = false;
+            selected = select(columns, &record)?;
+            if by_name {
+                writer.write_byte_record(&record).map_err(io_error)?;
+                continue;
+            }
+        }
+        translated.clear();
+        for (i, field) in record.iter().enumerate() {
+            if selected.as_ref().is_some_and(|selected| !selected.contains(&i)) {
+                translated.push_field(field);
+                continue;
+            }
+            let text: Cow<str> = utf8::from_utf8_lossy(field);
+            let out: String = chain.try_translate_str(&text).map_err(|denied| {
+                let line: u64 = record.position().map_or(0, |position| position.line());
+                stream::rejected(&denied, &format!("in the record on line {}, field {}", line,
+                                                   i + 1))
+            })?;
+            changed |= out.as_bytes() != field;
+            translated.push_field(out.as_bytes());
+        }
+        writer.write_byte_record(&translated).map_err(io_error)?;
+    }
+    writer.flush()?;
+    Ok(changed)
+}
+
+/// Find the indexes of the selected columns, given the header.
+fn select(columns: &[String], header: &ByteRecord) -> io::Result<Option<Vec<usize>>> {
+    if columns.is_empty() { return Ok(None); }
+    columns.iter()
+        .map(|column| match column.parse::<usize>() {
+            Ok(0) => Err(invalid("columns are numbered from 1".to_string())),
+            Ok(n) => Ok(n - 1),
+            Err(_) => header.iter().position(|name| name == column.as_bytes())
+                .ok_or_else(|| invalid(format!("no column named \"{}\"", column))),
+        })
+        .collect::<io::Result<Vec<usize>>>()
+        .map(Some)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Turn a CSV error into an I/O error, keeping the original if it was one (so that, for example,
+/// a broken pipe is still recognized).
+fn io_error(e: csv::Error) -> io::Error {
+    if !e.is_io_error() { return io::Error::new(io::ErrorKind::InvalidData, e); }
+    match e.into_kind() {
+        csv::ErrorKind::Io(e) => e,
+        kind => io::Error::other(format!("{:?}", kind)),
+    }
+}
@@ -14,9 +14,18 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-/// The Translator is an alias for a function that converts a UTF-32 codepoint (represented as a
-/// `u32`) to another UTF-32 codepoint. How this conversion is achieved is an exercise left to the
-/// developer (although some translator generator functions are provided in this module).
+use std::fmt;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::fst::Fst;
+use crate::inline::InlineStr;
+use crate::phf::PerfectMap;
+
+/// A Translator converts a UTF-32 codepoint (represented as a `u32`) to another UTF-32 codepoint.
+/// The common kinds are plain data, so running one is a `match` rather than a call through a
+/// pointer, and they can be cloned, serialized and inspected; anything else can be written as a
+/// [`Translator::Custom`] function. Translators are usually made with the generator functions in
+/// this module.
 ///
 /// To make this as configurable as possible, a translator does not (and should not!) handle the
 /// entire UTF-32 character set. If a character passed into a translator matches a codepoint that
@@ -25,17 +34,237 @@
 /// ordered data structure (such as a `Vec` or array) and iterators. The `translate()` function
 /// uses `flat_map` on an iterator of translators to lazily evaluate and return on the first
 /// successful translation.
-pub type Translator = Box<dyn Fn(u32) -> Option<u32>>;
+///
+/// Translators are `Send` and `Sync`, so a chain built from them can be shared between threads
+/// (for example, to swap in a reloaded chain while other threads are using the old one).
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Translator {
+    /// See [`range_translation`].
+    Range { source: u32, offset: u32, size: u32 },
+    /// See [`multirange_translation`].
+    #[serde(rename = "multirange")]
+    MultiRange { source: u32, target: u32, size: u32, slice: u32, iters: u32 },
+    /// See [`lookup_translation`].
+    Lookup { table: PerfectMap<u32> },
+    /// See [`ascii_filter`].
+    AsciiFilter,
+    /// Any other conversion, as a function. These can't be serialized.
+    #[serde(skip)]
+    Custom(Arc<dyn Fn(u32) -> Option<u32> + Send + Sync>),
+}
+
+impl Translator {
+    /// Wrap a function as a translator.
+    pub fn custom<F: Fn(u32) -> Option<u32> + Send + Sync + 'static>(f: F) -> Translator {
+        Translator::Custom(Arc::new(f))
+    }
+
+    /// Translate a codepoint, or return `None` if the translator doesn't handle it.
+    pub fn apply(&self, ord: u32) -> Option<u32> {
+        match self {
+            Translator::Range { source, offset, size } => {
+                // compare distances from the start, so a range that ends at u32::MAX can't overflow
+                let is_in_rt: bool = ord >= *source && ord - source < *size;
+                if is_in_rt { Some(ord.wrapping_sub(*offset)) } else { None }
+            }
+            Translator::MultiRange { source, target, size, slice, iters } => {
+                if ord < *source || *slice == 0 { return None; }
+                let is_in_mrt: bool = (ord - source) / slice < *iters;
+                if !is_in_mrt { return None; }
+                let ord_ir: u32 = (ord - source) % slice;
+                let is_in_rt: bool = ord_ir < *size;
+                if is_in_rt { ord_ir.checked_add(*target) } else { None }
+            }
+            Translator::Lookup { table } => table.get(ord).copied(),
+            Translator::AsciiFilter => if ord < ASCII_UB { Some(ord) } else { None },
+            Translator::Custom(f) => f(ord),
+        }
+    }
+
+    /// The kind of translator, as it is written in a config's `type` field ("custom" for
+    /// functions).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Translator::Range { .. } => "range",
+            Translator::MultiRange { .. } => "multirange",
+            Translator::Lookup { .. } => "lookup",
+            Translator::AsciiFilter => "ascii_filter",
+            Translator::Custom(_) => "custom",
+        }
+    }
+}
+
+impl fmt::Debug for Translator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Translator::Range { source, offset, size } => f.debug_struct("Range")
+                .field("source", source).field("offset", offset).field("size", size).finish(),
+            Translator::MultiRange { source, target, size, slice, iters } => {
+                f.debug_struct("MultiRange").field("source", source).field("target", target)
+                    .field("size", size).field("slice", slice).field("iters", iters).finish()
+            }
+            Translator::Lookup { table } => f.debug_struct("Lookup")
+                .field("entries", &table.len()).finish(),
+            Translator::AsciiFilter => write!(f, "AsciiFilter"),
+            Translator::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// An Expander is the one-to-many counterpart of a [`Translator`]. Instead of a single codepoint,
+/// it returns the whole replacement string, so it can map a character like `½` to `1/2`. It
+/// follows the same convention as a translator: `None` means the character isn't handled.
+/// Replacements are [`InlineStr`]s, so the usual short ones are never allocated on the heap.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Expander {
+    /// See [`map_translation`].
+    Map { table: PerfectMap<InlineStr> },
+    /// A map translator with too many entries for [`Expander::Map`], with its table stored as an
+    /// FST, keyed by the UTF-8 encoding of each character (see [`map_translation`]).
+    Fst { table: Fst },
+    /// A map translator with sources longer than one character, stored as an FST keyed by their
+    /// UTF-8 encoding and matched by longest match (see [`sequence_translation`]).
+    Sequence { table: Fst },
+    /// See [`delete_translation`].
+    Delete { ranges: Vec<(u32, u32)> },
+    /// Any other expansion, as a function. These can't be serialized.
+    #[serde(skip)]
+    Custom(Arc<dyn Fn(u32) -> Option<String> + Send + Sync>),
+}
+
+impl Expander {
+    /// Wrap a function as an expander.
+    pub fn custom<F: Fn(u32) -> Option<String> + Send + Sync + 'static>(f: F) -> Expander {
+        Expander::Custom(Arc::new(f))
+    }
+
+    /// Expand a codepoint, or return `None` if the expander doesn't handle it.
+    pub fn apply(&self, ord: u32) -> Option<InlineStr> {
+        match self {
+            Expander::Map { table } => table.get(ord).cloned(),
+            Expander::Fst { table } | Expander::Sequence { table } => char::from_u32(ord)
+                .and_then(|c| table.get(c.encode_utf8(&mut [0; 4]).as_bytes()))
+                .map(InlineStr::from),
+            Expander::Delete { ranges } => {
+                let is_in_dt: bool = ranges.iter().any(|&(s, e)| ord >= s && ord <= e);
+                if is_in_dt { Some(InlineStr::new()) } else { None }
+            }
+            Expander::Custom(f) => f(ord).map(InlineStr::from),
+        }
+    }
+
+    /// The kind of expander, as it is written in a config's `type` field ("custom" for
+    /// functions).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Expander::Map { .. } | Expander::Fst { .. } | Expander::Sequence { .. } => "map",
+            Expander::Delete { .. } => "delete",
+            Expander::Custom(_) => "custom",
+        }
+    }
+}
+
+impl fmt::Debug for Expander {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expander::Map { table } => f.debug_struct("Map").field("entries", &table.len())
+                .finish(),
+            Expander::Fst { table } => f.debug_struct("Fst").field("entries", &table.len())
+                .field("states", &table.states()).finish(),
+            Expander::Sequence { table } => f.debug_struct("Sequence")
+                .field("entries", &table.len()).field("states", &table.states()).finish(),
+            Expander::Delete { ranges } => f.debug_struct("Delete").field("ranges", ranges)
+                .finish(),
+            Expander::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
 
 /// This is a naive lookup table translator. It takes two strings of characters, and if the input
 /// matches one of the characters in the table, it returns the output character at the same index.
+///
+/// Characters are paired up by position, not by byte offset, so multibyte characters are handled
+/// correctly. If one string is longer than the other, the extra characters are ignored. If a
+/// character appears more than once in `source`, the first occurrence wins.
+///
+/// ## Example
+/// We can create a lookup translator that converts some Cyrillic homoglyphs to Latin letters:
+/// ```rs
+/// let tr_cyrillic: Translator = lookup_translation("аеорсх", "aeopcx");
+/// ```
 pub fn lookup_translation(source: &str, target: &str) -> Translator {
-    todo!("Implement lookup table translator");
-    Box::new(
-        move |ord: u32| {
-            None
-        }
-    )
+    let pairs: Vec<(u32, u32)> = source.chars().zip(target.chars())
+        .map(|(s, t)| (s as u32, t as u32))
+        .collect();
+    Translator::Lookup { table: PerfectMap::new(pairs) }
+}
+
+/// How many entries a map translator needs before its table is stored as an [`Fst`]. Smaller
+/// tables don't take enough memory to be worth the slower lookups.
+pub const FST_MIN_ENTRIES: usize = 4096;
+
+/// The map translator is the one-to-many version of the lookup table. Each source character is
+/// mapped to a replacement string, which may be any length. If a character appears more than once,
+/// the first occurrence wins.
+///
+/// Tables with at least [`FST_MIN_ENTRIES`] entries (like a compiled chain's) are stored as an
+/// [`Fst`] rather than a perfect hash table, which takes a fraction of the memory, since
+/// neighbouring characters share most of their states and replacements share their common
+/// starts; looking a character up takes a step per byte of its UTF-8 encoding instead of a
+/// single probe.
+///
+/// ## Example
+/// We can create a map translator that expands some ligatures and vulgar fractions:
+/// ```rs
+/// let ex_expand: Expander = map_translation(vec![('½', "1/2".to_string()), ('ﬁ', "fi".to_string())]);
+/// ```
+pub fn map_translation(pairs: Vec<(char, String)>) -> Expander {
+    if pairs.len() >= FST_MIN_ENTRIES {
+        let pairs: Vec<(Vec<u8>, String)> = pairs.into_iter()
+            .map(|(s, t)| (s.to_string().into_bytes(), t))
+            .collect();
+        return Expander::Fst { table: Fst::new(pairs) };
+    }
+    let pairs: Vec<(u32, InlineStr)> = pairs.into_iter()
+        .map(|(s, t)| (s as u32, InlineStr::from(t)))
+        .collect();
+    Expander::Map { table: PerfectMap::new(pairs) }
+}
+
+/// The sequence translator is the map translator for sources of any length, such as
+/// transliterations of letter combinations or `:shortcode:`s. Wherever the text starts with one of
+/// the sources, the longest one is replaced (single-character sources are looked up like a map
+/// translator's). If a source appears more than once, the first occurrence wins. The table is
+/// always stored as an [`Fst`], so it stays small and quick however many entries it has.
+///
+/// A sequence only matches if no rule before this one in the chain handles its first character.
+///
+/// ## Example
+/// We can create a sequence translator that transliterates some Cyrillic letter combinations:
+/// ```rs
+/// let ex_seq: Expander = sequence_translation(vec![("ый".to_string(), "iy".to_string()),
+///                                                  ("й".to_string(), "y".to_string())]);
+/// ```
+pub fn sequence_translation(pairs: Vec<(String, String)>) -> Expander {
+    let pairs: Vec<(Vec<u8>, String)> = pairs.into_iter().map(|(s, t)| (s.into_bytes(), t))
+        .collect();
+    Expander::Sequence { table: Fst::new(pairs) }
+}
+
+/// The delete translator removes characters entirely, by replacing them with an empty string. This
+/// is useful for stripping invisible characters such as zero-width spaces and joiners. It takes a
+/// list of inclusive codepoint ranges; single characters are just ranges where both ends are the
+/// same.
+///
+/// ## Example
+/// We can create a delete translator that strips zero-width characters and byte order marks:
+/// ```rs
+/// let ex_strip: Expander = delete_translation(vec![('\u{200B}', '\u{200D}'), ('\u{FEFF}', '\u{FEFF}')]);
+/// ```
+pub fn delete_translation(ranges: Vec<(char, char)>) -> Expander {
+    Expander::Delete { ranges: ranges.into_iter().map(|(s, e)| (s as u32, e as u32)).collect() }
 }
 
 /// Although the lookup table works fine for arbitrary groups of characters, it still has to go
@@ -51,13 +280,8 @@ pub fn lookup_translation(source: &str, target: &str) -> Translator {
 pub fn range_translation(source: char, target: char, size: u32) -> Translator {
     let s: u32 = source as u32;
     let t: u32 = target as u32;
-    let offset: u32 = s - t;
-    Box::new(
-        move |ord: u32| {
-            let is_in_rt: bool = ord >= s && ord <= s + size - 1;
-            if (is_in_rt) { Some(ord - offset) } else { None }
-        }
-    )
+    // the target range may come after the source range, so let the offset wrap around
+    Translator::Range { source: s, offset: s.wrapping_sub(t), size }
 }
 
 /// The multi-range translator is primarily useful for cases such as the Mathematical Alphanumeric
@@ -82,44 +306,30 @@ pub fn range_translation(source: char, target: char, size: u32) -> Translator {
 /// let tr_lower: Translator = multirange_translation('\u{1D41A}', 'a', 26, 52, 3),
 /// ```
 pub fn multirange_translation(source: char, target: char, size: u32, slice: u32, iters: u32) -> Translator {
-    let s: u32 = source as u32;
-    let t: u32 = target as u32;
-    Box::new(
-        move |ord: u32| {
-            let is_in_mrt: bool = ord >= s && ord <= s + (slice * iters) - 1;
-            if (is_in_mrt) {
-                let ord_ir: u32 = (ord - s) % slice;
-                let is_in_rt: bool = ord_ir <= s + size - 1;
-                if (is_in_rt) { Some(ord_ir + t) } else { None }
-            }
-            else { None }
-        }
-    )
+    Translator::MultiRange { source: source as u32, target: target as u32, size, slice, iters }
 }
 
+/// The upper bound (exclusive) of the characters [`ascii_filter`] passes through.
+const ASCII_UB: u32 = 128; // should I adjust this to allow for ASCII extended chars?
+
 /// The ASCII filter should be placed at the front of a translator list. If you do not intend to
 /// match against any ASCII characters, this filter will return if a character is ASCII. This is an
 /// optimization, as otherwise it would have to run through all of the translators before returning.
 pub fn ascii_filter() -> Translator {
-    let ascii_ub: u32 = 128; // should I adjust this to allow for ASCII extended chars?
-    Box::new(
-        move |ord: u32| {
-            if (ord < ascii_ub) { Some(ord) } else { None }
-        }
-    )
+    Translator::AsciiFilter
 }
 
 /// Run a chain of translators on a single character.
 pub fn translate(source: char, translator: &[Translator]) -> char {
     let ord: u32 = source as u32;
-    char::from_u32(translator.iter().flat_map(|f| (*f)(ord)).next().unwrap_or(ord))
+    char::from_u32(translator.iter().flat_map(|f| f.apply(ord)).next().unwrap_or(ord))
         .unwrap_or(source)
 }
 
 /// Run a chain of translators on a single character.
-pub fn translate_vec(source: char, translator: &Vec<Translator>) -> char {
+pub fn translate_vec(source: char, translator: &[Translator]) -> char {
     let ord: u32 = source as u32;
-    char::from_u32(translator.iter().flat_map(|f| (*f)(ord)).next().unwrap_or(ord))
+    char::from_u32(translator.iter().flat_map(|f| f.apply(ord)).next().unwrap_or(ord))
         .unwrap_or(source)
 }
 
@@ -127,8 +337,49 @@ pub fn translate_vec(source: char, translator: &Vec<Translator>) -> char {
 /// should use `translate()` with an array of translators.
 pub fn translate_one(source: char, translator: &Translator) -> char {
     let ord: u32 = source as u32;
-    match translator(ord) {
+    match translator.apply(ord) {
         Some(res0) => { char::from_u32(res0).unwrap_or(source) }
         None => { source }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Chain;
+
+    #[test]
+    fn large_maps_are_stored_as_an_fst() {
+        let pairs: Vec<(char, String)> = (0x4E00..0x4E00 + FST_MIN_ENTRIES as u32)
+            .filter_map(char::from_u32)
+            .map(|c| (c, format!("<{:X}>", c as u32)))
+            .collect();
+        let expander: Expander = map_translation(pairs.clone());
+        assert!(matches!(expander, Expander::Fst { .. }), "{:?}", expander);
+        assert_eq!(expander.kind(), "map");
+        assert_eq!(expander.apply(0x4E00).as_deref(), Some("<4E00>"));
+        assert_eq!(expander.apply(0x41), None);
+
+        let chain: Chain = Chain::from_table(pairs);
+        assert_eq!(chain.translate_str("a\u{4E01}b"), "a<4E01>b");
+        assert!(chain.leaves_unchanged("ab"));
+        assert!(!chain.leaves_unchanged("\u{4E01}"));
+    }
+
+    #[test]
+    fn ranges_at_the_top_of_u32_do_not_overflow() {
+        let range: Translator = Translator::Range { source: u32::MAX - 1, offset: 0, size: 16 };
+        assert_eq!(range.apply(u32::MAX), Some(u32::MAX));
+        assert_eq!(range.apply(u32::MAX - 2), None);
+
+        let multirange: Translator = Translator::MultiRange { source: u32::MAX - 9, target: 0x41,
+                                                              size: 2, slice: 4, iters: 1000 };
+        assert_eq!(multirange.apply(u32::MAX - 9), Some(0x41));
+        assert_eq!(multirange.apply(u32::MAX - 4), Some(0x42));
+        assert_eq!(multirange.apply(u32::MAX - 7), None);
+
+        let empty: Translator = Translator::MultiRange { source: 0x100, target: 0x41, size: 2,
+                                                         slice: 0, iters: 3 };
+        assert_eq!(empty.apply(0x100), None);
+    }
+}
@@ -0,0 +1,37 @@
+/// Fast UTF-8 validation for text that arrives as bytes.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+
+pub use simdutf8::compat::Utf8Error;
+
+/// Check that some bytes are valid UTF-8, using SIMD instructions where the CPU has them. On
+/// failure, the error says where the valid prefix ends and how long the invalid sequence is, the
+/// same as [`std::str::from_utf8`].
+pub fn from_utf8(bytes: &[u8]) -> Result<&str, Utf8Error> {
+    simdutf8::compat::from_utf8(bytes)
+}
+
+/// Decode bytes as UTF-8, replacing invalid sequences with U+FFFD REPLACEMENT CHARACTER, the same
+/// way `String::from_utf8_lossy` does. Valid input (the usual case) is checked with
+/// [`simdutf8::basic::from_utf8`], which is much faster than std's scalar check on large inputs
+/// and borrows rather than copies; only invalid input goes through the slower std path.
+pub fn from_utf8_lossy(bytes: &[u8]) -> Cow<'_, str> {
+    match simdutf8::basic::from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => String::from_utf8_lossy(bytes),
+    }
+}
@@ -0,0 +1,85 @@
+/// Reading the files inside zip and tar archives.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Entry};
+use zip::ZipArchive;
+use crate::compress;
+use crate::walk::{self, Filter, Walked};
+
+/// The kinds of archive that can be looked inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Zip,
+    /// A tar file, possibly compressed with gzip or zstd.
+    Tar,
+}
+
+/// What kind of archive a file is, going by its name.
+fn kind(path: &Path) -> Option<Kind> {
+    let name: String = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".zip") { return Some(Kind::Zip); }
+    [".tar", ".tar.gz", ".tgz", ".tar.zst", ".tzst"].iter()
+        .any(|ext| name.ends_with(ext))
+        .then_some(Kind::Tar)
+}
+
+/// Whether a file is an archive that can be looked inside, going by its name.
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// Call `each` with every regular file in an archive that passes the filter (see
+/// [`Filter::matches`] and [`Filter::admit_entry`]), in the order they are stored. Each file is
+/// named like `archive.zip!path/inside.txt`. Files that are skipped are counted in `walked`.
+pub fn entries<F>(path: &Path, filter: &Filter, walked: &mut Walked, mut each: F)
+    -> io::Result<()>
+    where F: FnMut(&Path, &mut dyn Read) -> io::Result<()> {
+    let mut visit = |name: &str, size: u64, reader: &mut dyn Read| -> io::Result<()> {
+        if !filter.matches(Path::new(name)) { return Ok(()); }
+        let full: PathBuf = PathBuf::from(format!("{}!{}", path.display(), name));
+        let mut start: Vec<u8> = Vec::new();
+        (&mut *reader).take(walk::SNIFF_SIZE).read_to_end(&mut start)?;
+        if !filter.admit_entry(&full, size, &start, walked) { return Ok(()); }
+        each(&full, &mut Cursor::new(start).chain(reader))
+    };
+    match kind(path) {
+        Some(Kind::Zip) => {
+            let mut archive: ZipArchive<File> = ZipArchive::new(File::open(path)?)
+                .map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i).map_err(io::Error::other)?;
+                if !file.is_file() { continue; }
+                let name: String = file.name().to_string();
+                visit(&name, file.size(), &mut file)?;
+            }
+        }
+        Some(Kind::Tar) => {
+            let mut archive: Archive<Box<dyn Read>> =
+                Archive::new(compress::decompress(File::open(path)?)?);
+            for entry in archive.entries()? {
+                let mut entry: Entry<Box<dyn Read>> = entry?;
+                if !entry.header().entry_type().is_file() { continue; }
+                let name: String = entry.path()?.to_string_lossy().into_owned();
+                visit(&name, entry.size(), &mut entry)?;
+            }
+        }
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive")),
+    }
+    Ok(())
+}
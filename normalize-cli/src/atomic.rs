@@ -0,0 +1,164 @@
+/// Replacing files atomically, so that they are never left half-written.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::{self, File, FileTimes, Metadata, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Replace the contents of the file at `path` with whatever `write` writes, if it returns `true`
+/// (if it returns `false`, the file is left alone). The new contents are written to a temporary
+/// file in the same directory as they are produced, which is then renamed over the original, so
+/// that a crash or a full disk leaves either the old file or the new one, never a truncated one,
+/// and the file never has to be held in memory. The new file gets the original's permissions, and
+/// its access and modification times too if `preserve_times` is set. If `path` is a symbolic link,
+/// the file it points to is replaced, and the link is kept.
+pub fn replace_with<F>(path: &Path, preserve_times: bool, write: F) -> io::Result<bool>
+    where F: FnOnce(&mut BufWriter<File>) -> io::Result<bool> {
+    let target: PathBuf = fs::canonicalize(path)?;
+    let metadata: Metadata = fs::metadata(&target)?;
+    let temp: PathBuf = temp_path(&target);
+    let result: io::Result<bool> = write_temp(&temp, &metadata, preserve_times, write)
+        .and_then(|changed| if changed { fs::rename(&temp, &target).map(|()| true) }
+                            else { Ok(false) });
+    if !matches!(result, Ok(true)) {
+        // the original is untouched; just don't leave the temporary file behind
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Write the temporary file, copying the original's metadata, and make sure it has reached the
+/// disk before it is renamed over the original.
+fn write_temp<F>(temp: &Path, metadata: &Metadata, preserve_times: bool, write: F)
+                 -> io::Result<bool>
+    where F: FnOnce(&mut BufWriter<File>) -> io::Result<bool> {
+    let file: File = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    let mut writer: BufWriter<File> = BufWriter::new(file);
+    if !write(&mut writer)? { return Ok(false); }
+    let file: File = writer.into_inner().map_err(|e| e.into_error())?;
+    file.set_permissions(metadata.permissions())?;
+    if preserve_times {
+        file.set_times(FileTimes::new().set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?))?;
+    }
+    file.sync_all()?;
+    Ok(true)
+}
+
+/// A name for the temporary file next to `target`: hidden, and unique to this process.
+fn temp_path(target: &Path) -> PathBuf {
+    let name: String = target.file_name().map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.utf-normalize-{}.tmp", name, process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::{Duration, SystemTime};
+
+    /// Create a fresh directory holding one file, returning the file's path.
+    fn file(test: &str, contents: &str) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir()
+            .join(format!("utf-normalize-atomic-{}-{}", test, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join("f.txt");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// The names of the files in a directory.
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn permissions_are_preserved() {
+        let path: PathBuf = file("permissions", "old\n");
+        let writable: fs::Permissions = fs::metadata(&path).unwrap().permissions();
+        let mut permissions: fs::Permissions = writable.clone();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions.clone()).unwrap();
+
+        let changed: bool = replace_with(&path, false, |out| {
+            out.write_all(b"new\n")?;
+            Ok(true)
+        }).unwrap();
+        assert!(changed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::metadata(&path).unwrap().permissions(), permissions);
+
+        fs::set_permissions(&path, writable).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn times_are_preserved_when_asked() {
+        let path: PathBuf = file("times", "old\n");
+        let then: SystemTime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(&path).unwrap()
+            .set_times(FileTimes::new().set_accessed(then).set_modified(then)).unwrap();
+
+        replace_with(&path, true, |out| out.write_all(b"new\n").map(|()| true)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), then);
+        replace_with(&path, false, |out| out.write_all(b"newer\n").map(|()| true)).unwrap();
+        assert_ne!(fs::metadata(&path).unwrap().modified().unwrap(), then);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "newer\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_kept() {
+        let path: PathBuf = file("symlink", "old\n");
+        let link: PathBuf = path.with_file_name("link.txt");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        replace_with(&link, false, |out| out.write_all(b"new\n").map(|()| true)).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unchanged_or_failed_writes_leave_the_original() {
+        let path: PathBuf = file("failed", "old\n");
+        let dir: &Path = path.parent().unwrap();
+
+        let changed: bool = replace_with(&path, false, |out| {
+            out.write_all(b"partial")?;
+            Ok(false)
+        }).unwrap();
+        assert!(!changed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert_eq!(listing(dir), ["f.txt"]);
+
+        let err: io::Error = replace_with(&path, false, |out| {
+            out.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        }).unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert_eq!(listing(dir), ["f.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+/// The bench subcommand: timing the chain on some input, in total and by translator.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::Args;
+use libnormalize::bench::{self, Group, Throughput};
+use libnormalize::chain::Chain;
+use tracing::{error, info};
+use crate::commands::read_text;
+use crate::load::{self, ChainArgs};
+use crate::walk::{self, Filter, FilterArgs, Walked};
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// A file to translate, or a directory to translate every file in, recursively. Can be given
+    /// more than once. Every file is read into memory before anything is timed.
+    #[arg(short, long, required = true, value_name = "PATH")]
+    pub input: Vec<PathBuf>,
+
+    /// How many times to translate the whole input. The fastest round is reported.
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub iterations: usize,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+/// Measure how fast the chain translates the input (see `bench::throughput`), then how the time
+/// splits between its translators (see `bench::breakdown`), and print both. Exits with 2 if any
+/// input couldn't be read.
+pub fn run(args: BenchArgs, opts: &ChainArgs) -> ExitCode {
+    let texts: Vec<String> = match read_corpus(&args.input, &args.filter) {
+        Some(texts) => texts,
+        None => return ExitCode::from(2),
+    };
+
+    let chain: Chain = load::chain(opts, &PathBuf::from("."));
+    let throughput: Throughput = bench::throughput(&chain, &texts, args.iterations);
+    println!("input:      {} files, {} bytes, {} characters", texts.len(), throughput.bytes,
+             throughput.chars);
+    println!("time:       {} (fastest of {})", millis(throughput.elapsed), args.iterations.max(1));
+    println!("throughput: {:.2} MB/s, {:.0} chars/s", throughput.mb_per_sec(),
+             throughput.chars_per_sec());
+    println!();
+    print_breakdown(&bench::breakdown(&chain, &texts));
+    ExitCode::SUCCESS
+}
+
+/// Read every file given (walking directories) into memory, for timing or profiling the chain on.
+/// Returns `None` after logging the problem if any of them couldn't be read.
+pub fn read_corpus(inputs: &[PathBuf], filter: &FilterArgs) -> Option<Vec<String>> {
+    let filter: Filter = walk::filter(filter)?;
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in inputs {
+        if path.is_dir() {
+            let walked: Walked = walk::walk(path, &filter);
+            if walked.errors > 0 { return None; }
+            files.extend(walked.files);
+        } else if filter.matches(path) {
+            files.push(path.clone());
+        }
+    }
+    let mut texts: Vec<String> = Vec::new();
+    for path in &files {
+        info!("[bench] {}", path.display());
+        match fs::File::open(path).and_then(read_text) {
+            Ok(text) => texts.push(text),
+            Err(e) => {
+                error!("[io] {}: {}", path.display(), e);
+                return None;
+            }
+        }
+    }
+    Some(texts)
+}
+
+/// Print a table of how many characters reached and were handled by each group of rules, how long
+/// it took and its share of the time spent in rules.
+fn print_breakdown(groups: &[Group]) {
+    let total: Duration = groups.iter().map(|group| group.elapsed).sum();
+    let width: usize = groups.iter().map(|group| group.name.len()).chain(["translator".len()])
+        .max().unwrap_or(0);
+    println!("{:<width$}  {:>12}  {:>12}  {:>12}  {:>6}", "translator", "reached", "handled",
+             "time", "share", width = width);
+    for group in groups {
+        let share: f64 = if total.is_zero() { 0.0 }
+                         else { group.elapsed.as_secs_f64() / total.as_secs_f64() * 100.0 };
+        println!("{:<width$}  {:>12}  {:>12}  {:>12}  {:>5.1}%", group.name, group.reached,
+                 group.handled, millis(group.elapsed), share, width = width);
+    }
+}
+
+/// A duration in milliseconds, to three decimal places.
+fn millis(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1e3)
+}
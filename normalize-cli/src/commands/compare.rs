@@ -0,0 +1,86 @@
+/// The compare subcommand: checking whether two strings are confusable.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::Args;
+use libnormalize::chain::Chain;
+use libnormalize::compare::{self, Comparison, Edit, Verdict};
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// The first string, such as a new username or domain.
+    pub a: String,
+
+    /// The second string, such as an existing one to check it against.
+    pub b: String,
+
+    /// Compare case-insensitively, as for domain names.
+    #[arg(short, long)]
+    pub ignore_case: bool,
+
+    /// Also count strings as too close if their skeletons are at least this similar (from 0 to
+    /// 1), even though they differ, to catch look-alikes the chain doesn't know of like "1" and
+    /// "l".
+    #[arg(long, value_name = "SIMILARITY", value_parser = parse_threshold)]
+    pub threshold: Option<f64>,
+
+    /// Don't print anything; just exit with the verdict.
+    #[arg(long)]
+    pub silent: bool,
+}
+
+/// Compare two strings by their skeletons (see `compare::compare`), printing the skeletons, each
+/// character that differs, how similar they are and the verdict. Exits with 1 if they are
+/// identical, confusable or similar, and 0 if they are different.
+pub fn run(args: CompareArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &PathBuf::from("."));
+    let comparison: Comparison = compare::compare(&chain, &args.a, &args.b, args.ignore_case,
+                                                  args.threshold);
+    if !args.silent { print(&comparison); }
+    if comparison.verdict == Verdict::Different { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Print a comparison.
+fn print(comparison: &Comparison) {
+    println!("skeletons:  {:?} {:?}", comparison.skeletons.0, comparison.skeletons.1);
+    for (i, edit) in comparison.edits.iter().enumerate() {
+        let position: usize = i + 1;
+        match *edit {
+            Edit::Same(_) => {}
+            Edit::Substitute(a, b) => println!("{:>10}: {} vs {}", position, describe(a),
+                                               describe(b)),
+            Edit::Insert(b) => println!("{:>10}: (nothing) vs {}", position, describe(b)),
+            Edit::Delete(a) => println!("{:>10}: {} vs (nothing)", position, describe(a)),
+        }
+    }
+    println!("similarity: {:.0}%", comparison.similarity * 100.0);
+    println!("verdict:    {}", comparison.verdict);
+}
+
+/// A character with its codepoint, like "'а' (U+0430)".
+fn describe(c: char) -> String {
+    format!("'{}' (U+{:04X})", c.escape_debug(), c as u32)
+}
+
+/// Parse a similarity threshold between 0 and 1.
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(threshold),
+        _ => Err(format!("invalid similarity \"{}\" (expected a number from 0 to 1)", s)),
+    }
+}
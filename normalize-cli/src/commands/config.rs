@@ -0,0 +1,201 @@
+/// The config subcommand, which inspects and creates configuration.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use clap::Subcommand;
+use clio::Input;
+use libnormalize::bench;
+use libnormalize::chain::{Chain, TestFailure};
+use libnormalize::config::{self, Config, ConfigError, Format, Layer};
+use libnormalize::learn::{learn, Learned};
+use libnormalize::lint::{self, Overlap};
+use tracing::{error, info, warn};
+use crate::commands::bench::read_corpus;
+use crate::load::{self, exit_on_error, ChainArgs};
+use crate::walk::FilterArgs;
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Show which config files apply, in order of precedence, and the merged result.
+    Resolve,
+    /// Check a config file: that it parses and builds, that no sections shadow each other, and
+    /// that its test vectors pass. Exits with 1 if anything fails.
+    Validate {
+        /// The config file to check.
+        path: PathBuf,
+
+        /// Also fail if there are any warnings, such as overlapping sections.
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Write a commented starter config.
+    Init {
+        /// Where to write the config ("-" for stdout).
+        #[arg(default_value=".utf-normalize.toml")]
+        path: PathBuf,
+
+        /// Overwrite the file if it already exists.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Infer a lookup translator from example pairs, and print it as a config section.
+    Learn {
+        /// Example pairs, one per line, as the original text and the normalized text separated by
+        /// a tab. Defaults to stdin.
+        #[arg(value_parser, default_value="-")]
+        examples: Input,
+
+        /// Name of the generated section.
+        #[arg(long, default_value="learned")]
+        name: String,
+    },
+    /// Print the merged config with its sections reordered so that the ones that handle the most
+    /// characters of some sample text come first, without changing what the chain does.
+    Reorder {
+        /// A file of sample text, or a directory to read every file in, recursively. Can be given
+        /// more than once.
+        #[arg(short, long, required = true, value_name = "PATH")]
+        input: Vec<PathBuf>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+pub fn run(command: ConfigCommand, opts: &ChainArgs) -> ExitCode {
+    match command {
+        ConfigCommand::Resolve => {
+            let found: Vec<(&str, PathBuf)> = load::configs(opts, Path::new("."));
+            print_resolved(&load::load_layers(opts, found), opts.profile.as_deref());
+        }
+        ConfigCommand::Validate { path, strict } => return validate(&path, strict),
+        ConfigCommand::Init { path, force } => write_template(&path, force),
+        ConfigCommand::Learn { examples, name } => print_learned(examples, &name),
+        ConfigCommand::Reorder { input, filter } => return print_reordered(&input, &filter, opts),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Load and build a single config file, then lint it and run its test vectors, reporting every
+/// problem found.
+fn validate(path: &Path, strict: bool) -> ExitCode {
+    let built: Result<Chain, ConfigError> = config::load(path, Format::from_path(path))
+        .and_then(|cfg| config::build(&cfg));
+    let chain: Chain = match built {
+        Ok(chain) => chain,
+        Err(e) => {
+            error!("[config] {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let overlaps: Vec<Overlap> = lint::overlaps(&chain);
+    overlaps.iter().for_each(|overlap| warn!("[lint] {}", overlap));
+    let failures: Vec<TestFailure> = chain.self_test().err().unwrap_or_default();
+    failures.iter().for_each(|failure| error!("[test] {}", failure));
+
+    let summary: String = format!("[summary] {}: {} tests, {} failed, {} warnings", path.display(),
+                                  chain.tests.len(), failures.len(), overlaps.len());
+    if failures.is_empty() && overlaps.is_empty() { info!("{}", summary); }
+    else { warn!("{}", summary); }
+    if !failures.is_empty() || (strict && !overlaps.is_empty()) { ExitCode::FAILURE }
+    else { ExitCode::SUCCESS }
+}
+
+/// Print the config layers that apply, and the config they merge into.
+fn print_resolved(layers: &[Layer], profile: Option<&str>) {
+    println!("# Layers, from lowest to highest precedence:");
+    for layer in layers {
+        match &layer.path {
+            Some(path) => println!("#   {}: {}", layer.name, path.display()),
+            None => println!("#   {} (built-in)", layer.name),
+        }
+    }
+    if let Some(profile) = profile { println!("# The \"{}\" profile is applied on top.", profile); }
+    println!("# UTF_NORMALIZE_* environment variables are applied last.");
+    println!();
+
+    let cfg: Config = load::resolve_config(layers, profile);
+    print!("{}", exit_on_error(config::to_string(&cfg, Format::Toml)));
+}
+
+/// Profile the chain on the sample text (see `bench::hot_order`), then print the merged config
+/// with every section's priority set so that the chain is built in the new order. Sections that
+/// handled nothing come last. Exits with 2 if any sample couldn't be read.
+fn print_reordered(inputs: &[PathBuf], filter: &FilterArgs, opts: &ChainArgs) -> ExitCode {
+    let texts: Vec<String> = match read_corpus(inputs, filter) {
+        Some(texts) => texts,
+        None => return ExitCode::from(2),
+    };
+    let mut cfg: Config = load::config(opts, Path::new("."));
+    let chain: Chain = exit_on_error(config::build(&cfg));
+    let order: Vec<(String, usize)> = bench::hot_order(&chain, &texts);
+
+    let names: Vec<String> = cfg.entries().into_iter().map(|(name, _)| name).collect();
+    let sections = cfg.translator.iter_mut().chain(cfg.sections.values_mut());
+    for (name, sect) in names.iter().zip(sections) {
+        sect.priority = order.iter().position(|(group, _)| group == name)
+            .map_or(0, |i| (order.len() - i) as i32);
+    }
+
+    println!("# Sections, by how many characters of the sample they handled:");
+    for (name, hits) in &order {
+        println!("#   {}: {}", name, hits);
+    }
+    println!();
+    print!("{}", exit_on_error(config::to_string(&cfg, Format::Toml)));
+    ExitCode::SUCCESS
+}
+
+/// Write the starter config to a file (or stdout), refusing to overwrite an existing file unless
+/// forced to.
+fn write_template(path: &Path, force: bool) {
+    if path == Path::new("-") {
+        print!("{}", config::TEMPLATE);
+        return;
+    }
+    if path.exists() && !force {
+        error!("[config] {} already exists (use --force to overwrite it)", path.display());
+        process::exit(1);
+    }
+    if let Err(e) = std::fs::write(path, config::TEMPLATE) {
+        error!("[config] Could not write {}: {}", path.display(), e);
+        process::exit(1);
+    }
+    info!("[config] Wrote a starter config to {}", path.display());
+}
+
+/// Learn a lookup table from tab-separated example pairs, and print it as a config section.
+fn print_learned(mut examples: Input, name: &str) {
+    let mut data: String = String::new();
+    examples.read_to_string(&mut data).unwrap();
+    let pairs: Vec<(&str, &str)> = data.lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let pair: Option<(&str, &str)> = line.split_once('\t');
+            if pair.is_none() { warn!("[learn] Skipping line without a tab: {:?}", line); }
+            pair
+        })
+        .collect();
+
+    let learned: Learned = learn(&pairs);
+    learned.conflicts.iter().for_each(|conflict| warn!("[learn] {}", conflict));
+
+    let cfg: Config = Config { translator: vec![learned.to_section(name)], ..Config::default() };
+    print!("{}", exit_on_error(config::to_string(&cfg, Format::Toml)));
+}
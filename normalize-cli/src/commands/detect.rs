@@ -0,0 +1,90 @@
+/// The detect subcommand, which reports suspicious characters in the input.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, Read};
+use std::process::ExitCode;
+use clap::Args;
+use clio::Input;
+use libnormalize::chain::{Chain, ExitPolicy};
+use libnormalize::detect::{self, Class, Finding, Severity};
+use libnormalize::source::{self, Language};
+use libnormalize::stream::{self, Position, Tracker};
+use tracing::error;
+use crate::commands::{input_name, read_text};
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct DetectArgs {
+    /// Input data. Defaults to stdin.
+    #[arg(value_parser, default_value="-")]
+    pub input: Input,
+
+    /// Treat the input as source code in LANGUAGE (rust, python, js, c, cpp, java or go), and
+    /// only report characters in its comments and string literals.
+    #[arg(long, value_name = "LANGUAGE")]
+    pub lang: Option<Language>,
+}
+
+/// Report every suspicious character in the input. Exits with 1 if any finding has severity
+/// "error" (or as the chain's exit code policy says), and 2 if the input couldn't be read.
+pub fn run(mut args: DetectArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &load::search_from(&args.input));
+    let name: String = input_name(&args.input);
+    match report(&chain, &name, &mut args.input, &Class::ALL, args.lang) {
+        Ok(worst) => exit_code(worst, chain.exit_code),
+        Err(e) => {
+            error!("[io] {}: {}", name, e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Print the findings of the given classes in some input, as "name:line:column: finding",
+/// returning the worst severity among them. The input is read a chunk at a time (see
+/// `stream::detect_reader`), except that with a `language` it is read whole, and only findings in
+/// comments and string literals count.
+pub fn report<R: Read>(chain: &Chain, name: &str, input: R, classes: &[Class],
+                       language: Option<Language>) -> io::Result<Severity> {
+    let mut worst: Severity = Severity::Off;
+    let mut found = |position: Position, finding: Finding| {
+        if !classes.contains(&finding.class) { return; }
+        println!("{}:{}:{}: {}", name, position.line, position.column, finding);
+        worst = worst.max(finding.severity);
+    };
+    let Some(language) = language else {
+        stream::detect_reader(chain, input, found)?;
+        return Ok(worst);
+    };
+
+    let text: String = read_text(input)?;
+    let literals: Vec<(usize, usize)> = source::literals(language, &text);
+    let findings: Vec<Finding> = detect::detect(chain, &text).into_iter()
+        .filter(|finding| {
+            literals.iter().any(|&(start, end)| (start..end).contains(&finding.offset))
+        })
+        .collect();
+    let positions: Vec<Position> = Tracker::new()
+        .feed(&text, findings.iter().map(|finding| finding.offset));
+    positions.into_iter().zip(findings).for_each(|(position, finding)| found(position, finding));
+    Ok(worst)
+}
+
+/// The exit code for the worst severity found, under the chain's exit code policy: by default, 1
+/// for errors, and 0 otherwise.
+pub fn exit_code(worst: Severity, policy: ExitPolicy) -> ExitCode {
+    if policy.fails(false, worst, worst >= Severity::Error) { ExitCode::FAILURE }
+    else { ExitCode::SUCCESS }
+}
@@ -0,0 +1,82 @@
+/// The explain subcommand, which describes single characters.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::Args;
+use libnormalize::chain::{Change, Chain};
+use libnormalize::detect;
+use libnormalize::unicode;
+use tracing::error;
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct ExplainArgs {
+    /// Characters to explain, either as they are (every character of each argument is explained)
+    /// or as codepoints, like U+0430.
+    #[arg(required = true, value_name = "CHAR")]
+    pub chars: Vec<String>,
+}
+
+/// Describe each character: its codepoint, name, block and script, its detection class, and what
+/// the chain turns it into and why. Exits with 2 if an argument isn't a valid codepoint.
+pub fn run(args: ExplainArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &PathBuf::from("."));
+    let mut chars: Vec<char> = Vec::new();
+    for arg in &args.chars {
+        match parse(arg) {
+            Ok(parsed) => chars.extend(parsed),
+            Err(e) => {
+                error!("[explain] {}", e);
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    for (i, &c) in chars.iter().enumerate() {
+        if i > 0 { println!(); }
+        println!("U+{:04X} {:?}", c as u32, c);
+        println!("  name:        {}", unicode::name(c).as_deref().unwrap_or("-"));
+        println!("  block:       {}", unicode::block(c).unwrap_or("No Block"));
+        println!("  script:      {}", unicode::script(c));
+        println!("  class:       {}",
+                 detect::classify(&chain, c).map(|class| class.name()).unwrap_or("-"));
+        println!("  translation: {}", translation(&chain, c));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Parse an argument: a codepoint like "U+0430", or otherwise the characters themselves.
+fn parse(arg: &str) -> Result<Vec<char>, String> {
+    let Some(hex) = arg.strip_prefix("U+").or_else(|| arg.strip_prefix("u+")) else {
+        return Ok(arg.chars().collect());
+    };
+    u32::from_str_radix(hex, 16).ok()
+        .and_then(char::from_u32)
+        .map(|c| vec![c])
+        .ok_or_else(|| format!("\"{}\" is not a valid codepoint", arg))
+}
+
+/// What the chain does with a character on its own, and what decided it.
+fn translation(chain: &Chain, c: char) -> String {
+    let changes: Vec<Change> = chain.changes(c.encode_utf8(&mut [0; 4]));
+    match changes.into_iter().next() {
+        Some(change) => format!("{:?} ({})", change.replacement, chain.describe(change.handler)),
+        None if chain.is_allowed(c) => "unchanged (allowed)".to_string(),
+        None if chain.is_denied(c) => "unchanged (denied)".to_string(),
+        None => "unchanged".to_string(),
+    }
+}
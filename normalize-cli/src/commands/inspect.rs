@@ -0,0 +1,73 @@
+/// The inspect subcommand, which shows how each character of the input is handled.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::process::ExitCode;
+use clap::Args;
+use clio::Input;
+use libnormalize::chain::Chain;
+use libnormalize::detect::{self, Class};
+use libnormalize::unicode;
+use tracing::error;
+use crate::commands::{input_name, read_text};
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// Input data. Defaults to stdin.
+    #[arg(value_parser, default_value="-")]
+    pub input: Input,
+
+    /// Include ASCII characters, which are skipped by default.
+    #[arg(long)]
+    pub all: bool,
+}
+
+/// Print one line per character of the input: its byte offset, codepoint, general category,
+/// whether it is ASCII, what the chain turns it into and why, its detection class (if it has one),
+/// and its Unicode name.
+pub fn run(mut args: InspectArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &load::search_from(&args.input));
+    let text: String = match read_text(&mut args.input) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("[io] {}: {}", input_name(&args.input), e);
+            return ExitCode::from(2);
+        }
+    };
+
+    for (base, protected, segment) in chain.segments(&text) {
+        for (offset, c) in segment.char_indices() {
+            if c.is_ascii() && !args.all { continue; }
+            let mut out: String = String::new();
+            let how: &str = if protected {
+                out.push(c);
+                "protected"
+            } else if chain.lookup_into(c, &mut out) {
+                "mapped"
+            } else {
+                chain.fallback_into(c, &mut out);
+                "fallback"
+            };
+            let class: Option<Class> = detect::classify(&chain, c);
+            println!("{:>8}  U+{:04X}  {:<6}  {}  {:<5}  -> {:<12}  {:<9}  {:<13}  {}",
+                     base + offset, c as u32, format!("{:?}", c), unicode::category(c),
+                     if c.is_ascii() { "ascii" } else { "-" }, format!("{:?}", out), how,
+                     class.map(|class| class.name()).unwrap_or("-"),
+                     unicode::name(c).as_deref().unwrap_or("-"));
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,45 @@
+/// Subcommands of the command-line interface.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+pub mod normalize;
+pub mod detect;
+pub mod scan;
+pub mod inspect;
+pub mod explain;
+pub mod config;
+pub mod presets;
+pub mod table;
+pub mod obfuscate;
+pub mod compare;
+pub mod skeleton;
+pub mod bench;
+
+use std::io::Read;
+use clio::Input;
+use libnormalize::utf8;
+
+/// A name for an input in reports: its path, or `<stdin>`.
+pub fn input_name(input: &Input) -> String {
+    if input.is_std() { "<stdin>".to_string() } else { input.path().display().to_string() }
+}
+
+/// Read an entire input as text. Invalid UTF-8 is decoded as U+FFFD, so offsets in the result
+/// match the input everywhere before the first invalid byte.
+pub fn read_text<R: Read>(mut input: R) -> std::io::Result<String> {
+    let mut data: Vec<u8> = Vec::new();
+    input.read_to_end(&mut data)?;
+    Ok(utf8::from_utf8_lossy(&data).into_owned())
+}
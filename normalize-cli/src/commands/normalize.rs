@@ -0,0 +1,797 @@
+/// The normalize subcommand, which translates text into its normalized form.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use clap::{Args, ValueEnum};
+use clio::{Input, Output};
+use libnormalize::chain::{Change, Chain};
+use libnormalize::detect;
+use libnormalize::encoding::{Bom, Encoder, InputEncoding, OutputEncoding, Transcoder};
+use libnormalize::json;
+use libnormalize::markdown;
+use libnormalize::source::{self, Language};
+use libnormalize::stream::{self, Position, Tracker};
+use libnormalize::tabular;
+use libnormalize::utf8;
+use memmap2::Mmap;
+use similar::TextDiff;
+use tracing::{error, info, warn};
+use crate::archive;
+use crate::atomic;
+use crate::compress::{self, Compression, Compressor};
+use crate::commands::read_text;
+use crate::load::{ChainArgs, Chains};
+use crate::progress::Progress;
+use crate::report::{Finding, ReportFormat, Reporter};
+use crate::stats::Stats;
+use crate::walk::{self, Filter, FilterArgs, Walked};
+
+/// ANSI escape codes for highlighting.
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Args, Debug)]
+pub struct NormalizeArgs {
+    /// Files to normalize, one after another ("-" for stdin).
+    #[arg(value_name = "FILE")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directories to normalize every file in, recursively.
+    #[arg(short, long, value_name = "DIR")]
+    pub recursive: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Input data, if no FILEs or directories are given. Defaults to stdin.
+    #[arg(short, long, value_parser, default_value="-",
+          conflicts_with_all = ["inputs", "recursive"])]
+    pub input_file: Input,
+
+    /// Location to output to. Defaults to stdout. Every input is written here in turn, unless
+    /// --output-name is given.
+    #[arg(short, long, value_parser, default_value="-")]
+    pub output_file: Output,
+
+    /// Write each input to its own file instead, named by this template. `{dir}`, `{name}`,
+    /// `{stem}` and `{ext}` are replaced by the input's directory, file name, file name without
+    /// its extension, and extension (with its dot), e.g. "{dir}/{stem}.normalized{ext}".
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "output_file")]
+    pub output_name: Option<String>,
+
+    /// Normalize each file where it is, replacing its contents. Files that don't change are left
+    /// alone.
+    #[arg(long, conflicts_with_all = ["output_file", "output_name", "out_dir"])]
+    pub in_place: bool,
+
+    /// With --in-place, keep a copy of each original file, named by adding this suffix (".bak" if
+    /// none is given).
+    #[arg(long, value_name = "SUFFIX", requires = "in_place", num_args = 0..=1,
+          default_missing_value = ".bak")]
+    pub backup: Option<String>,
+
+    /// With --in-place, keep each changed file's access and modification times.
+    #[arg(long, requires = "in_place")]
+    pub preserve_times: bool,
+
+    /// Write each file under this directory instead, at the same path it has relative to the
+    /// directory being walked (or as given, for FILEs). Directories are created as needed.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output_file", "output_name"])]
+    pub out_dir: Option<PathBuf>,
+
+    /// Don't write anything; just print the name of every file that would change. Exits with 1 if
+    /// any would, and 2 if any couldn't be read.
+    #[arg(long, conflicts_with_all = ["output_file", "output_name", "in_place", "out_dir"])]
+    pub check: bool,
+
+    /// Don't write anything; print a unified diff of what would change in each file instead.
+    /// Exits like --check.
+    #[arg(long,
+          conflicts_with_all = ["output_file", "output_name", "in_place", "out_dir", "check"])]
+    pub diff: bool,
+
+    /// Don't write anything; list every character that would be replaced instead, as
+    /// "path:line:column U+XXXX 'original' -> 'replacement' (translator)". Exits like --check.
+    #[arg(long, conflicts_with_all = ["output_file", "output_name", "in_place", "out_dir", "check",
+                                      "diff"])]
+    pub report: bool,
+
+    /// The format to write --report in.
+    #[arg(long, value_name = "FORMAT", value_enum, default_value = "text", requires = "report")]
+    pub report_format: ReportFormat,
+
+    /// Don't write anything; print just the lines that have characters that would be replaced or
+    /// that the detectors flag (see the detect subcommand), prefixed with the file name and line
+    /// number. Exits like --check.
+    #[arg(long, conflicts_with_all = ["output_file", "output_name", "in_place", "out_dir", "check",
+                                      "diff", "report"])]
+    pub grep: bool,
+
+    /// Don't write anything; print how many characters would be replaced across every input,
+    /// by Unicode block and by translator, and the most common ones. Exits like --check.
+    #[arg(long, conflicts_with_all = ["output_file", "output_name", "in_place", "out_dir", "check",
+                                      "diff", "report", "grep"])]
+    pub stats: bool,
+
+    /// How many of the most common characters --stats lists.
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "stats")]
+    pub top: usize,
+
+    /// The encoding of the input. "auto" goes by the byte order mark, recognizes UTF-16 without
+    /// one by its NUL bytes, and otherwise reads UTF-8. Input in other encodings is converted to
+    /// UTF-8 before it is normalized, and written as UTF-8.
+    #[arg(long, value_name = "ENCODING", default_value_t = InputEncoding::Auto)]
+    pub encoding: InputEncoding,
+
+    /// With --encoding auto, read input that isn't valid UTF-8 as Windows-1252 (a superset of
+    /// Latin-1), rather than replacing the invalid bytes with U+FFFD.
+    #[arg(long)]
+    pub latin1_fallback: bool,
+
+    /// The encoding to write output in. "ascii" guarantees 7-bit output: any non-ASCII
+    /// characters left after translation go through the fallback policy, with "keep" treated as
+    /// "replace". With --check, a file that would need that counts as changed.
+    #[arg(long, value_name = "ENCODING", default_value_t = OutputEncoding::Utf8,
+          conflicts_with_all = ["diff", "report", "grep", "stats"])]
+    pub output_encoding: OutputEncoding,
+
+    /// What to do with a byte order mark at the start of the input: "strip" leaves it out of the
+    /// output, "keep" writes one (in the output encoding) if the input had one, and "add" always
+    /// writes one (except in ASCII). Either way, it isn't treated as part of the text.
+    #[arg(long, value_name = "POLICY", default_value_t = Bom::Keep)]
+    pub bom: Bom,
+
+    /// Compress output with gzip or zstd (or "none" to write it uncompressed). Without this,
+    /// output files are compressed if their names end in .gz or .zst, so --in-place keeps
+    /// compressed files compressed. Compressed input is always decompressed. --highlight output
+    /// is never compressed.
+    #[arg(long, value_name = "FORMAT", value_enum,
+          conflicts_with_all = ["diff", "report", "grep", "stats"])]
+    pub compress: Option<Compression>,
+
+    /// Read files by mapping them into memory rather than with read calls, which can be faster
+    /// for very large files. A file must not be changed by anything else while it is mapped.
+    /// Stdin is read as usual.
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Show progress bars for the current file and the whole run, when writing to a terminal.
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Write each line as soon as it has been read and translated, rather than in larger blocks,
+    /// for following growing files (`tail -f log | utf-normalize --line-buffered`) and
+    /// interactive use.
+    #[arg(long, conflicts_with_all = ["in_place", "check", "diff", "report", "grep", "stats"])]
+    pub line_buffered: bool,
+
+    /// Records end with a NUL byte rather than a newline, like `grep -z` and `xargs -0`. Each
+    /// record is translated as a whole, --grep prints matching records, and --check ends each
+    /// file name with a NUL.
+    #[arg(short = 'z', long)]
+    pub null: bool,
+
+    /// Translate each file on N threads at once (or one per core, if N is left out), to make use
+    /// of every core on very large files. The file is split into large blocks that end at a
+    /// newline, which are translated side by side and written out in order, so the output is the
+    /// same; output only appears a few megabytes at a time, though.
+    #[arg(short = 'j', long, value_name = "N", num_args = 0..=1, default_missing_value = "0",
+          conflicts_with_all = ["line_buffered", "null", "tabular", "json", "markdown", "lang",
+                                "diff", "report", "grep", "stats", "highlight"])]
+    pub threads: Option<usize>,
+
+    /// Highlight replaced characters: the original in red, followed by its replacement in green.
+    /// "auto" (the default if WHEN is left out) only highlights when writing to a terminal.
+    #[arg(long, value_name = "WHEN", value_enum, num_args = 0..=1, default_value = "never",
+          default_missing_value = "auto",
+          conflicts_with_all = ["output_name", "in_place", "out_dir", "check", "diff", "report",
+                                "grep", "stats"])]
+    pub highlight: When,
+
+    /// Read the input as CSV, translating only the --columns given (or every field), and leaving
+    /// the rest of each record alone. Quoted fields are understood.
+    #[arg(long, group = "tabular",
+          conflicts_with_all = ["diff", "report", "grep", "stats", "highlight", "line_buffered"])]
+    pub csv: bool,
+
+    /// Like --csv, for tab-separated values.
+    #[arg(long, group = "tabular",
+          conflicts_with_all = ["diff", "report", "grep", "stats", "highlight", "line_buffered"])]
+    pub tsv: bool,
+
+    /// With --csv or --tsv, the columns to translate, separated by commas: header names (the
+    /// header itself is left alone), or numbers starting at 1.
+    #[arg(long, value_delimiter = ',', value_name = "COLUMN", requires = "tabular")]
+    pub columns: Vec<String>,
+
+    /// Read the input as JSON (one document, or several one after another like JSON Lines) and
+    /// translate only its string values, leaving object keys, numbers, and the structure alone.
+    /// Everything but the strings that change is written back byte for byte, formatting and
+    /// all.
+    #[arg(long, conflicts_with_all = ["tabular", "diff", "report", "grep", "stats", "highlight",
+                                      "line_buffered"])]
+    pub json: bool,
+
+    /// With --json, translate only the strings under this JSON pointer (like /user/name or
+    /// /items/0), rather than every string. Can be given more than once.
+    #[arg(long = "json-pointer", value_name = "POINTER", requires = "json")]
+    pub json_pointers: Vec<String>,
+
+    /// Read the input as Markdown, translating only the prose: fenced and indented code blocks,
+    /// inline code, link URLs and reference labels, and bare URLs are left alone.
+    #[arg(long, conflicts_with_all = ["tabular", "json", "diff", "report", "grep", "stats",
+                                      "highlight", "line_buffered"])]
+    pub markdown: bool,
+
+    /// Read the input as source code in LANGUAGE (rust, python, js, c, cpp, java or go),
+    /// translating only its comments and string literals and leaving the code itself alone. "js"
+    /// covers TypeScript, and "java" covers C#. Built with the tree-sitter feature, the source is
+    /// parsed with the language's grammar rather than a simple lexer.
+    #[arg(long, value_name = "LANGUAGE",
+          conflicts_with_all = ["tabular", "json", "markdown", "diff", "report", "grep", "stats",
+                                "highlight", "line_buffered"])]
+    pub lang: Option<Language>,
+}
+
+/// How the input is split up to be translated.
+#[derive(Debug, Clone, Copy)]
+enum Structure<'a> {
+    /// In chunks that end at a newline (see `stream::translate_reader`).
+    Chunks,
+    /// In large blocks that end at a newline, translated on this many threads at once (see
+    /// `stream::translate_parallel`).
+    Parallel(usize),
+    /// A record at a time, flushing after each one (see `stream::translate_records`).
+    Records(u8),
+    /// As delimited text, translating just some columns (see `tabular::translate_table`).
+    Table(u8, &'a [String]),
+    /// As JSON, translating just the strings under some JSON pointers (see
+    /// `json::translate_json`).
+    Json(&'a [String]),
+    /// As Markdown, translating just the prose (see `markdown::translate_markdown`).
+    Markdown,
+    /// As source code, translating just the comments and strings (see
+    /// `source::translate_source`).
+    Source(Language),
+}
+
+impl<'a> Structure<'a> {
+    /// The structure the arguments ask for. `columns` and `pointers` are `args.columns` and
+    /// `args.json_pointers`, borrowed on their own so that the rest of `args` can be borrowed
+    /// mutably while this is around.
+    fn of(args: &NormalizeArgs, columns: &'a [String], pointers: &'a [String]) -> Structure<'a> {
+        if let Some(language) = args.lang { return Structure::Source(language); }
+        match () {
+            _ if args.json => Structure::Json(pointers),
+            _ if args.markdown => Structure::Markdown,
+            _ if args.csv => Structure::Table(b',', columns),
+            _ if args.tsv => Structure::Table(b'\t', columns),
+            _ if args.null => Structure::Records(b'\0'),
+            _ if args.line_buffered => Structure::Records(b'\n'),
+            _ => match args.threads {
+                Some(threads) => Structure::Parallel(threads),
+                None => Structure::Chunks,
+            },
+        }
+    }
+}
+
+/// When to do something that only makes sense on a terminal.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Normalize every input in turn. Inputs that can't be read or written are reported and skipped,
+/// and make the exit code 1 (2 with `--check`, `--diff`, `--report`, `--grep` and `--stats`, where
+/// 1 means that something would change, unless the chain's exit code policy says otherwise). When
+/// there is more than one input, a summary is logged at the end.
+pub fn run(mut args: NormalizeArgs, opts: &ChainArgs) -> ExitCode {
+    let filter: Filter = match walk::filter(&args.filter) {
+        Some(filter) => filter,
+        None => return ExitCode::from(2),
+    };
+    // files given on the command line are checked for looking binary too, unless there's just one
+    let multiple: bool = args.inputs.len() > 1 || !args.recursive.is_empty();
+    let mut given: Walked = Walked::default();
+    // each input, along with its path relative to the directory it was found in (for --out-dir)
+    let mut inputs: Vec<(PathBuf, PathBuf)> = args.inputs.iter()
+        .filter(|input| is_stdin(input)
+            || (filter.matches(input) && (!multiple || filter.admit(input, &mut given))))
+        .map(|input| (input.clone(), relative(input)))
+        .collect();
+    let mut failed: usize = given.errors;
+    let mut binary: usize = given.binary;
+    let mut large: usize = given.large;
+    for dir in &args.recursive {
+        let walked: Walked = walk::walk(dir, &filter);
+        inputs.extend(walked.files.into_iter().map(|file| {
+            let relative: PathBuf = file.strip_prefix(dir).map(Path::to_path_buf)
+                .unwrap_or_else(|_| relative(&file));
+            (file, relative)
+        }));
+        binary += walked.binary;
+        large += walked.large;
+        failed += walked.errors;
+    }
+    if args.inputs.is_empty() && args.recursive.is_empty() {
+        let input: PathBuf = if args.input_file.is_std() { PathBuf::from("-") }
+                             else { args.input_file.path().to_path_buf() };
+        inputs.push((input.clone(), relative(&input)));
+    }
+
+    // modes that only look at the inputs, and exit with 1 if anything would change
+    let dry_run: bool = args.check || args.diff || args.report || args.grep || args.stats;
+    let structure: Structure = Structure::of(&args, &args.columns, &args.json_pointers);
+    let highlight: bool = match args.highlight {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => args.output_file.is_std() && io::stdout().is_terminal(),
+    };
+
+    // -o's path, if it isn't stdout, whose extension can ask for compression
+    let out_path: Option<PathBuf> = (!args.output_file.is_std())
+        .then(|| args.output_file.path().to_path_buf());
+    let mut reporter: Reporter = Reporter::new(args.report_format);
+    let mut stats: Stats = Stats::new();
+    let mut chains: Chains = Chains::new(opts);
+    let progress: Progress = Progress::new(args.progress, inputs.iter().map(|(input, _)| input));
+    let mut changed: usize = 0;
+    // whether a file changed in a way its chain's exit code policy fails on
+    let mut fails: bool = false;
+    // files inside archives that were skipped
+    let mut skipped: Walked = Walked::default();
+    for (input, relative) in &inputs {
+        let search_from: PathBuf = if is_stdin(input) { PathBuf::from(".") } else { input.clone() };
+        let chain: Arc<Chain> = chains.get(&search_from);
+        progress.start(input);
+        let is_archive: bool = filter.archives() && archive::is_archive(input);
+        let result: io::Result<bool> = if is_archive && !dry_run {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "archives can only be read (with \
+                                                           --check, --diff, --report, --grep or \
+                                                           --stats)"))
+        } else if is_archive {
+            let mut any: bool = false;
+            archive::entries(input, &filter, &mut skipped, |name, reader| {
+                let reader: Transcoder<Box<dyn Read + '_>> = decode(Box::new(reader), &args)?;
+                any |= look(&args, &chain, name, reader, &mut reporter, &mut stats, &progress)?;
+                Ok(())
+            }).map(|()| any)
+        } else if dry_run {
+            open(input, &args, &progress).and_then(|reader| {
+                look(&args, &chain, input, reader, &mut reporter, &mut stats, &progress)
+            })
+        } else {
+            open(input, &args, &progress).and_then(|reader| {
+                let format: OutputFormat = OutputFormat::new(args.output_encoding, args.bom,
+                                                             reader.had_bom(), args.compress);
+                match (&args.out_dir, &args.output_name) {
+                    _ if args.in_place => {
+                        translate_in_place(&chain, input, reader, format.to(input), structure,
+                                           args.backup.as_deref(), args.preserve_times)
+                    }
+                    (Some(out_dir), _) => mirrored_path(out_dir, input, relative)
+                        .and_then(|path| {
+                            translate_to_file(&chain, reader, &path, format, structure)
+                        }),
+                    (None, Some(template)) => output_path(template, input)
+                        .and_then(|path| {
+                            translate_to_file(&chain, reader, &path, format, structure)
+                        }),
+                    (None, None) if highlight => {
+                        let output: BufWriter<&mut Output> = BufWriter::new(&mut args.output_file);
+                        translate_highlighted(&chain, reader, format.encoder(output, &chain)?)
+                    }
+                    (None, None) => {
+                        let format: OutputFormat = match &out_path {
+                            Some(path) => format.to(path),
+                            None => format,
+                        };
+                        translate(&chain, reader, BufWriter::new(&mut args.output_file), format,
+                                  structure)
+                    }
+                }
+            })
+        };
+        match result {
+            Ok(true) => {
+                let verb: &str = if dry_run { "would change" } else { "changed" };
+                progress.suspend(|| info!("[normalize] {}: {}", input.display(), verb));
+                changed += 1;
+                fails |= chain.exit_code.fails(true, detect::Severity::Off, dry_run);
+            }
+            Ok(false) => progress.suspend(|| info!("[normalize] {}: unchanged", input.display())),
+            // the reader on the other end of a pipe went away (e.g. `| head`); that's not an error
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+            Err(e) => {
+                progress.suspend(|| error!("[io] {}: {}", input.display(), e));
+                failed += 1;
+            }
+        }
+    }
+    progress.finish();
+    binary += skipped.binary;
+    large += skipped.large;
+    failed += skipped.errors;
+
+    let finished: io::Result<()> = if args.stats { stats.write(io::stdout().lock(), args.top) }
+                                   else { reporter.finish(io::stdout().lock()) };
+    match finished {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+        Err(e) => {
+            error!("[io] <stdout>: {}", e);
+            failed += 1;
+        }
+    }
+
+    if inputs.len() > 1 || !args.recursive.is_empty() {
+        let verb: &str = if dry_run { "would change" } else { "changed" };
+        let summary: String = format!("[summary] {} files, {} {}, {} failed", inputs.len(),
+                                      changed, verb, failed);
+        if failed > 0 { warn!("{}", summary); } else { info!("{}", summary); }
+        if binary > 0 {
+            warn!("[summary] {} binary files skipped (use --binary force to include them)", binary);
+        }
+        if large > 0 {
+            warn!("[summary] {} files over --max-filesize skipped", large);
+        }
+    }
+    match (dry_run, failed > 0) {
+        (true, true) => ExitCode::from(2),
+        (false, true) => ExitCode::FAILURE,
+        _ if fails => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn is_stdin(input: &Path) -> bool {
+    input == Path::new("-")
+}
+
+/// Look at a file (or stdin, or a file inside an archive) in one of the modes that don't write
+/// anything: `--check` (printing its name if it would change), `--diff`, `--report`, `--grep` or
+/// `--stats`. Returns whether anything would change.
+fn look<R: Read>(args: &NormalizeArgs, chain: &Chain, input: &Path, reader: Transcoder<R>,
+                 reporter: &mut Reporter, stats: &mut Stats, progress: &Progress)
+    -> io::Result<bool> {
+    let delimiter: u8 = if args.null { b'\0' } else { b'\n' };
+    match () {
+        _ if args.diff => print_diff(chain, input, reader),
+        _ if args.report => print_report(chain, input, reader, reporter),
+        _ if args.grep => print_matching_records(chain, input, reader, delimiter),
+        _ if args.stats => count_changes(chain, reader, stats),
+        _ => {
+            let format: OutputFormat = OutputFormat::new(args.output_encoding, args.bom,
+                                                         reader.had_bom(), args.compress);
+            let structure: Structure = Structure::of(args, &args.columns, &args.json_pointers);
+            let changed: bool = translate(chain, reader, io::sink(), format, structure)?;
+            if changed {
+                progress.suspend(|| print!("{}{}", input.display(), delimiter as char));
+            }
+            Ok(changed)
+        }
+    }
+}
+
+/// Open a file (or stdin) for reading, counting what is read towards the progress bars. Most modes
+/// translate inputs as they are read, writing output as it is produced, so that neither the input
+/// nor the output has to fit in memory and pipes see output as soon as it is ready.
+///
+/// With `--mmap`, files are mapped into memory and read straight from the mapping, so reading
+/// them takes no system calls, and the kernel pages them in (and drops them again) as needed.
+/// Input that isn't UTF-8 is converted to UTF-8 as it is read (see `--encoding`).
+/// A byte order mark at the start is left out, and noted (see `Transcoder::had_bom`). Gzip and
+/// zstd compressed input is decompressed first.
+fn open<'a>(input: &Path, args: &NormalizeArgs, progress: &'a Progress)
+    -> io::Result<Transcoder<Box<dyn Read + 'a>>> {
+    let reader: Box<dyn Read + 'a> = if is_stdin(input) { progress.wrap(io::stdin().lock()) }
+                                     else { open_file(input, args.mmap, progress)? };
+    decode(reader, args)
+}
+
+/// Decompress (if it is compressed) and decode (see `--encoding`) what is read from `reader`.
+fn decode<'a>(reader: Box<dyn Read + 'a>, args: &NormalizeArgs)
+    -> io::Result<Transcoder<Box<dyn Read + 'a>>> {
+    Transcoder::new(compress::decompress(reader)?, args.encoding, args.latin1_fallback)
+}
+
+/// Open a file, mapping it into memory with `mmap`.
+fn open_file<'a>(input: &Path, mmap: bool, progress: &'a Progress)
+    -> io::Result<Box<dyn Read + 'a>> {
+    let file: File = File::open(input)?;
+    if !mmap { return Ok(progress.wrap(file)); }
+    // SAFETY: the mapping is only ever read, and --mmap documents that the file must not be
+    // changed while it is mapped (which would be undefined behaviour). --in-place replaces files
+    // by renaming a new file over them, which leaves the mapped file itself untouched.
+    let map: Mmap = unsafe { Mmap::map(&file)? };
+    Ok(progress.wrap(Cursor::new(map)))
+}
+
+/// A name for an input in reports: its path, or `<stdin>`.
+fn name(input: &Path) -> String {
+    if is_stdin(input) { "<stdin>".to_string() } else { input.display().to_string() }
+}
+
+/// Translate a file (or stdin), writing every replaced character as the original in red followed
+/// by its replacement in green. Protected regions and unchanged characters are written as they
+/// are. Characters are highlighted one at a time, after `pre_normalize`; `post_normalize` is not
+/// applied, so that the output lines up with the input. A strict chain fails on the characters it
+/// rejects, as with `stream::translate_reader`.
+fn translate_highlighted<R: Read, W: Write>(chain: &Chain, reader: R, mut output: W)
+    -> io::Result<bool> {
+    let mut changed: bool = false;
+    let mut out: String = String::new();
+    let mut tracker: Tracker = Tracker::new();
+    stream::read_chunks(reader, |text| {
+        if chain.is_strict() {
+            // characters are translated one at a time below, which doesn't check for them
+            if let Err(denied) = chain.try_translate_str(text) {
+                return Err(stream::denied_error(denied, text, &mut tracker));
+            }
+            tracker.feed(text, []);
+        }
+        for (_, protected, segment) in chain.segments(text) {
+            if protected {
+                output.write_all(segment.as_bytes())?;
+                continue;
+            }
+            for c in chain.pre_normalize.apply(segment).chars() {
+                out.clear();
+                chain.translate_into(c, &mut out);
+                if out.len() == c.len_utf8() && out.starts_with(c) {
+                    write!(output, "{}", c)?;
+                } else {
+                    write!(output, "{}{}{}{}{}", RED, c, GREEN, out, RESET)?;
+                    changed = true;
+                }
+            }
+        }
+        output.flush()
+    })?;
+    Ok(changed)
+}
+
+/// Add every change that translating a file (or stdin) would make to the report.
+fn print_report<R: Read>(chain: &Chain, input: &Path, reader: R, reporter: &mut Reporter)
+    -> io::Result<bool> {
+    let name: String = name(input);
+    let mut stdout: BufWriter<io::StdoutLock> = BufWriter::new(io::stdout().lock());
+    let mut result: io::Result<()> = Ok(());
+    let mut changed: bool = false;
+    let mut found = |position: Position, change: Change| {
+        changed = true;
+        if result.is_err() { return; }
+        result = reporter.add(&mut stdout, Finding::new(chain, &name, position, change));
+    };
+    stream::changes_reader(chain, reader, &mut found)?;
+    result?;
+    stdout.flush()?;
+    Ok(changed)
+}
+
+/// Print every record (line, unless `delimiter` says otherwise) of a file (or stdin) that has a
+/// character that would be replaced or that the detectors flag, like `grep -n` with the file name.
+fn print_matching_records<R: Read>(chain: &Chain, input: &Path, reader: R, delimiter: u8)
+    -> io::Result<bool> {
+    let mut reader: BufReader<R> = BufReader::new(reader);
+    let name: String = name(input);
+    let mut stdout: BufWriter<io::StdoutLock> = BufWriter::new(io::stdout().lock());
+    let mut matched: bool = false;
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut number: usize = 0;
+    loop {
+        bytes.clear();
+        if reader.read_until(delimiter, &mut bytes)? == 0 { break; }
+        number += 1;
+        let text: Cow<str> = utf8::from_utf8_lossy(&bytes);
+        let record: &str = text.strip_suffix(delimiter as char).unwrap_or(&text);
+        let record: &str = record.strip_suffix('\r').filter(|_| delimiter == b'\n')
+            .unwrap_or(record);
+        if chain.changes(record).is_empty() && detect::detect(chain, record).is_empty() {
+            continue;
+        }
+        write!(stdout, "{}:{}:{}{}", name, number, record, delimiter as char)?;
+        matched = true;
+    }
+    stdout.flush()?;
+    Ok(matched)
+}
+
+/// Count every change that translating a file (or stdin) would make.
+fn count_changes<R: Read>(chain: &Chain, reader: R, stats: &mut Stats) -> io::Result<bool> {
+    let mut changed: bool = false;
+    let mut found = |_: Position, change: Change| {
+        changed = true;
+        stats.add(chain, change);
+    };
+    stream::changes_reader(chain, reader, &mut found)?;
+    Ok(changed)
+}
+
+/// Print a unified diff between a file (or stdin) and its translation, if anything changed. Unlike
+/// the other modes, this needs the whole input (and its translation) in memory at once.
+fn print_diff<R: Read>(chain: &Chain, input: &Path, reader: R) -> io::Result<bool> {
+    let original: String = read_text(reader)?;
+    let mut translated: Vec<u8> = Vec::new();
+    if !stream::translate_reader(chain, original.as_bytes(), &mut translated)? {
+        return Ok(false);
+    }
+    let translated: String = utf8::from_utf8_lossy(&translated).into_owned();
+    let name: String = name(input);
+    let diff: TextDiff<str> = TextDiff::from_lines(original.as_str(), translated.as_str());
+    let mut stdout: io::StdoutLock = io::stdout().lock();
+    write!(stdout, "{}", diff.unified_diff().context_radius(3)
+        .header(&name, &format!("{} (normalized)", name)))?;
+    stdout.flush()?;
+    Ok(true)
+}
+
+/// How translated text is written: in which encoding, whether it starts with a byte order mark,
+/// and how it is compressed.
+#[derive(Debug, Clone, Copy)]
+struct OutputFormat {
+    encoding: OutputEncoding,
+    bom: bool,
+    /// Whether the input's byte order mark is being added or removed, which counts as a change.
+    bom_changed: bool,
+    /// The compression given with --compress, or decided by the output's name (see
+    /// `OutputFormat::to`). Output is not compressed if neither says anything.
+    compression: Option<Compression>,
+}
+
+impl OutputFormat {
+    /// The format for an input that did or didn't start with a byte order mark.
+    fn new(encoding: OutputEncoding, policy: Bom, had_bom: bool, compression: Option<Compression>)
+        -> OutputFormat {
+        let bom: bool = policy.emit(had_bom, encoding);
+        OutputFormat { encoding, bom, bom_changed: bom != had_bom, compression }
+    }
+
+    /// The format for writing to `path`: compressed as its extension says, unless --compress
+    /// said otherwise.
+    fn to(mut self, path: &Path) -> OutputFormat {
+        self.compression.get_or_insert_with(|| Compression::from_path(path));
+        self
+    }
+
+    /// Start writing to `output` in this format, writing the byte order mark if there is one.
+    fn encoder<'a, W: Write>(&self, output: W, chain: &'a Chain) -> io::Result<Encoder<'a, W>> {
+        let mut encoder: Encoder<W> = Encoder::new(output, self.encoding, chain);
+        if self.bom { encoder.write_bom()?; }
+        Ok(encoder)
+    }
+}
+
+/// Translate a file (or stdin) into `output`, written in `format`, split up as `structure` says.
+/// Returns whether the output differs from the input: whether the translation changed anything,
+/// characters had to be replaced because the output encoding can't represent them, or a byte
+/// order mark was added or removed.
+fn translate<R: Read, W: Write>(chain: &Chain, reader: R, output: W, format: OutputFormat,
+                                structure: Structure) -> io::Result<bool> {
+    let output: Compressor<W> = Compressor::new(output, format.compression.unwrap_or_default())?;
+    let mut encoder: Encoder<Compressor<W>> = format.encoder(output, chain)?;
+    let changed: bool = match structure {
+        Structure::Chunks => stream::translate_reader(chain, reader, &mut encoder)?,
+        Structure::Parallel(threads) => {
+            stream::translate_parallel(chain, reader, &mut encoder, threads)?
+        }
+        Structure::Records(delimiter) => {
+            stream::translate_records(chain, BufReader::new(reader), &mut encoder, delimiter)?
+        }
+        Structure::Table(delimiter, columns) => {
+            tabular::translate_table(chain, reader, &mut encoder, delimiter, columns)?
+        }
+        Structure::Json(pointers) => json::translate_json(chain, reader, &mut encoder, pointers)?,
+        Structure::Markdown => {
+            markdown::translate_markdown(chain, BufReader::new(reader), &mut encoder)?
+        }
+        Structure::Source(language) => {
+            source::translate_source(chain, language, reader, &mut encoder)?
+        }
+    };
+    let replaced: bool = encoder.replaced();
+    encoder.into_inner().finish()?;
+    Ok(changed || replaced || format.bom_changed)
+}
+
+/// Translate a file (or stdin) into a new file at `path`.
+fn translate_to_file<R: Read>(chain: &Chain, reader: R, path: &Path, format: OutputFormat,
+                              structure: Structure) -> io::Result<bool> {
+    translate(chain, reader, BufWriter::new(File::create(path)?), format.to(path), structure)
+}
+
+/// Translate a file and replace it with the result (atomically; see `atomic::replace_with`), if
+/// anything changed (files whose text doesn't change are left in the encoding they are in, even
+/// with --output-encoding). The translation is streamed into a temporary file, so the file is
+/// never held in memory. With a `--backup` suffix, the original is first copied to a file named by
+/// adding the suffix to its name.
+fn translate_in_place<R: Read>(chain: &Chain, input: &Path, reader: R, format: OutputFormat,
+                              structure: Structure, backup: Option<&str>, preserve_times: bool)
+    -> io::Result<bool> {
+    if is_stdin(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--in-place can't write to stdin"));
+    }
+    atomic::replace_with(input, preserve_times, |temp| {
+        if !translate(chain, reader, temp, format, structure)? { return Ok(false); }
+        if let Some(suffix) = backup {
+            let mut name: OsString = input.as_os_str().to_os_string();
+            name.push(suffix);
+            fs::copy(input, &name)?;
+        }
+        Ok(true)
+    })
+}
+
+/// The path of an input given on the command line, made relative by dropping any root, `.` and
+/// `..` components, e.g. "/srv/docs/a.md" becomes "srv/docs/a.md".
+fn relative(input: &Path) -> PathBuf {
+    input.components().filter(|c| matches!(c, Component::Normal(_))).collect()
+}
+
+/// Where `--out-dir` puts an input, creating the directories it needs. Refuses to overwrite the
+/// input itself, which happens if the output directory is the one being walked.
+fn mirrored_path(out_dir: &Path, input: &Path, relative: &Path) -> io::Result<PathBuf> {
+    if is_stdin(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "--out-dir can't name the output for stdin"));
+    }
+    let path: PathBuf = out_dir.join(relative);
+    if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+    if fs::canonicalize(&path).is_ok_and(|path| fs::canonicalize(input).is_ok_and(|i| i == path)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "--out-dir would overwrite the input"));
+    }
+    Ok(path)
+}
+
+/// Name the output file for an input from an `--output-name` template.
+fn output_path(template: &str, input: &Path) -> io::Result<PathBuf> {
+    if is_stdin(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "--output-name can't name the output for stdin"));
+    }
+    let dir: &Path = input.parent().filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let part = |part: Option<&std::ffi::OsStr>| part.map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext: String = input.extension().map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let path: PathBuf = PathBuf::from(template
+        .replace("{dir}", &dir.to_string_lossy())
+        .replace("{name}", &part(input.file_name()))
+        .replace("{stem}", &part(input.file_stem()))
+        .replace("{ext}", &ext));
+
+    // compare without any `.` components, since `{dir}` is `.` for inputs without a directory
+    let plain = |path: &Path| -> PathBuf {
+        path.components().filter(|c| *c != Component::CurDir).collect()
+    };
+    if plain(&path) == plain(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "--output-name would overwrite the input"));
+    }
+    Ok(path)
+}
@@ -0,0 +1,113 @@
+/// The obfuscate subcommand: spoofing text with homoglyphs.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::Args;
+use clio::Input;
+use libnormalize::chain::Chain;
+use libnormalize::obfuscate::Obfuscator;
+use libnormalize::stream;
+use tracing::error;
+use crate::commands::input_name;
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct ObfuscateArgs {
+    /// Input data. Defaults to stdin.
+    #[arg(value_parser, default_value="-")]
+    pub input: Input,
+
+    /// The fraction of the characters that have homoglyphs to replace, from 0 to 1.
+    #[arg(long, value_name = "RATE", default_value_t = 1.0, value_parser = parse_rate)]
+    pub rate: f64,
+
+    /// Seed the random choices, so the same input is always obfuscated the same way. Without
+    /// this, every run is different.
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// Replace an ASCII character with only these homoglyphs, like "a=аɑ", rather than every one
+    /// the chain knows of. Leave out the homoglyphs ("a=") to never replace it. Can be given more
+    /// than once.
+    #[arg(long, value_name = "CHAR=HOMOGLYPHS", value_parser = parse_homoglyphs)]
+    pub homoglyph: Vec<(char, Vec<char>)>,
+
+    /// Print the homoglyphs each ASCII character can be replaced with, and exit.
+    #[arg(long)]
+    pub list: bool,
+}
+
+/// Replace ASCII characters in the input with homoglyphs, using the chain backwards (see
+/// `Obfuscator`): pick a chain with confusables like `--preset cyrillic,greek` to get text that
+/// looks the same. Normalizing the output with the same chain gives back the input. Exits with 2
+/// if the input couldn't be read.
+pub fn run(mut args: ObfuscateArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &load::search_from(&args.input));
+    let seed: u64 = args.seed.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+    });
+    let mut obfuscator: Obfuscator = Obfuscator::new(&chain, seed);
+    obfuscator.rate = args.rate;
+    for (c, homoglyphs) in args.homoglyph.drain(..) {
+        obfuscator.homoglyphs.insert(c, homoglyphs);
+    }
+
+    if args.list {
+        for (c, homoglyphs) in &obfuscator.homoglyphs {
+            if homoglyphs.is_empty() { continue; }
+            let listed: Vec<String> = homoglyphs.iter()
+                .map(|h| format!("{} (U+{:04X})", h, *h as u32))
+                .collect();
+            println!("{}\t{}", c, listed.join(" "));
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let name: String = input_name(&args.input);
+    let mut output: BufWriter<io::StdoutLock> = BufWriter::new(io::stdout().lock());
+    let result: io::Result<()> = stream::read_chunks(&mut args.input, |text| {
+        output.write_all(obfuscator.obfuscate(text).as_bytes())
+    }).and_then(|()| output.flush());
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("[io] {}: {}", name, e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Parse a rate between 0 and 1.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("invalid rate \"{}\" (expected a number from 0 to 1)", s)),
+    }
+}
+
+/// Parse an ASCII character and its homoglyphs, like "a=аɑ".
+fn parse_homoglyphs(s: &str) -> Result<(char, Vec<char>), String> {
+    let (c, homoglyphs) = s.split_once('=')
+        .ok_or_else(|| format!("invalid homoglyphs \"{}\" (expected CHAR=HOMOGLYPHS)", s))?;
+    let mut chars = c.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() => Ok((c, homoglyphs.chars().collect())),
+        _ => Err(format!("invalid homoglyphs \"{}\" (expected a single ASCII character before \
+                          the =)", s)),
+    }
+}
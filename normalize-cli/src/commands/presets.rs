@@ -0,0 +1,42 @@
+/// The presets subcommand, which lists the built-in presets.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::process::ExitCode;
+use libnormalize::presets::{self, External, ALL, PRESETS};
+use crate::load::{self, ChainArgs};
+
+/// Print the name, description and Unicode blocks of every built-in preset, then the presets
+/// loaded from the data directory (if there is one), and the name that selects them all.
+pub fn run(opts: &ChainArgs) -> ExitCode {
+    let external: Vec<External> = load::external(opts);
+    let width: usize = PRESETS.iter().map(|preset| preset.name.len())
+        .chain(external.iter().map(|preset| preset.name.len()))
+        .chain([ALL.len()])
+        .max()
+        .unwrap_or(0);
+    for preset in PRESETS {
+        println!("{:<width$}  {}", preset.name, preset.description, width = width);
+        println!("{:<width$}  blocks: {}", "", preset.blocks.join(", "), width = width);
+        if let Some(replaced) = external.iter().find(|other| other.name == preset.name) {
+            println!("{:<width$}  replaced by {}", "", replaced.path.display(), width = width);
+        }
+    }
+    for preset in external.iter().filter(|preset| presets::find(&preset.name).is_none()) {
+        println!("{:<width$}  from {}", preset.name, preset.path.display(), width = width);
+    }
+    println!("{:<width$}  Every preset above, in this order", ALL, width = width);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,141 @@
+/// The scan subcommand, which checks files for bidirectional controls and invisible characters.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::Args;
+use libnormalize::chain::Chain;
+use libnormalize::detect::{Class, Finding, Severity};
+use libnormalize::stream::{Position, Tracker};
+use libnormalize::utf8;
+use tracing::{error, info};
+use crate::commands::detect;
+use crate::git;
+use crate::load::{self, ChainArgs};
+use crate::walk::{self, Filter, FilterArgs, Walked};
+
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// Files to scan, and directories to scan every file in, recursively. With --staged, these
+    /// limit which staged files are scanned (as git pathspecs).
+    #[arg(required_unless_present = "staged")]
+    pub paths: Vec<PathBuf>,
+
+    /// Scan what is staged in git (the index, not the working tree), and report findings only in
+    /// the lines the staged changes add or change, grouped by hunk. For use as a pre-commit hook.
+    #[arg(long)]
+    pub staged: bool,
+
+    /// Only report findings of at least this severity (info, warn or error). Every class is
+    /// checked, with the severities the config gives them.
+    #[arg(long, value_name = "SEVERITY", default_value_t = Severity::Warn)]
+    pub min_severity: Severity,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+/// Scan every file for suspicious characters (bidi controls, invisible characters, confusables,
+/// mixed scripts and denied characters), without changing anything, for use as a CI gate. Only
+/// findings of at least `--min-severity` are reported; by default that leaves out confusables,
+/// since source code is full of legitimate non-ASCII text. Exits with 1 if any finding has severity "error" (or as the
+/// chain's exit code policy says), and 2 if any file couldn't be read.
+pub fn run(args: ScanArgs, opts: &ChainArgs) -> ExitCode {
+    let filter: Filter = match walk::filter(&args.filter) {
+        Some(filter) => filter,
+        None => return ExitCode::from(2),
+    };
+    let chain: Chain = load::chain(opts, &PathBuf::from("."));
+    let classes: Vec<Class> = Class::ALL.into_iter()
+        .filter(|&class| chain.severity.get(class) >= args.min_severity.max(Severity::Info))
+        .collect();
+    if args.staged {
+        return match scan_staged(&chain, &classes, &filter, &args.paths) {
+            Ok(worst) => detect::exit_code(worst, chain.exit_code),
+            Err(e) => {
+                error!("[git] {}", e);
+                ExitCode::from(2)
+            }
+        };
+    }
+
+    let mut worst: Severity = Severity::Off;
+    let mut unreadable: bool = false;
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in args.paths {
+        if path.is_dir() {
+            let walked: Walked = walk::walk(&path, &filter);
+            unreadable |= walked.errors > 0;
+            files.extend(walked.files);
+        } else if filter.matches(&path) {
+            files.push(path);
+        }
+    }
+    for path in files {
+        info!("[scan] {}", path.display());
+        let name: String = path.display().to_string();
+        match fs::File::open(&path)
+            .and_then(|file| detect::report(&chain, &name, file, &classes, None)) {
+            Ok(severity) => worst = worst.max(severity),
+            Err(e) => {
+                error!("[io] {}: {}", path.display(), e);
+                unreadable = true;
+            }
+        }
+    }
+    if unreadable { ExitCode::from(2) } else { detect::exit_code(worst, chain.exit_code) }
+}
+
+/// Scan the staged version of every staged file (limited to `pathspecs`, if there are any), and
+/// print the findings in each hunk of staged changes, as "path:line:column: finding" under the
+/// hunk's header. Files that look binary are skipped. Returns the worst severity among the
+/// findings printed.
+fn scan_staged(chain: &Chain, classes: &[Class], filter: &Filter, pathspecs: &[PathBuf])
+    -> io::Result<Severity> {
+    let top: PathBuf = git::toplevel()?;
+    let mut worst: Severity = Severity::Off;
+    for path in git::staged_files(pathspecs)?.into_iter().filter(|path| filter.matches(path)) {
+        info!("[scan] {} (staged)", path.display());
+        let data: Vec<u8> = git::staged_contents(&top, &path)?;
+        if walk::looks_binary(&data[..data.len().min(walk::SNIFF_SIZE as usize)]) {
+            info!("[walk] {}: skipped, looks binary", path.display());
+            continue;
+        }
+        let text: Cow<str> = utf8::from_utf8_lossy(&data);
+        let findings: Vec<Finding> = libnormalize::detect::detect(chain, &text).into_iter()
+            .filter(|finding| classes.contains(&finding.class))
+            .collect();
+        if findings.is_empty() { continue; }
+        let positions: Vec<Position> = Tracker::new()
+            .feed(&text, findings.iter().map(|finding| finding.offset));
+
+        for hunk in git::staged_hunks(&top, &path)? {
+            let mut inside = positions.iter().zip(&findings)
+                .filter(|(position, _)| hunk.contains(position.line))
+                .peekable();
+            if inside.peek().is_none() { continue; }
+            println!("{}: {}", path.display(), hunk.header);
+            for (position, finding) in inside {
+                println!("{}:{}:{}: {}", path.display(), position.line, position.column, finding);
+                worst = worst.max(finding.severity);
+            }
+        }
+    }
+    Ok(worst)
+}
@@ -0,0 +1,69 @@
+/// The skeleton subcommand: printing the skeleton of each line.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::ExitCode;
+use clap::Args;
+use clio::Input;
+use libnormalize::chain::Chain;
+use libnormalize::compare;
+use libnormalize::utf8;
+use tracing::error;
+use crate::commands::input_name;
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct SkeletonArgs {
+    /// Input data, one string per line. Defaults to stdin.
+    #[arg(value_parser, default_value="-")]
+    pub input: Input,
+
+    /// Lowercase the skeletons, for matching case-insensitively.
+    #[arg(short, long)]
+    pub ignore_case: bool,
+}
+
+/// Print the skeleton of each line of the input (see `compare::skeleton`), one per line, so that
+/// confusable strings can be matched by comparing their skeletons. This follows UTS #39, with the
+/// chain standing in for its table of confusables. Exits with 2 if the input couldn't be read.
+pub fn run(args: SkeletonArgs, opts: &ChainArgs) -> ExitCode {
+    let chain: Chain = load::chain(opts, &load::search_from(&args.input));
+    let name: String = input_name(&args.input);
+    match print_skeletons(&chain, args.input, args.ignore_case) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("[io] {}: {}", name, e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Print the skeleton of each line, as each one is read. Invalid UTF-8 is decoded as U+FFFD.
+fn print_skeletons(chain: &Chain, input: Input, ignore_case: bool) -> io::Result<()> {
+    let mut input: BufReader<Input> = BufReader::new(input);
+    let mut output: BufWriter<io::StdoutLock> = BufWriter::new(io::stdout().lock());
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 { break; }
+        let text: String = utf8::from_utf8_lossy(&line).into_owned();
+        let text: &str = text.strip_suffix('\n').unwrap_or(&text);
+        let text: &str = text.strip_suffix('\r').unwrap_or(text);
+        writeln!(output, "{}", compare::skeleton(chain, text, ignore_case))?;
+        output.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+/// The table subcommand, which prints the effective mapping of the chain.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::Args;
+use libnormalize::chain::Chain;
+use libnormalize::unicode;
+use tracing::error;
+use crate::load::{self, ChainArgs};
+
+#[derive(Args, Debug)]
+pub struct TableArgs {
+    /// Only print characters in this Unicode block (e.g. "Cyrillic"). Can be given more than
+    /// once.
+    #[arg(long, value_name = "NAME")]
+    pub block: Vec<String>,
+}
+
+/// Print every character the chain handles and what it turns into, one per line and separated
+/// by tabs, in codepoint order (see `Chain::compile`). Exits with 2 if a block name is unknown.
+pub fn run(args: TableArgs, opts: &ChainArgs) -> ExitCode {
+    let mut ranges: Vec<(char, char)> = Vec::new();
+    for name in &args.block {
+        match unicode::block_range(name) {
+            Some(range) => ranges.push(range),
+            None => {
+                error!("[table] Unknown Unicode block \"{}\"", name);
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let chain: Chain = load::chain(opts, &PathBuf::from("."));
+    for (c, replacement) in chain.compile() {
+        if !ranges.is_empty() && !ranges.iter().any(|&(start, end)| (start..=end).contains(&c)) {
+            continue;
+        }
+        println!("U+{:04X}\t{}\t{}", c as u32, c.escape_debug(), replacement.escape_debug());
+    }
+    ExitCode::SUCCESS
+}
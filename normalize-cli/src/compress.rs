@@ -0,0 +1,118 @@
+/// Reading and writing gzip and zstd compressed files.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// The magic numbers that compressed streams start with.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A compression format. Neither magic number can start UTF-8 or UTF-16 text, so compressed input
+/// is recognized by its first few bytes, whatever the file is called.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression a file's extension implies: `.gz` for gzip, and `.zst` for zstd.
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// The compression the start of a stream says it is in.
+    pub fn sniff(start: &[u8]) -> Compression {
+        if start.starts_with(GZIP_MAGIC) { Compression::Gzip }
+        else if start.starts_with(ZSTD_MAGIC) { Compression::Zstd }
+        else { Compression::None }
+    }
+}
+
+/// Read a stream, decompressing it if it is gzip or zstd compressed (see
+/// [`Compression::sniff`]). A gzip file made of several members, as `cat a.gz b.gz` makes, is
+/// read to the end.
+pub fn decompress<'a, R: Read + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut start: Vec<u8> = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut reader).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut start)?;
+    let compression: Compression = Compression::sniff(&start);
+    // put the magic number back in front of the rest of the stream
+    let whole = Cursor::new(start).chain(reader);
+    Ok(match compression {
+        Compression::None => Box::new(whole),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(whole)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(BufReader::new(whole))?),
+    })
+}
+
+/// Writes to `W`, compressing what is written. [`Compressor::finish`] must be called once
+/// everything has been written, to write the end of the compressed stream.
+pub enum Compressor<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressor<W> {
+    /// Start writing to `writer` with the given compression, at its default level.
+    pub fn new(writer: W, compression: Compression) -> io::Result<Compressor<W>> {
+        Ok(match compression {
+            Compression::None => Compressor::None(writer),
+            Compression::Gzip => Compressor::Gzip(GzEncoder::new(writer,
+                                                                 flate2::Compression::default())),
+            Compression::Zstd => Compressor::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Finish the compressed stream and flush it, returning the writer underneath.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer: W = match self {
+            Compressor::None(writer) => writer,
+            Compressor::Gzip(encoder) => encoder.finish()?,
+            Compressor::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressor::None(writer) => writer.write(buf),
+            Compressor::Gzip(encoder) => encoder.write(buf),
+            Compressor::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressor::None(writer) => writer.flush(),
+            Compressor::Gzip(encoder) => encoder.flush(),
+            Compressor::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+/// Reading staged changes from the git index, for pre-commit hooks.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A hunk of staged changes, as the lines it covers in the staged version of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The hunk's header, like "@@ -3,2 +3,4 @@".
+    pub header: String,
+    /// The first line of the hunk in the staged file, starting at 1.
+    pub start: usize,
+    /// How many lines of the staged file the hunk covers.
+    pub len: usize,
+}
+
+impl Hunk {
+    /// Whether a line of the staged file is part of the hunk.
+    pub fn contains(&self, line: usize) -> bool {
+        (self.start..self.start + self.len).contains(&line)
+    }
+}
+
+/// Run a git command, returning what it wrote to stdout. If git can't be run or fails, the error
+/// is what it wrote to stderr.
+fn run(command: &mut Command) -> io::Result<Vec<u8>> {
+    let output: Output = command.output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run git: {}", e)))?;
+    if output.status.success() { return Ok(output.stdout); }
+    let message: String = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if message.is_empty() { Err(io::Error::other(format!("git failed ({})", output.status))) }
+    else { Err(io::Error::other(message)) }
+}
+
+/// The top directory of the repository the working directory is in.
+pub fn toplevel() -> io::Result<PathBuf> {
+    let out: Vec<u8> = run(Command::new("git").args(["rev-parse", "--show-toplevel"]))?;
+    Ok(PathBuf::from(String::from_utf8_lossy(&out).trim_end()))
+}
+
+/// The files with staged changes, relative to the top of the repository, limited to `pathspecs`
+/// if there are any. Deleted files are left out, since there is nothing left of them to check.
+pub fn staged_files(pathspecs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let out: Vec<u8> = run(Command::new("git")
+        .args(["diff", "--cached", "--name-only", "-z", "--diff-filter=ACMR", "--"])
+        .args(pathspecs))?;
+    Ok(out.split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| PathBuf::from(String::from_utf8_lossy(name).into_owned()))
+        .collect())
+}
+
+/// The staged contents of a file (what would be committed), rather than what is in the working
+/// tree. `path` is relative to `top`.
+pub fn staged_contents(top: &Path, path: &Path) -> io::Result<Vec<u8>> {
+    let mut object: String = ":".to_string();
+    object.push_str(&path.to_string_lossy());
+    run(Command::new("git").arg("-C").arg(top).args(["cat-file", "blob", &object]))
+}
+
+/// The hunks of staged changes to a file that add or change lines. `path` is relative to `top`.
+pub fn staged_hunks(top: &Path, path: &Path) -> io::Result<Vec<Hunk>> {
+    let out: Vec<u8> = run(Command::new("git").arg("-C").arg(top)
+        .args(["diff", "--cached", "--unified=0", "--no-color", "--no-ext-diff", "--"])
+        .arg(path))?;
+    Ok(String::from_utf8_lossy(&out).lines()
+        .filter_map(parse_hunk)
+        .filter(|hunk| hunk.len > 0)
+        .collect())
+}
+
+/// Parse a hunk header, like "@@ -3,2 +3,4 @@ fn main() {". The line count is 1 if it's left out.
+fn parse_hunk(line: &str) -> Option<Hunk> {
+    let rest: &str = line.strip_prefix("@@ -")?;
+    let end: usize = rest.find(" @@")?;
+    let (_, new) = rest[..end].split_once(" +")?;
+    let (start, len) = match new.split_once(',') {
+        Some((start, len)) => (start.parse().ok()?, len.parse().ok()?),
+        None => (new.parse().ok()?, 1),
+    };
+    Some(Hunk { header: line[..end + 7].to_string(), start, len })
+}
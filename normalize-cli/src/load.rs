@@ -0,0 +1,291 @@
+/// Finding, loading and caching the translator chain the CLI runs with.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use clap::Args;
+use clio::Input;
+use libnormalize::cache;
+use libnormalize::chain::{Chain, Fallback};
+use libnormalize::config::{self, Config, ConfigError, Format, Global, Layer};
+use libnormalize::presets::{self, External};
+use tracing::{error, warn};
+
+/// Options that decide which chain is used. These apply to every subcommand.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ChainArgs {
+    /// Use this config file instead of searching for one.
+    #[arg(short, long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Don't read or write the compiled config cache.
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Built-in presets to use, separated by commas (see the presets subcommand), or "all" for
+    /// every one of them. Configs are applied on top of them.
+    #[arg(long, global = true, value_delimiter = ',', value_name = "NAME")]
+    pub preset: Vec<String>,
+
+    /// Apply a named profile from the config.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// A regular expression for regions to pass through untouched, such as URLs, UUIDs or emoji,
+    /// like the protect patterns in a config. Can be given more than once; these are added to the
+    /// config's patterns.
+    #[arg(long, global = true, value_name = "REGEX")]
+    pub protect: Vec<String>,
+
+    /// A regular expression that limits normalization to the regions it matches (such as quoted
+    /// strings), leaving everything else byte for byte as it was. Can be given more than once, to
+    /// normalize inside the matches of any of them.
+    #[arg(long, global = true, value_name = "REGEX")]
+    pub only: Vec<String>,
+
+    /// When to exit with a failure, overriding the config's exit_code: "default", "never" (only
+    /// on errors), "changes" (whenever anything is changed, even when writing, or found), or
+    /// "info", "warn" or "error" (on findings of at least that severity).
+    #[arg(long, global = true, value_name = "POLICY")]
+    pub exit_code: Option<String>,
+
+    /// A directory of extra presets: config files, named after the file, and Unicode's
+    /// confusables.txt, as the "confusables" preset. These replace built-in presets with the same
+    /// name. Defaults to $UTF_NORMALIZE_DATA_DIR.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// What to do with non-ASCII characters that no translator handles, overriding the config's
+    /// fallback: "keep", "drop", "replace" (with the config's replacement, or with "?" as in
+    /// "replace=?"), "escape" (as \u{XXXX}) or "error" (fail on the first one, saying where it
+    /// is).
+    #[arg(long, global = true, value_name = "POLICY", value_parser = parse_fallback)]
+    pub fallback: Option<(Fallback, Option<String>)>,
+}
+
+/// Parse a fallback policy from the command line, where "replace" can be followed by `=` and the
+/// replacement.
+fn parse_fallback(s: &str) -> Result<(Fallback, Option<String>), String> {
+    let (policy, replacement): (&str, Option<&str>) = match s.split_once('=') {
+        Some((policy, replacement)) => (policy, Some(replacement)),
+        None => (s, None),
+    };
+    let fallback: Fallback = policy.parse()?;
+    if replacement.is_some() && fallback != Fallback::Replace {
+        return Err(format!("only \"replace\" takes a replacement, not \"{}\"", policy));
+    }
+    Ok((fallback, replacement.map(str::to_string)))
+}
+
+/// Where to start looking for a project config: the directory of the input file, or the working
+/// directory when reading from stdin.
+pub fn search_from(input: &Input) -> PathBuf {
+    if input.is_std() { PathBuf::from(".") } else { input.path().to_path_buf() }
+}
+
+/// Load the chain described by the presets given with `--preset`, and the config given with
+/// `--config` or every config that applies to `search_from` (see `find_configs`). If there are
+/// no presets or configs at all, the default preset is used.
+pub fn chain(opts: &ChainArgs, search_from: &Path) -> Chain {
+    chain_from(opts, configs(opts, search_from))
+}
+
+/// The merged config that `chain` builds its chain from (never taken from the cache).
+pub fn config(opts: &ChainArgs, search_from: &Path) -> Config {
+    let found: Vec<(&'static str, PathBuf)> = configs(opts, search_from);
+    if found.is_empty() && opts.preset.is_empty() {
+        return resolve_config(&load_layers(&with_default_preset(opts), found), None);
+    }
+    resolve_config(&load_layers(opts, found), opts.profile.as_deref())
+}
+
+/// The options with the default preset selected, for when no presets or configs are given.
+fn with_default_preset(opts: &ChainArgs) -> ChainArgs {
+    ChainArgs { preset: vec![presets::DEFAULT.to_string()], ..opts.clone() }
+}
+
+/// Load the chain described by the presets given with `--preset` and the given config files.
+fn chain_from(opts: &ChainArgs, found: Vec<(&'static str, PathBuf)>) -> Chain {
+    if found.is_empty() && opts.preset.is_empty() {
+        return load_chain(&load_layers(&with_default_preset(opts), found), None);
+    }
+    if found.is_empty() || opts.no_cache {
+        return load_chain(&load_layers(opts, found), opts.profile.as_deref());
+    }
+    load_chain_cached(opts, found)
+}
+
+/// Loads the chains for many inputs, which may each have their own project config. Inputs that
+/// use the same config files share one chain.
+pub struct Chains<'a> {
+    opts: &'a ChainArgs,
+    loaded: HashMap<Vec<(&'static str, PathBuf)>, Arc<Chain>>,
+}
+
+impl<'a> Chains<'a> {
+    pub fn new(opts: &'a ChainArgs) -> Chains<'a> {
+        Chains { opts, loaded: HashMap::new() }
+    }
+
+    /// The chain for an input at `search_from` (see `chain`).
+    pub fn get(&mut self, search_from: &Path) -> Arc<Chain> {
+        let found: Vec<(&'static str, PathBuf)> = configs(self.opts, search_from);
+        if let Some(chain) = self.loaded.get(&found) { return chain.clone(); }
+        let chain: Arc<Chain> = Arc::new(chain_from(self.opts, found.clone()));
+        self.loaded.insert(found, chain.clone());
+        chain
+    }
+}
+
+/// The config files to use: just the one given with `--config` if there is one, and every config
+/// that applies to `search_from` otherwise.
+pub fn configs(opts: &ChainArgs, search_from: &Path) -> Vec<(&'static str, PathBuf)> {
+    match &opts.config {
+        Some(path) => vec![("cli", path.clone())],
+        None => find_configs(search_from),
+    }
+}
+
+/// Find every config file that applies, from lowest to highest precedence (see
+/// `config::resolve`), along with the name of the layer each one belongs to. This stacks up every
+/// config we can find: the user's config directory, a .utf-normalize.toml in or above
+/// `search_from`, and the one named by UTF_NORMALIZE_CONFIG.
+pub fn find_configs(search_from: &Path) -> Vec<(&'static str, PathBuf)> {
+    [
+        ("user", config::user_config_path()),
+        ("project", config::project_config_path(search_from)),
+        ("env", config::env_config_path()),
+    ].into_iter()
+        .filter_map(|(name, path)| path.map(|path| (name, path)))
+        .collect()
+}
+
+/// Load the presets in the directory given with `--data-dir` or `UTF_NORMALIZE_DATA_DIR`, if
+/// there is one, precompiling its `confusables.txt` into the cache directory unless `--no-cache`
+/// is given. Exits with an error message if any of them can't be loaded.
+pub fn external(opts: &ChainArgs) -> Vec<External> {
+    match opts.data_dir.clone().or_else(presets::env_data_dir) {
+        Some(dir) => {
+            let cache_dir: Option<PathBuf> = cache::cache_dir().filter(|_| !opts.no_cache);
+            exit_on_error(presets::load_dir_cached(&dir, cache_dir.as_deref()))
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Load config files into layers. The first layer is always the built-in defaults, followed by
+/// the presets selected with `--preset` (if any), and the last holds the patterns given with
+/// `--protect` and `--only` and the policies given with `--exit-code` and `--fallback` (if any).
+/// Exits with an error message if any of the presets are unknown or any of the configs are
+/// invalid.
+pub fn load_layers(opts: &ChainArgs, found: Vec<(&str, PathBuf)>) -> Vec<Layer> {
+    let mut layers: Vec<Layer> = vec![
+        Layer { name: "defaults".to_string(), path: None, config: Config::default() }
+    ];
+    if !opts.preset.is_empty() {
+        let cfg: Config = exit_on_error(presets::compose_with(&opts.preset, &external(opts)));
+        layers.push(Layer { name: format!("presets ({})", opts.preset.join(", ")), path: None,
+                            config: cfg });
+    }
+    for (name, path) in found {
+        let cfg: Config = exit_on_error(config::load(&path, Format::from_path(&path)));
+        layers.push(Layer { name: name.to_string(), path: Some(path), config: cfg });
+    }
+    if !opts.protect.is_empty() || !opts.only.is_empty() || opts.exit_code.is_some()
+        || opts.fallback.is_some() {
+        let (fallback, replacement): (Option<String>, Option<String>) = match &opts.fallback {
+            Some((fallback, replacement)) => (Some(fallback.to_string()), replacement.clone()),
+            None => (None, None),
+        };
+        let global: Global = Global { protect: opts.protect.clone(), only: opts.only.clone(),
+                                      exit_code: opts.exit_code.clone(), fallback, replacement,
+                                      ..Global::default() };
+        layers.push(Layer { name: "command line".to_string(), path: None,
+                            config: Config { global, ..Config::default() } });
+    }
+    layers
+}
+
+/// Like `load_chain`, but reuses a compiled chain from the cache directory if none of the config
+/// files have changed since it was compiled, and refreshes the cache otherwise. Problems with the
+/// cache itself are never fatal; they just mean the configs are loaded normally.
+fn load_chain_cached(opts: &ChainArgs, found: Vec<(&str, PathBuf)>) -> Chain {
+    let profile: Option<&str> = opts.profile.as_deref();
+    // the cache key is every config path, the presets and profile, the files in the data
+    // directory, and every override from the environment
+    let mut key: Vec<String> = found.iter()
+        .map(|(name, path)| format!("{}={}", name, path.display()))
+        .collect();
+    key.extend(opts.preset.iter().enumerate().map(|(i, name)| format!("--preset[{}]={}", i, name)));
+    key.extend(profile.map(|profile| format!("--profile={}", profile)));
+    key.extend(opts.protect.iter().enumerate()
+        .map(|(i, pattern)| format!("--protect[{}]={}", i, pattern)));
+    key.extend(opts.only.iter().enumerate()
+        .map(|(i, pattern)| format!("--only[{}]={}", i, pattern)));
+    key.extend(opts.exit_code.as_ref().map(|policy| format!("--exit-code={}", policy)));
+    key.extend(opts.data_dir.as_ref().map(|dir| format!("--data-dir={}", dir.display())));
+    if let Some(dir) = opts.data_dir.clone().or_else(presets::env_data_dir) {
+        key.extend(cache::dir_listing(&dir).into_iter().map(|file| format!("data:{}", file)));
+    }
+    key.extend(opts.fallback.as_ref().map(|(fallback, replacement)| {
+        format!("--fallback={}={:?}", fallback, replacement)
+    }));
+    key.extend(std::env::vars().filter(|(name, _)| name.starts_with("UTF_NORMALIZE_"))
+        .map(|(name, value)| format!("{}={}", name, value)));
+    key.sort();
+
+    let path: Option<PathBuf> = cache::cache_dir().map(|dir| cache::cache_path(&dir, &key));
+    if let Some(chain) = path.as_deref().and_then(cache::load) {
+        return chain;
+    }
+
+    let layers: Vec<Layer> = load_layers(opts, found);
+    let chain: Chain = load_chain(&layers, profile);
+    if let Some(path) = path {
+        let files: Vec<PathBuf> = layers.into_iter().flat_map(|layer| layer.config.files).collect();
+        if let Err(e) = cache::store(&path, &chain, &files) {
+            warn!("[cache] Could not write {}: {}", path.display(), e);
+        }
+    }
+    chain
+}
+
+/// Merge config layers into a translator chain, applying the selected profile (if any) and any
+/// environment variable overrides. Exits with an error message if the config is invalid.
+fn load_chain(layers: &[Layer], profile: Option<&str>) -> Chain {
+    exit_on_error(config::build(&resolve_config(layers, profile)))
+}
+
+/// Merge config layers, then apply the selected profile and any environment variable overrides.
+pub fn resolve_config(layers: &[Layer], profile: Option<&str>) -> Config {
+    let mut cfg: Config = config::resolve(layers);
+    if let Some(profile) = profile { exit_on_error(cfg.select_profile(profile)); }
+    exit_on_error(cfg.apply_env());
+    cfg
+}
+
+/// Unwrap a config result, or print the error and exit.
+pub fn exit_on_error<T>(result: Result<T, ConfigError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            error!("[config] {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+/// Logging to stderr, with -q and -v to pick how much of it is shown.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+use clap::{ArgAction, Args};
+use tracing_subscriber::filter::LevelFilter;
+
+/// How much is logged to stderr. These are global, so they can go before or after the subcommand.
+#[derive(Args, Debug)]
+pub struct LogArgs {
+    /// Only log errors. Summaries, skipped files and warnings (like lint overlaps) are hidden.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log more: once for each file as it's processed and the summary at the end, twice for each
+    /// character a translator replaces (and which translator did it), and three times for
+    /// everything.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl LogArgs {
+    /// The most verbose level that gets logged. Without -q or -v, that's warnings and errors.
+    pub fn level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::ERROR,
+            (false, 0) => LevelFilter::WARN,
+            (false, 1) => LevelFilter::INFO,
+            (false, 2) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Install the subscriber that writes log messages to stderr. Messages already say where they
+/// come from (`[io]`, `[walk]` and so on), so there's no timestamp, level or target before them,
+/// and no color, so they read the same as they always have.
+pub fn init(args: &LogArgs) {
+    tracing_subscriber::fmt()
+        .with_max_level(args.level())
+        .with_writer(io::stderr)
+        .without_time()
+        .with_target(false)
+        .with_level(false)
+        .with_ansi(false)
+        .init();
+}
@@ -14,70 +14,127 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::io::{Read, Write};
-use clap::Parser;
-use clio::{Input, Output};
-use libnormalize::translators::{translate, range_translation, multirange_translation, ascii_filter};
+mod archive;
+mod atomic;
+mod commands;
+mod compress;
+mod git;
+mod load;
+mod logging;
+mod progress;
+mod report;
+mod stats;
+mod walk;
+
+use std::process::ExitCode;
+use clap::{ArgMatches, Args as _, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use commands::bench::BenchArgs;
+use commands::compare::CompareArgs;
+use commands::config::ConfigCommand;
+use commands::detect::DetectArgs;
+use commands::explain::ExplainArgs;
+use commands::inspect::InspectArgs;
+use commands::normalize::NormalizeArgs;
+use commands::obfuscate::ObfuscateArgs;
+use commands::scan::ScanArgs;
+use commands::skeleton::SkeletonArgs;
+use commands::table::TableArgs;
+use load::ChainArgs;
+use logging::LogArgs;
 
 /// Program for normalizing uncommon Unicode characters into their ASCII equivalents.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Input data. Defaults to stdin.
-    #[arg(short, long, value_parser, default_value="-")]
-    input_file: Input,
+    #[command(flatten)]
+    chain: ChainArgs,
 
-    /// Location to output to. Defaults to stdout.
-    #[arg(short, long, value_parser, default_value="-")]
-    output_file: Output,
-}
+    #[command(flatten)]
+    log: LogArgs,
 
-fn main() {
-    let mut args = Args::parse();
+    /// List the built-in presets (the same as the presets subcommand).
+    #[arg(long)]
+    list_presets: bool,
 
-    /*
-     * This is just a test translator; it converts ASCII characters from lowercase to uppercase, and
-     * vice versa.
-     */
-    let test_translator = [
-        range_translation('a', 'A', 26),
-        range_translation('A', 'a', 26)
-    ];
+    /// Without a subcommand, the input is normalized (see the normalize subcommand).
+    #[command(flatten)]
+    normalize: NormalizeArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    /*
-     * This is another test translator that implements the Caesar cipher (with a right rotation of
-     * 1).
-     */
-    let caesar_translator = [
-        range_translation('a', 'b', 25),
-        range_translation('z', 'a', 1),
-        range_translation('A', 'B', 25),
-        range_translation('Z', 'A', 1)
-    ];
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Translate text into its normalized form. This is the default.
+    Normalize(Box<NormalizeArgs>),
+    /// Report suspicious characters (bidi controls, invisible characters and confusables).
+    Detect(DetectArgs),
+    /// Check files for bidi controls, invisible characters and other suspicious characters.
+    Scan(ScanArgs),
+    /// Show each character of the input, with its name and category, and how it is handled.
+    Inspect(InspectArgs),
+    /// Describe single characters, and what the chain does with them.
+    Explain(ExplainArgs),
+    /// Inspect, check and create configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// List the built-in presets that --preset accepts.
+    Presets,
+    /// Print every character the chain handles, and what it turns into.
+    Table(TableArgs),
+    /// Replace ASCII characters with homoglyphs from the chain, for red-team and filter testing.
+    Obfuscate(ObfuscateArgs),
+    /// Check whether two strings (like usernames or domains) are confusable.
+    Compare(CompareArgs),
+    /// Print the skeleton of each line of the input, for matching confusable strings.
+    Skeleton(SkeletonArgs),
+    /// Measure how fast the chain translates some input, and how the time splits by translator.
+    Bench(BenchArgs),
+}
 
-    let mathematical_an_translator = [
-        // return first on ASCII chars
-        ascii_filter(),
-        // Mathematical bold, italic, bold/italic, script, bold script; uppercase
-        multirange_translation('\u{1D400}', 'A', 26, 52, 5),
-        // Mathematical bold, italic, bold/italic, script, bold script; lowercase
-        multirange_translation('\u{1D41A}', 'a', 26, 52, 5),
-        // Mathematical bold fraktur; sans, bold, italic, bold/italic uppercase
-        multirange_translation('\u{1D56C}', 'A', 26, 52, 5),
-        // Mathematical bold fraktur; sans, bold, italic, bold/italic lowercase
-        multirange_translation('\u{1D586}', 'a', 26, 52, 5)
-    ];
+fn main() -> ExitCode {
+    let matches: ArgMatches = Args::command().get_matches();
+    let args: Args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    reject_normalize_args(&matches);
+    logging::init(&args.log);
+    if args.list_presets { return commands::presets::run(&args.chain); }
 
-    /* Read input (for reading from stdin, this is intended to be a pipe) */
-    if (args.input_file.is_std()) {
-        todo!("implement reading from stdin");
+    match args.command {
+        None => commands::normalize::run(args.normalize, &args.chain),
+        Some(Command::Normalize(normalize)) => commands::normalize::run(*normalize, &args.chain),
+        Some(Command::Detect(detect)) => commands::detect::run(detect, &args.chain),
+        Some(Command::Scan(scan)) => commands::scan::run(scan, &args.chain),
+        Some(Command::Inspect(inspect)) => commands::inspect::run(inspect, &args.chain),
+        Some(Command::Explain(explain)) => commands::explain::run(explain, &args.chain),
+        Some(Command::Config(config)) => commands::config::run(config, &args.chain),
+        Some(Command::Presets) => commands::presets::run(&args.chain),
+        Some(Command::Table(table)) => commands::table::run(table, &args.chain),
+        Some(Command::Obfuscate(obfuscate)) => commands::obfuscate::run(obfuscate, &args.chain),
+        Some(Command::Compare(compare)) => commands::compare::run(compare, &args.chain),
+        Some(Command::Skeleton(skeleton)) => commands::skeleton::run(skeleton, &args.chain),
+        Some(Command::Bench(bench)) => commands::bench::run(bench, &args.chain),
     }
-    else {
-        let f: &mut std::fs::File = args.input_file.get_file().unwrap();
-        let mut res0: String = String::new();
-        f.read_to_string(&mut res0).unwrap();
-        let charray = res0.chars();
-        let res1 = charray.map(|a| translate(a, &test_translator)).collect::<String>();
-        args.output_file.write(res1.as_bytes()).unwrap();
+}
+
+/// Exit with an error if any of normalize's own options were given before a subcommand, where
+/// they would otherwise be ignored. (They can be given without a subcommand, since normalize is
+/// the default; global options like `--preset` can go anywhere.)
+fn reject_normalize_args(matches: &ArgMatches) {
+    let Some((name, _)) = matches.subcommand() else { return; };
+    let normalize: clap::Command = NormalizeArgs::augment_args(clap::Command::new("normalize"));
+    let given: Option<&clap::Arg> = normalize.get_arguments().find(|arg| {
+        matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    if let Some(arg) = given {
+        let shown: String = match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => arg.get_id().to_string(),
+        };
+        Args::command().error(ErrorKind::ArgumentConflict,
+                              format!("{} can't be used before the {} subcommand", shown, name))
+            .exit();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,105 @@
+/// Progress bars for long runs.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// How the bars are drawn.
+const TEMPLATE: &str = "{prefix:>7} [{bar:40}] {bytes:>10}/{total_bytes:<10} {wide_msg}";
+
+/// A bar for the current file, and one for every input together, both counting bytes read. When
+/// progress isn't shown, every method does nothing.
+pub struct Progress {
+    bars: Option<Bars>,
+}
+
+struct Bars {
+    multi: MultiProgress,
+    file: ProgressBar,
+    total: ProgressBar,
+}
+
+impl Progress {
+    /// Set up progress bars for a run over `inputs`, if `enabled`. They are only drawn when both
+    /// stdout and stderr are terminals, so they never end up in redirected output or logs.
+    pub fn new<'a>(enabled: bool, inputs: impl Iterator<Item = &'a PathBuf>) -> Progress {
+        if !enabled || !io::stdout().is_terminal() || !io::stderr().is_terminal() {
+            return Progress { bars: None };
+        }
+        // stdin has no length, so it only moves the bars forward
+        let length: u64 = inputs.filter_map(|input| fs::metadata(input).ok())
+            .map(|meta| meta.len())
+            .sum();
+        let style: ProgressStyle = ProgressStyle::with_template(TEMPLATE)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        let multi: MultiProgress = MultiProgress::new();
+        let file: ProgressBar = multi.add(ProgressBar::new(0).with_style(style.clone())
+            .with_prefix("file"));
+        let total: ProgressBar = multi.add(ProgressBar::new(length).with_style(style)
+            .with_prefix("total"));
+        Progress { bars: Some(Bars { multi, file, total }) }
+    }
+
+    /// Start on the next input.
+    pub fn start(&self, input: &Path) {
+        if let Some(bars) = &self.bars {
+            bars.file.set_length(fs::metadata(input).map(|meta| meta.len()).unwrap_or(0));
+            bars.file.set_position(0);
+            bars.file.set_message(input.display().to_string());
+        }
+    }
+
+    /// Count everything read from `reader` towards both bars.
+    pub fn wrap<'a, R: Read + 'a>(&'a self, reader: R) -> Box<dyn Read + 'a> {
+        Box::new(Counted { inner: reader, bars: self.bars.as_ref() })
+    }
+
+    /// Hide the bars while `f` prints something, so the output isn't mixed into them.
+    pub fn suspend<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        match &self.bars {
+            Some(bars) => bars.multi.suspend(f),
+            None => f(),
+        }
+    }
+
+    /// Remove the bars, once every input is done.
+    pub fn finish(&self) {
+        if let Some(bars) = &self.bars {
+            bars.file.finish_and_clear();
+            bars.total.finish_and_clear();
+        }
+    }
+}
+
+/// A reader that moves the progress bars forward as it is read.
+struct Counted<'a, R> {
+    inner: R,
+    bars: Option<&'a Bars>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n: usize = self.inner.read(buf)?;
+        if let Some(bars) = self.bars {
+            bars.file.inc(n as u64);
+            bars.total.inc(n as u64);
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,219 @@
+/// Reports of the changes normalization would make, as text, JSON or SARIF.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+use libnormalize::chain::{Change, Chain};
+use libnormalize::detect::{self, Class};
+use libnormalize::stream::Position;
+
+/// The formats `--report` can be written in.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// One line per change: "path:line:column U+XXXX 'original' -> 'replacement' (translator)".
+    #[default]
+    Text,
+    /// A single JSON object, with a list of findings.
+    Json,
+    /// SARIF 2.1.0, for GitHub code scanning and other static analysis tools.
+    Sarif,
+}
+
+/// Where a finding is in its file.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<Position> for Location {
+    fn from(position: Position) -> Location {
+        Location { offset: position.offset, line: position.line, column: position.column }
+    }
+}
+
+/// The span of a finding: from the start of the character to just after it.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+/// A character that normalization replaces, with everything a report says about it.
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub file: String,
+    pub span: Span,
+    /// The original character's codepoint, as "U+XXXX".
+    pub codepoint: String,
+    pub character: char,
+    pub replacement: String,
+    /// What replaced the character (see `Chain::describe`).
+    pub translator: String,
+    /// The detection class of the character (see the detect subcommand), if it has one.
+    pub class: Option<String>,
+    /// The severity configured for the character's class, if it has one.
+    pub severity: Option<String>,
+}
+
+impl Finding {
+    pub fn new(chain: &Chain, file: &str, position: Position, change: Change) -> Finding {
+        let end: Location = Location {
+            offset: position.offset + change.character.len_utf8(),
+            line: position.line,
+            column: position.column + 1,
+        };
+        let class: Option<Class> = detect::classify(chain, change.character);
+        Finding {
+            file: file.to_string(),
+            span: Span { start: position.into(), end },
+            codepoint: format!("U+{:04X}", change.character as u32),
+            character: change.character,
+            replacement: change.replacement,
+            translator: chain.describe(change.handler),
+            class: class.map(|class| class.name().to_string()),
+            severity: class.map(|class| chain.severity.get(class).to_string()),
+        }
+    }
+}
+
+/// The JSON report: every finding, in the order they were found.
+#[derive(Serialize, Debug)]
+struct JsonReport<'a> {
+    findings: &'a [Finding],
+}
+
+/// Collects findings and writes them out in the chosen format. Text reports are written as
+/// findings arrive; the other formats are a single document, written by `finish`.
+#[derive(Debug, Default)]
+pub struct Reporter {
+    format: ReportFormat,
+    findings: Vec<Finding>,
+}
+
+impl Reporter {
+    pub fn new(format: ReportFormat) -> Reporter {
+        Reporter { format, findings: Vec::new() }
+    }
+
+    /// Add a finding to the report.
+    pub fn add<W: Write>(&mut self, out: &mut W, finding: Finding) -> io::Result<()> {
+        match self.format {
+            ReportFormat::Text => writeln!(out, "{}:{}:{} {} '{}' -> '{}' ({})", finding.file,
+                                           finding.span.start.line, finding.span.start.column,
+                                           finding.codepoint, finding.character,
+                                           finding.replacement, finding.translator),
+            ReportFormat::Json | ReportFormat::Sarif => {
+                self.findings.push(finding);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write out the report, once every input has been processed.
+    pub fn finish<W: Write>(self, mut out: W) -> io::Result<()> {
+        match self.format {
+            ReportFormat::Text => {}
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, &JsonReport { findings: &self.findings })?;
+                writeln!(out)?;
+            }
+            ReportFormat::Sarif => {
+                serde_json::to_writer_pretty(&mut out, &sarif(&self.findings))?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// The SARIF rule for findings whose character has no detection class.
+const REPLACED_RULE: &str = "replaced";
+
+/// The SARIF rules findings can belong to: one per detection class, and one for everything else.
+fn sarif_rules() -> Vec<Value> {
+    let mut rules: Vec<Value> = Class::ALL.iter().map(|class| {
+        let description: &str = match class {
+            Class::BidiControls => "Bidirectional control character, which can make text display \
+                                    differently from how it is read (as in Trojan Source attacks).",
+            Class::Invisible => "Invisible character, such as a zero-width space.",
+            Class::Confusables => "Character that looks like an ASCII character (a homoglyph).",
+            Class::MixedScripts => "Letter from a different script than the rest of its word.",
+            Class::Denied => "Character on the deny list, which normalization refuses.",
+        };
+        json!({ "id": class.name(), "shortDescription": { "text": description } })
+    }).collect();
+    rules.push(json!({
+        "id": REPLACED_RULE,
+        "shortDescription": { "text": "Character that normalization replaces." },
+    }));
+    rules
+}
+
+/// The SARIF level for a finding's severity. Characters without a class are notes.
+fn sarif_level(severity: Option<&str>) -> &'static str {
+    match severity {
+        Some("error") => "error",
+        Some("warn") => "warning",
+        Some("off") => "none",
+        _ => "note",
+    }
+}
+
+/// A SARIF 2.1.0 log with a single run, holding every finding as a result.
+fn sarif(findings: &[Finding]) -> Value {
+    let results: Vec<Value> = findings.iter().map(|finding| json!({
+        "ruleId": finding.class.as_deref().unwrap_or(REPLACED_RULE),
+        "level": sarif_level(finding.severity.as_deref()),
+        "message": {
+            "text": format!("{} '{}' is replaced with '{}' ({})", finding.codepoint,
+                            finding.character, finding.replacement, finding.translator),
+        },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": finding.file.replace('\\', "/") },
+                "region": {
+                    "startLine": finding.span.start.line,
+                    "startColumn": finding.span.start.column,
+                    "endLine": finding.span.end.line,
+                    "endColumn": finding.span.end.column,
+                    "byteOffset": finding.span.start.offset,
+                    "byteLength": finding.span.end.offset - finding.span.start.offset,
+                },
+            },
+        }],
+    })).collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "utf-normalize",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/cptlobster/utf-normalize",
+                    "rules": sarif_rules(),
+                },
+            },
+            // columns count characters, not UTF-16 code units
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
@@ -0,0 +1,79 @@
+/// Statistics about the changes normalization would make across a run.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Write};
+use libnormalize::chain::{Change, Chain};
+use libnormalize::unicode;
+
+/// Counts of the characters that would be replaced, by block, by translator and by codepoint.
+#[derive(Debug, Default)]
+pub struct Stats {
+    total: usize,
+    blocks: HashMap<&'static str, usize>,
+    translators: HashMap<String, usize>,
+    /// Each codepoint's count, along with its replacement.
+    codepoints: HashMap<char, (usize, String)>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Count a change.
+    pub fn add(&mut self, chain: &Chain, change: Change) {
+        self.total += 1;
+        let block: &'static str = unicode::block(change.character).unwrap_or("No Block");
+        *self.blocks.entry(block).or_default() += 1;
+        *self.translators.entry(chain.describe(change.handler)).or_default() += 1;
+        self.codepoints.entry(change.character).or_insert((0, change.replacement)).0 += 1;
+    }
+
+    /// Write the statistics: the total, the counts by block and by translator, and the `top` most
+    /// common codepoints. Each list is sorted from most to least common.
+    pub fn write<W: Write>(&self, mut out: W, top: usize) -> io::Result<()> {
+        writeln!(out, "{} characters would be replaced", self.total)?;
+        if self.total == 0 { return out.flush(); }
+
+        writeln!(out, "\nBy block:")?;
+        for (block, count) in by_count(&self.blocks) {
+            writeln!(out, "{:>10}  {}", count, block)?;
+        }
+        writeln!(out, "\nBy translator:")?;
+        for (translator, count) in by_count(&self.translators) {
+            writeln!(out, "{:>10}  {}", count, translator)?;
+        }
+        writeln!(out, "\nTop {} codepoints:", top.min(self.codepoints.len()))?;
+        let counts: HashMap<char, usize> = self.codepoints.iter()
+            .map(|(&c, &(count, _))| (c, count))
+            .collect();
+        for (c, count) in by_count(&counts).into_iter().take(top) {
+            writeln!(out, "{:>10}  U+{:04X}  {:<6} -> {:?}", count, c as u32, format!("{:?}", c),
+                     self.codepoints[&c].1)?;
+        }
+        out.flush()
+    }
+}
+
+/// Sort counts from most to least common, breaking ties by key so the output is stable.
+fn by_count<K: Clone + Ord + Hash>(counts: &HashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut sorted: Vec<(K, usize)> = counts.iter().map(|(key, &count)| (key.clone(), count))
+        .collect();
+    sorted.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    sorted
+}
@@ -0,0 +1,283 @@
+/// Walking directory trees for recursive modes.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use clap::{Args, ValueEnum};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use tracing::{error, info};
+use libnormalize::encoding;
+use crate::archive;
+use crate::compress;
+
+/// How many bytes at the start of a file are checked to decide if it is binary.
+pub const SNIFF_SIZE: u64 = 8 * 1024;
+
+/// How much of the start of a file (in percent) can be invalid UTF-8 before it counts as binary.
+const MAX_INVALID_PERCENT: usize = 30;
+
+/// Options for choosing which files are processed.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Only process files matching this glob (e.g. '**/*.md'). Can be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files matching this glob (e.g. 'vendor/**'). Can be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// When walking directories, don't skip files that are ignored by .gitignore, .ignore and
+    /// similar files.
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// When walking directories, don't skip hidden files and directories.
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// What to do with files that look binary (with NUL bytes, or mostly invalid UTF-8) when
+    /// walking directories or given more than one file: "skip" them (the default), report them as
+    /// an "error", or "force" them to be processed anyway (which a bare --binary means).
+    #[arg(long, value_name = "POLICY", value_enum, default_value = "skip", num_args = 0..=1,
+          default_missing_value = "force")]
+    pub binary: Binary,
+
+    /// Look inside zip and tar archives (.zip, .tar, .tar.gz, .tgz, .tar.zst), processing the text
+    /// files in them. Archives can only be read, so this only works with --check, --diff,
+    /// --report, --grep and --stats. The filters apply to the paths inside archives too.
+    #[arg(long)]
+    pub archives: bool,
+
+    /// Skip (with a warning) files bigger than this when walking directories or given more than
+    /// one file, e.g. "10M". K, M and G are powers of 1024.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_filesize: Option<u64>,
+}
+
+/// What to do with files that look binary.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Binary {
+    /// Report them as errors, and don't process them.
+    Error,
+    /// Leave them out, counting them in the summary.
+    #[default]
+    Skip,
+    /// Process them like any other file.
+    Force,
+}
+
+/// Compiled include and exclude globs. Globs without a `/` match the file name anywhere in the
+/// tree, like in a .gitignore; globs with one match the whole path, relative to the directory
+/// being walked. In both, `*` doesn't match across directories, and `**` does.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    include: Option<(GlobSet, GlobSet)>,
+    exclude: (GlobSet, GlobSet),
+    no_ignore: bool,
+    hidden: bool,
+    binary: Binary,
+    max_filesize: Option<u64>,
+    archives: bool,
+}
+
+impl Filter {
+    pub fn new(args: &FilterArgs) -> Result<Filter, globset::Error> {
+        let include: Option<(GlobSet, GlobSet)> = if args.include.is_empty() { None }
+                                                  else { Some(compile(&args.include)?) };
+        Ok(Filter {
+            include,
+            exclude: compile(&args.exclude)?,
+            no_ignore: args.no_ignore,
+            hidden: args.hidden,
+            binary: args.binary,
+            max_filesize: args.max_filesize,
+            archives: args.archives,
+        })
+    }
+
+    /// Whether a file should be processed. `path` should be relative to the directory being
+    /// walked, or as it was given on the command line.
+    pub fn matches(&self, path: &Path) -> bool {
+        // globs are written without a leading `./`
+        let path: PathBuf = path.components().filter(|c| *c != Component::CurDir).collect();
+        let is_match = |(names, paths): &(GlobSet, GlobSet)| {
+            path.file_name().is_some_and(|name| names.is_match(name)) || paths.is_match(&path)
+        };
+        self.include.as_ref().is_none_or(is_match) && !is_match(&self.exclude)
+    }
+
+    /// Whether to look inside archives (see [`crate::archive`]).
+    pub fn archives(&self) -> bool {
+        self.archives
+    }
+
+    /// Whether a file should be processed, going by its size and whether it looks binary (and the
+    /// --binary policy). Files that are skipped are counted in `walked` (and reported, if they are
+    /// too big), and binary files that are errors are reported and counted there too. With
+    /// --archives, archives are let through, to be looked inside.
+    pub fn admit(&self, path: &Path, walked: &mut Walked) -> bool {
+        // files whose size can't be read are let through, so the error is reported later
+        let size = || fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+        if !self.admit_size(path, size, walked) { return false; }
+        if self.archives && archive::is_archive(path) { return true; }
+        self.binary == Binary::Force || self.admit_binary(path, is_binary(path), walked)
+    }
+
+    /// Whether a file inside an archive should be processed, like [`Filter::admit`], given its
+    /// size and the start of it. `name` is what to call it in warnings.
+    pub fn admit_entry(&self, name: &Path, size: u64, start: &[u8], walked: &mut Walked) -> bool {
+        if !self.admit_size(name, || size, walked) { return false; }
+        self.binary == Binary::Force || self.admit_binary(name, looks_binary(start), walked)
+    }
+
+    /// Apply --max-filesize, reporting and counting files that are too big.
+    fn admit_size<F: FnOnce() -> u64>(&self, name: &Path, size: F, walked: &mut Walked) -> bool {
+        let Some(max) = self.max_filesize else { return true };
+        let size: u64 = size();
+        if size <= max { return true; }
+        info!("[walk] {}: skipped, {} bytes is over --max-filesize ({} bytes)",
+              name.display(), size, max);
+        walked.large += 1;
+        false
+    }
+
+    /// Apply the --binary policy to a file that does or doesn't look binary.
+    fn admit_binary(&self, name: &Path, binary: bool, walked: &mut Walked) -> bool {
+        if !binary { return true; }
+        match self.binary {
+            Binary::Error => {
+                error!("[walk] {}: looks binary (use --binary force to process it anyway)",
+                       name.display());
+                walked.errors += 1;
+            }
+            _ => {
+                info!("[walk] {}: skipped, looks binary", name.display());
+                walked.binary += 1;
+            }
+        }
+        false
+    }
+}
+
+/// Compile the filters given on the command line, reporting any invalid globs.
+pub fn filter(args: &FilterArgs) -> Option<Filter> {
+    Filter::new(args).map_err(|e| error!("[glob] {}", e)).ok()
+}
+
+/// Compile a list of globs into the ones matched against file names and the ones matched against
+/// whole paths.
+fn compile(globs: &[String]) -> Result<(GlobSet, GlobSet), globset::Error> {
+    let mut names: GlobSetBuilder = GlobSetBuilder::new();
+    let mut paths: GlobSetBuilder = GlobSetBuilder::new();
+    for glob in globs {
+        let compiled: Glob = GlobBuilder::new(glob).literal_separator(true).build()?;
+        if glob.contains('/') { paths.add(compiled); } else { names.add(compiled); }
+    }
+    Ok((names.build()?, paths.build()?))
+}
+
+/// The files found under a directory.
+#[derive(Debug, Default)]
+pub struct Walked {
+    pub files: Vec<PathBuf>,
+    /// How many files were skipped for looking binary.
+    pub binary: usize,
+    /// How many files were skipped for being over --max-filesize.
+    pub large: usize,
+    /// How many entries couldn't be read (or were binary, with `--binary error`). Each one has
+    /// already been reported.
+    pub errors: usize,
+}
+
+/// Find every regular file under `root` that passes the filter, in a stable (sorted) order.
+/// Unless the filter says otherwise, files ignored by .gitignore (and .ignore) files, hidden files
+/// and directories, and files that look binary are skipped. Symbolic links are not followed.
+/// Entries that can't be read are reported and skipped.
+pub fn walk(root: &Path, filter: &Filter) -> Walked {
+    let mut walked: Walked = Walked::default();
+    let walker = WalkBuilder::new(root)
+        .standard_filters(!filter.no_ignore)
+        .hidden(!filter.hidden)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                let relative: &Path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                if filter.matches(relative) && filter.admit(entry.path(), &mut walked) {
+                    walked.files.push(entry.into_path());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("[walk] {}", e);
+                walked.errors += 1;
+            }
+        }
+    }
+    walked
+}
+
+/// Parse a file size: a number of bytes, optionally followed by K, M or G (for KiB, MiB and GiB;
+/// "KB", "KiB" and lowercase letters work too).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let lower: String = s.trim().to_ascii_lowercase();
+    let unit: &str = lower.trim_start_matches(|c: char| c.is_ascii_digit());
+    let number: &str = &lower[..lower.len() - unit.len()];
+    let shift: u32 = match unit.trim_end_matches('b').trim_end_matches('i') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        _ => return Err(format!("unknown size unit in \"{}\" (expected K, M or G)", s)),
+    };
+    number.parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size \"{}\"", s))
+}
+
+/// Whether a file looks binary, going by the start of it (see [`looks_binary`]). Gzip and zstd
+/// compressed files are judged by what they decompress to, since that's what is read. Files that
+/// can't be read are not binary, so that the error is reported when they are processed.
+fn is_binary(path: &Path) -> bool {
+    let mut start: Vec<u8> = Vec::new();
+    let read: io::Result<usize> = File::open(path)
+        .and_then(compress::decompress)
+        .and_then(|reader| reader.take(SNIFF_SIZE).read_to_end(&mut start));
+    read.is_ok() && looks_binary(&start)
+}
+
+/// Whether the start of a file looks binary: whether it has a NUL byte (unless it looks like
+/// UTF-16, which is full of them), or more than `MAX_INVALID_PERCENT` of its bytes aren't valid
+/// UTF-8 (text in a legacy encoding like Latin-1 has far fewer).
+pub fn looks_binary(start: &[u8]) -> bool {
+    if encoding::utf16(start).is_some() { return false; }
+    start.contains(&0) || invalid_utf8(start) * 100 > start.len() * MAX_INVALID_PERCENT
+}
+
+/// How many bytes aren't part of valid UTF-8. A character cut off at the end doesn't count.
+fn invalid_utf8(mut bytes: &[u8]) -> usize {
+    let mut invalid: usize = 0;
+    while let Err(e) = std::str::from_utf8(bytes) {
+        let Some(len) = e.error_len() else { break };
+        invalid += len;
+        bytes = &bytes[e.valid_up_to() + len..];
+    }
+    invalid
+}
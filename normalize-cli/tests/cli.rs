@@ -0,0 +1,151 @@
+/// Tests that run the utf-normalize binary.
+//     Copyright (C) 2024  Dustin Thomas <io@cptlobster.dev>
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Run the binary with some arguments.
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_utf-normalize"))
+        .args(args)
+        .env_remove("UTF_NORMALIZE_CONFIG")
+        .output()
+        .expect("could not run utf-normalize")
+}
+
+/// Write a file for a test into a fresh directory, returning its path.
+fn input(test: &str, name: &str, contents: &str) -> PathBuf {
+    let dir: PathBuf = std::env::temp_dir()
+        .join(format!("utf-normalize-test-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn global_options_before_a_subcommand() {
+    let file: PathBuf = input("global", "f.txt", "pаy\n");
+    let output: Output = run(&["--preset", "cyrillic", "detect", file.to_str().unwrap()]);
+    assert!(output.status.success());
+    let stdout: String = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("confusables character U+0430"), "{}", stdout);
+}
+
+#[test]
+fn normalize_options_before_a_subcommand() {
+    let file: PathBuf = input("before", "f.txt", "pаy\n");
+    let output: Output = run(&["--in-place", "detect", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "pаy\n");
+}
+
+#[test]
+fn fallback_error_fails_on_unhandled_characters() {
+    let file: PathBuf = input("fallback", "f.txt", "ok\nné\n");
+    let config: PathBuf = input("fallback", "config.toml", "");
+    let output: Output = run(&["-c", config.to_str().unwrap(), "--fallback", "error", "-i",
+                               file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr: String = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unmapped character U+00E9 at line 2, column 2"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "ok\nné\n");
+
+    let output: Output = run(&["-c", config.to_str().unwrap(), "--fallback", "error",
+                               file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn denied_characters_fail_normalize_and_check() {
+    let file: PathBuf = input("deny", "f.txt", "ok\nné\n");
+    let config: PathBuf = input("deny", "config.toml", "[deny]\nsource = [\"é\"]\n");
+    let config: &str = config.to_str().unwrap();
+    let output: Output = run(&["-c", config, "-i", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr: String = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("denied character U+00E9 at line 2, column 2"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "ok\nné\n");
+
+    let output: Output = run(&["-c", config, "--check", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output: Output = run(&["-c", config, "detect", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout: String = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("denied character U+00E9"), "{}", stdout);
+}
+
+#[test]
+fn config_learn_writes_only_the_learned_section() {
+    let examples: PathBuf = input("learn", "examples.tsv", "café\tcafe\n");
+    let output: Output = run(&["config", "learn", examples.to_str().unwrap()]);
+    assert!(output.status.success());
+    let stdout: String = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("[[translator]]\n"), "{}", stdout);
+    assert!(!stdout.contains("[global]"), "{}", stdout);
+}
+
+#[test]
+fn cached_chains_notice_new_presets_in_the_data_directory() {
+    let file: PathBuf = input("datadir", "f.txt", "pаy\n");
+    let config: PathBuf = input("datadir", "config.toml", "");
+    let dir: PathBuf = config.parent().unwrap().to_path_buf();
+    let data: PathBuf = dir.join("data");
+    fs::create_dir_all(&data).unwrap();
+    let normalize = || {
+        let output: Output = Command::new(env!("CARGO_BIN_EXE_utf-normalize"))
+            .args(["-c", config.to_str().unwrap(), "--preset", "cyrillic", "--data-dir",
+                   data.to_str().unwrap(), file.to_str().unwrap()])
+            .env_remove("UTF_NORMALIZE_CONFIG")
+            .env("XDG_CACHE_HOME", dir.join("cache"))
+            .output()
+            .expect("could not run utf-normalize");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(normalize(), "pay\n");
+    assert_eq!(normalize(), "pay\n");
+    assert!(fs::read_dir(dir.join("cache/utf-normalize")).unwrap().next().is_some());
+
+    // a data directory preset replaces the built-in one of the same name
+    let preset: &str = "[cyrillic]\ntype = \"lookup\"\nmap = { \"а\" = \"A\" }\n";
+    fs::write(data.join("cyrillic.toml"), preset).unwrap();
+    assert_eq!(normalize(), "pAy\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sarif_reports_locate_findings() {
+    let file: PathBuf = input("sarif", "f.txt", "ok\npаy\n");
+    let config: PathBuf = input("sarif", "config.toml", "");
+    let output: Output = run(&["-c", config.to_str().unwrap(), "--preset", "cyrillic", "--report",
+                               "--report-format", "sarif", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let sarif: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sarif["version"], "2.1.0");
+    let run: &serde_json::Value = &sarif["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "utf-normalize");
+    let results: &Vec<serde_json::Value> = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["ruleId"], "confusables");
+    let region: &serde_json::Value = &results[0]["locations"][0]["physicalLocation"]["region"];
+    assert_eq!(region["startLine"], 2);
+    assert_eq!(region["startColumn"], 2);
+    assert_eq!(region["endColumn"], 3);
+}